@@ -0,0 +1,38 @@
+-- The schema created by the Python backend, so existing databases are left as they are
+CREATE TABLE IF NOT EXISTS githubowner (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS githubrepo (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    description VARCHAR,
+    owner_id INTEGER NOT NULL REFERENCES githubowner (id),
+    created_at TIMESTAMP NOT NULL,
+    CONSTRAINT unique_owner_name UNIQUE (owner_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS release (
+    id SERIAL PRIMARY KEY,
+    repo_id INTEGER NOT NULL REFERENCES githubrepo (id),
+    readme_filename VARCHAR,
+    readme VARCHAR,
+    version VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+    description VARCHAR,
+    created_at TIMESTAMP NOT NULL,
+    meta_data JSONB,
+    meta_data_errors VARCHAR,
+    outputs JSONB,
+    outputs_errors VARCHAR,
+    CONSTRAINT unique_repo_version UNIQUE (repo_id, version)
+);
+
+-- Publishing upserts owners by name
+CREATE UNIQUE INDEX IF NOT EXISTS githubowner_name ON githubowner (name);
+-- Counting the repos of an owner
+CREATE INDEX IF NOT EXISTS githubrepo_owner_id ON githubrepo (owner_id);
+-- Listing the newest releases and the feed
+CREATE INDEX IF NOT EXISTS release_created_at ON release (created_at);
@@ -0,0 +1,6 @@
+-- Fallback search in the database when OpenSearch is unavailable
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS githubowner_name_trgm ON githubowner USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS githubrepo_name_trgm ON githubrepo USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS release_description_trgm ON release USING GIN (description gin_trgm_ops);
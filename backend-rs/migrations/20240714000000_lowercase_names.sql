@@ -0,0 +1,20 @@
+-- Owners and repos are stored trimmed and lowercased since publishes normalize them. Names
+-- differing only by case would collide, so only one of them is renamed and the others are
+-- left to be merged by hand.
+UPDATE githubowner SET name = lower(btrim(name))
+    WHERE name <> lower(btrim(name))
+    AND id IN (
+        SELECT DISTINCT ON (lower(btrim(name))) id FROM githubowner ORDER BY lower(btrim(name)), id
+    )
+    AND NOT EXISTS (SELECT 1 FROM githubowner other WHERE other.name = lower(btrim(githubowner.name)));
+
+UPDATE githubrepo SET name = lower(btrim(name))
+    WHERE name <> lower(btrim(name))
+    AND id IN (
+        SELECT DISTINCT ON (owner_id, lower(btrim(name))) id FROM githubrepo
+            ORDER BY owner_id, lower(btrim(name)), id
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM githubrepo other
+            WHERE other.owner_id = githubrepo.owner_id AND other.name = lower(btrim(githubrepo.name))
+    );
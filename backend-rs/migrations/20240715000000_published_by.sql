@@ -0,0 +1,2 @@
+-- Who published a release, NULL for releases published before publishes were authenticated
+ALTER TABLE release ADD COLUMN IF NOT EXISTS published_by VARCHAR;
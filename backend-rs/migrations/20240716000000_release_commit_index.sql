@@ -0,0 +1,2 @@
+-- Releases are looked up by commit prefix, which needs an index supporting LIKE
+CREATE INDEX IF NOT EXISTS release_commit ON release (commit text_pattern_ops);
@@ -0,0 +1,2 @@
+-- Archived repos are left out of listings and search unless asked for
+ALTER TABLE githubrepo ADD COLUMN IF NOT EXISTS archived BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- How the readme has to be rendered, readmes published before were all assumed to be markdown
+ALTER TABLE release ADD COLUMN IF NOT EXISTS readme_type VARCHAR NOT NULL DEFAULT 'markdown';
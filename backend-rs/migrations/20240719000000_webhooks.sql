@@ -0,0 +1,26 @@
+-- URLs notified of new releases of a repo, which doesn't have to be published yet
+CREATE TABLE IF NOT EXISTS webhook (
+    id SERIAL PRIMARY KEY,
+    owner VARCHAR NOT NULL,
+    repo VARCHAR NOT NULL,
+    url VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    CONSTRAINT unique_webhook UNIQUE (owner, repo, url)
+);
+
+-- Pending and past deliveries of a release to a webhook, retried until delivered or given up
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id SERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhook (id) ON DELETE CASCADE,
+    release_id INTEGER NOT NULL REFERENCES release (id) ON DELETE CASCADE,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL,
+    delivered_at TIMESTAMP,
+    failed_at TIMESTAMP,
+    last_error VARCHAR
+);
+
+-- The worker picks up the deliveries that are due
+CREATE INDEX IF NOT EXISTS webhook_delivery_pending ON webhook_delivery (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,9 @@
+-- Readmes are stored compressed once per distinct content, keyed by the sha256 of the text
+CREATE TABLE IF NOT EXISTS readme (
+    hash VARCHAR PRIMARY KEY,
+    content BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- Releases published before keep their readme in `release.readme`
+ALTER TABLE release ADD COLUMN IF NOT EXISTS readme_hash VARCHAR REFERENCES readme (hash);
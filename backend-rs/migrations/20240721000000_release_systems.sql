@@ -0,0 +1,2 @@
+-- Nix systems like `x86_64-linux` the outputs of a release target, empty when not published
+ALTER TABLE release ADD COLUMN IF NOT EXISTS systems VARCHAR[] NOT NULL DEFAULT '{}';
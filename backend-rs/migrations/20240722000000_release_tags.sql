@@ -0,0 +1,2 @@
+-- Keywords like `cli` or `nixos` the author picked, empty when not published
+ALTER TABLE release ADD COLUMN IF NOT EXISTS tags VARCHAR[] NOT NULL DEFAULT '{}';
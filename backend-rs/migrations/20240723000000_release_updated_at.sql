@@ -0,0 +1,20 @@
+-- When the release was last changed, the same as `created_at` until then
+ALTER TABLE release ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+UPDATE release SET updated_at = created_at WHERE updated_at IS NULL;
+ALTER TABLE release ALTER COLUMN updated_at SET NOT NULL;
+
+-- Maintained by the database, so that every way of changing a release bumps it
+CREATE OR REPLACE FUNCTION release_set_updated_at() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        NEW.updated_at := COALESCE(NEW.updated_at, NEW.created_at);
+    ELSIF NEW IS DISTINCT FROM OLD THEN
+        NEW.updated_at := now() AT TIME ZONE 'utc';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS release_updated_at ON release;
+CREATE TRIGGER release_updated_at BEFORE INSERT OR UPDATE ON release
+    FOR EACH ROW EXECUTE FUNCTION release_set_updated_at();
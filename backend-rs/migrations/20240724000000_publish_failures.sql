@@ -0,0 +1,13 @@
+-- Rejected publishes with why they were rejected, to debug publishers whose releases are missing
+CREATE TABLE IF NOT EXISTS publish_failure (
+    id SERIAL PRIMARY KEY,
+    owner VARCHAR NOT NULL,
+    repo VARCHAR NOT NULL,
+    version VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+    status SMALLINT NOT NULL,
+    reason VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS publish_failure_created_at ON publish_failure (created_at);
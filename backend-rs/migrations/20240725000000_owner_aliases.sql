@@ -0,0 +1,5 @@
+-- Former names of owners which were merged into another, links to them are redirected
+CREATE TABLE IF NOT EXISTS owner_alias (
+    alias VARCHAR PRIMARY KEY,
+    owner_id INTEGER NOT NULL REFERENCES githubowner (id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- The version admins recommend for a repo, e.g. an older stable release over a newer prerelease
+ALTER TABLE release ADD COLUMN IF NOT EXISTS recommended BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX IF NOT EXISTS release_recommended ON release (repo_id) WHERE recommended;
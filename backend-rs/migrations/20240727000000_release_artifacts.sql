@@ -0,0 +1,3 @@
+-- What publishers know about the release's build, left NULL by those who don't say
+ALTER TABLE release ADD COLUMN IF NOT EXISTS closure_size BIGINT;
+ALTER TABLE release ADD COLUMN IF NOT EXISTS nar_hash VARCHAR;
@@ -0,0 +1,10 @@
+-- Releases changed in place by publishing their version again, with the commit they had before
+CREATE TABLE IF NOT EXISTS release_change (
+    id SERIAL PRIMARY KEY,
+    release_id INTEGER NOT NULL REFERENCES release (id) ON DELETE CASCADE,
+    previous_commit VARCHAR NOT NULL,
+    commit VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS release_change_release_id ON release_change (release_id);
@@ -0,0 +1,31 @@
+-- When the repo or the set of its releases last changed, e.g. by archiving it or deleting one of
+-- its releases, which leave no trace in the `updated_at` of the releases
+ALTER TABLE githubrepo ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+UPDATE githubrepo SET updated_at = created_at WHERE updated_at IS NULL;
+ALTER TABLE githubrepo ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION githubrepo_set_updated_at() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        NEW.updated_at := COALESCE(NEW.updated_at, NEW.created_at);
+    ELSIF NEW IS DISTINCT FROM OLD THEN
+        NEW.updated_at := now() AT TIME ZONE 'utc';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS githubrepo_updated_at ON githubrepo;
+CREATE TRIGGER githubrepo_updated_at BEFORE INSERT OR UPDATE ON githubrepo
+    FOR EACH ROW EXECUTE FUNCTION githubrepo_set_updated_at();
+
+CREATE OR REPLACE FUNCTION release_deleted_touch_repo() RETURNS trigger AS $$
+BEGIN
+    UPDATE githubrepo SET updated_at = now() AT TIME ZONE 'utc' WHERE id = OLD.repo_id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS release_deleted_touch_repo ON release;
+CREATE TRIGGER release_deleted_touch_repo AFTER DELETE ON release
+    FOR EACH ROW EXECUTE FUNCTION release_deleted_touch_repo();
@@ -0,0 +1,847 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::NaiveDateTime;
+use opensearch::{http::StatusCode, DeleteParts, GetParts, UpdateByQueryParts};
+use serde_json::{json, Map, Value};
+use sqlx::{FromRow, Pool, Postgres, Transaction};
+use std::{sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+use crate::api::flake::{int_param, stored_readme};
+use crate::api::publish::{description_from_readme, normalize_name, upsert_owner};
+use crate::api::MAX_LIST_LIMIT;
+use crate::common::{with_db_timeout, AppError, AppState, Paginated, QueryParams};
+
+// The search document of a release as it's stored, to compare it with the database row
+#[utoipa::path(
+    get,
+    path = "/api/admin/index/{id}",
+    params(("id" = i32, Path, description = "Release id")),
+    responses(
+        (status = 200, body = Object, description = "The `_source` of the document"),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 404, description = "No document for the release"),
+        (status = 503, description = "Search unavailable"),
+    )
+)]
+pub async fn get_index_document(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    state.authorize_admin(&headers)?;
+
+    let id = id.to_string();
+    let response = state
+        .opensearch
+        .get(GetParts::IndexId("flakes", &id))
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to get search document {id}: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    match response.status_code() {
+        StatusCode::NOT_FOUND => Err(AppError::NotFound),
+        status if status.is_success() => {
+            let mut body = response
+                .json::<Value>()
+                .await
+                .context("Failed to decode opensearch response as json")?;
+            Ok(Json(body["_source"].take()))
+        }
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            tracing::error!(%status, body, "Failed to get search document {id}");
+            Err(AppError::search_unavailable())
+        }
+    }
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct DeleteDocumentResponse {
+    existed: bool,
+}
+
+// Removes the search document of a single release, e.g. one that was yanked
+#[utoipa::path(
+    delete,
+    path = "/api/admin/index/{id}",
+    params(("id" = i32, Path, description = "Release id")),
+    responses(
+        (status = 200, body = DeleteDocumentResponse),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 503, description = "Read-only mode or search unavailable"),
+    )
+)]
+pub async fn delete_index_document(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Json<DeleteDocumentResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let existed = delete_document(&state, id).await?;
+    Ok(Json(DeleteDocumentResponse { existed }))
+}
+
+/// What deleting a release removed, nothing when it was already gone.
+#[derive(serde::Serialize, ToSchema)]
+pub struct DeleteReleaseResponse {
+    release_id: Option<i32>,
+    deleted_release: bool,
+    deleted_document: bool,
+}
+
+// Removes a release from both the database and the search index
+#[utoipa::path(
+    delete,
+    path = "/api/flake/github/{owner}/{repo}/{version}",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+        ("version" = String, Path, description = "Release version"),
+    ),
+    responses(
+        (status = 200, body = DeleteReleaseResponse),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 503, description = "Read-only mode or search unavailable"),
+    )
+)]
+pub async fn delete_release(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo, version)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<DeleteReleaseResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let release_id = with_db_timeout(
+        state.db_timeout,
+        "get_release_id",
+        get_release_id(&owner, &repo, &version, &state.pool),
+    )
+    .await?;
+    let Some(release_id) = release_id else {
+        return Ok(Json(DeleteReleaseResponse {
+            release_id: None,
+            deleted_release: false,
+            deleted_document: false,
+        }));
+    };
+
+    // The document goes first, so that a failure leaves the release around to retry with
+    let deleted_document = delete_document(&state, release_id).await?;
+    let deleted_release = with_db_timeout(
+        state.db_timeout,
+        "delete_release_row",
+        delete_release_row(release_id, &state.pool),
+    )
+    .await?;
+
+    Ok(Json(DeleteReleaseResponse {
+        release_id: Some(release_id),
+        deleted_release,
+        deleted_document,
+    }))
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct MergeOwnersRequest {
+    from: String,
+    to: String,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct MergeOwnersResponse {
+    owner: String,
+    repos_moved: u64,
+    documents_updated: i64,
+}
+
+// Moves the repos of an owner that was renamed on GitHub to the new name, repo pages under the
+// old name redirect to it
+#[utoipa::path(
+    post,
+    path = "/api/admin/owner/merge",
+    request_body = MergeOwnersRequest,
+    responses(
+        (status = 200, body = MergeOwnersResponse),
+        (status = 400, description = "Merging an owner into itself"),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 404, description = "Unknown owner to merge from"),
+        (status = 409, description = "Both owners have a repo of the same name"),
+        (status = 503, description = "Read-only mode or search unavailable"),
+    )
+)]
+pub async fn post_merge_owners(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(merge): Json<MergeOwnersRequest>,
+) -> Result<Json<MergeOwnersResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let (from, to) = (normalize_name(&merge.from), normalize_name(&merge.to));
+    if from == to {
+        return Err(AppError::BadRequest(
+            "from and to must be different owners".to_string(),
+        ));
+    }
+
+    let repos_moved = with_db_timeout(
+        state.db_timeout,
+        "merge_owners",
+        merge_owners(&state, &from, &to),
+    )
+    .await?;
+    // The search documents are only updated once the merge is committed, for OpenSearch not to
+    // hold the locks of the transaction. Merging again updates the ones a failure left behind.
+    let documents_updated = rename_owner_documents(&state, &from, &to).await?;
+
+    Ok(Json(MergeOwnersResponse {
+        owner: to,
+        repos_moved,
+        documents_updated,
+    }))
+}
+
+// Moves the repos and aliases of `from` to `to` and deletes `from`, returning how many repos
+// were moved. An owner merged into `to` before has none left to move.
+async fn merge_owners(state: &AppState, from: &str, to: &str) -> Result<u64, AppError> {
+    let mut tx = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to start merge transaction")?;
+
+    let from_id: Option<i32> =
+        sqlx::query_scalar("SELECT id FROM githubowner WHERE name = $1 FOR UPDATE")
+            .bind(from)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to fetch owner from database")?;
+    // Merged before, only the search documents can be left to update
+    let Some(from_id) = from_id else {
+        let merged: bool = sqlx::query_scalar(
+            "SELECT EXISTS ( \
+                SELECT 1 FROM owner_alias \
+                INNER JOIN githubowner ON githubowner.id = owner_alias.owner_id \
+                WHERE owner_alias.alias = $1 AND githubowner.name = $2 \
+            )",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to fetch owner alias from database")?;
+        return if merged {
+            Ok(0)
+        } else {
+            Err(AppError::NotFound)
+        };
+    };
+    let to_id = upsert_owner(to, &mut tx).await?;
+
+    let clashes = clashing_repos(from_id, to_id, &mut tx).await?;
+    if !clashes.is_empty() {
+        return Err(AppError::Conflict(format!(
+            "both owners have the repos {}, they have to be merged by hand",
+            clashes.join(", ")
+        )));
+    }
+
+    let repos_moved = sqlx::query("UPDATE githubrepo SET owner_id = $2 WHERE owner_id = $1")
+        .bind(from_id)
+        .bind(to_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move repos in database")?
+        .rows_affected();
+    // Links to the old name, or to names it had itself, keep working as redirects
+    sqlx::query("UPDATE owner_alias SET owner_id = $2 WHERE owner_id = $1")
+        .bind(from_id)
+        .bind(to_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move owner aliases in database")?;
+    sqlx::query(
+        "INSERT INTO owner_alias (alias, owner_id) VALUES ($1, $2) \
+            ON CONFLICT (alias) DO UPDATE SET owner_id = EXCLUDED.owner_id",
+    )
+    .bind(from)
+    .bind(to_id)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to record owner alias in database")?;
+    // An owner renamed back to a former name isn't an alias of itself
+    sqlx::query("DELETE FROM owner_alias WHERE alias = $1")
+        .bind(to)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to remove owner alias from database")?;
+    sqlx::query("DELETE FROM githubowner WHERE id = $1")
+        .bind(from_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete owner from database")?;
+
+    tx.commit()
+        .await
+        .context("Failed to commit merge transaction")?;
+    // The repos of both owners changed their name
+    state.repo_ids.clear().await;
+
+    Ok(repos_moved)
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct SetArchivedRequest {
+    archived: bool,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct SetArchivedResponse {
+    owner: String,
+    repo: String,
+    archived: bool,
+    documents_updated: i64,
+}
+
+// Marks a repo as archived, which leaves its releases out of listings and search by default
+#[utoipa::path(
+    put,
+    path = "/api/admin/repo/{owner}/{repo}/archived",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+    ),
+    request_body = SetArchivedRequest,
+    responses(
+        (status = 200, body = SetArchivedResponse),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 404, description = "Unknown repo"),
+        (status = 503, description = "Read-only mode or search unavailable"),
+    )
+)]
+pub async fn put_repo_archived(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(request): Json<SetArchivedRequest>,
+) -> Result<Json<SetArchivedResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    with_db_timeout(
+        state.db_timeout,
+        "set_archived",
+        set_archived(&state, &owner, &repo, request.archived),
+    )
+    .await?;
+    // Like merging owners, the search documents are updated once it's committed. Archiving
+    // again updates the ones a failure left behind.
+    let documents_updated = update_documents(
+        &state,
+        json!({ "term": { "full_name": format!("{owner}/{repo}") } }),
+        json!({
+            "source": "ctx._source.archived = params.archived",
+            "params": { "archived": request.archived },
+        }),
+    )
+    .await?;
+
+    Ok(Json(SetArchivedResponse {
+        owner,
+        repo,
+        archived: request.archived,
+        documents_updated,
+    }))
+}
+
+// Sets whether the repo is archived, `NotFound` when there's no such repo
+async fn set_archived(
+    state: &AppState,
+    owner: &str,
+    repo: &str,
+    archived: bool,
+) -> Result<(), AppError> {
+    let updated = sqlx::query(
+        "UPDATE githubrepo SET archived = $3 \
+            FROM githubowner \
+            WHERE githubowner.id = githubrepo.owner_id \
+            AND githubowner.name = $1 AND githubrepo.name = $2",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(archived)
+    .execute(&state.pool)
+    .await
+    .context("Failed to update repo in database")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct SetRecommendedRequest {
+    // `null` goes back to recommending the newest stable release
+    version: Option<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct SetRecommendedResponse {
+    owner: String,
+    repo: String,
+    recommended: Option<String>,
+}
+
+// Recommends a version of a repo over its newest one, replacing the version recommended before
+#[utoipa::path(
+    put,
+    path = "/api/admin/repo/{owner}/{repo}/recommended",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+    ),
+    request_body = SetRecommendedRequest,
+    responses(
+        (status = 200, body = SetRecommendedResponse),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 404, description = "Unknown repo or version"),
+        (status = 503, description = "Read-only mode"),
+    )
+)]
+pub async fn put_repo_recommended(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(request): Json<SetRecommendedRequest>,
+) -> Result<Json<SetRecommendedResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    with_db_timeout(
+        state.db_timeout,
+        "set_recommended",
+        set_recommended(&state.pool, &owner, &repo, request.version.as_deref()),
+    )
+    .await?;
+
+    Ok(Json(SetRecommendedResponse {
+        owner,
+        repo,
+        recommended: request.version,
+    }))
+}
+
+async fn set_recommended(
+    pool: &Pool<Postgres>,
+    owner: &str,
+    repo: &str,
+    version: Option<&str>,
+) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to start recommend transaction")?;
+
+    let repo_id: i32 = sqlx::query_scalar(
+        "SELECT githubrepo.id FROM githubrepo \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 \
+            FOR UPDATE OF githubrepo",
+    )
+    .bind(owner)
+    .bind(repo)
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to fetch repo from database")?
+    .ok_or(AppError::NotFound)?;
+    // Cleared first, the unique index allows only one recommended release at a time
+    sqlx::query("UPDATE release SET recommended = FALSE WHERE repo_id = $1 AND recommended")
+        .bind(repo_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to clear recommended release in database")?;
+    if let Some(version) = version {
+        let updated = sqlx::query(
+            "UPDATE release SET recommended = TRUE WHERE repo_id = $1 AND version = $2",
+        )
+        .bind(repo_id)
+        .bind(version)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to recommend release in database")?
+        .rows_affected();
+        if updated == 0 {
+            return Err(AppError::NotFound);
+        }
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit recommend transaction")?;
+    Ok(())
+}
+
+// Releases updated per transaction by the description backfill
+const BACKFILL_BATCH_SIZE: i64 = 100;
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct BackfillDescriptionsResponse {
+    releases_updated: u64,
+    documents_updated: i64,
+}
+
+// Fills the empty descriptions of releases published before they were made up from readmes,
+// like publishing does now. Releases whose readme has no prose keep an empty description, so
+// running it again only picks up what's left.
+#[utoipa::path(
+    post,
+    path = "/api/admin/backfill-descriptions",
+    responses(
+        (status = 200, body = BackfillDescriptionsResponse),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 503, description = "Read-only mode or search unavailable"),
+    )
+)]
+pub async fn post_backfill_descriptions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<BackfillDescriptionsResponse>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let mut response = BackfillDescriptionsResponse {
+        releases_updated: 0,
+        documents_updated: 0,
+    };
+    // Batches go by id, so that releases left without a description aren't fetched again
+    let mut after_id = 0;
+    loop {
+        let batch = with_db_timeout(
+            state.db_timeout,
+            "backfill_descriptions_batch",
+            backfill_descriptions_batch(&state, after_id),
+        )
+        .await?;
+        let Some((last_id, releases_updated, documents_updated)) = batch else {
+            break;
+        };
+        after_id = last_id;
+        response.releases_updated += releases_updated;
+        response.documents_updated += documents_updated;
+        tracing::info!(
+            after_id,
+            releases_updated = response.releases_updated,
+            documents_updated = response.documents_updated,
+            "Backfilled descriptions"
+        );
+    }
+
+    Ok(Json(response))
+}
+
+// Backfills the next batch of releases after `after_id`, returning the last id looked at and
+// how many releases and documents were updated, or `None` once there are none left. The search
+// documents are updated before committing, so a batch they fail for is picked up again.
+async fn backfill_descriptions_batch(
+    state: &AppState,
+    after_id: i32,
+) -> Result<Option<(i32, u64, i64)>, AppError> {
+    let mut tx = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to start backfill transaction")?;
+
+    type Row = (i32, Option<Vec<u8>>, Option<String>, Option<String>);
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT release.id, readme.content, release.readme_hash, release.readme \
+            FROM release \
+            LEFT JOIN readme ON readme.hash = release.readme_hash \
+            WHERE release.id > $1 AND COALESCE(TRIM(release.description), '') = '' \
+                AND (release.readme_hash IS NOT NULL OR release.readme IS NOT NULL) \
+            ORDER BY release.id \
+            LIMIT $2",
+    )
+    .bind(after_id)
+    .bind(BACKFILL_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await
+    .context("Failed to fetch releases without description from database")?;
+    let Some(&(last_id, ..)) = rows.last() else {
+        return Ok(None);
+    };
+
+    let descriptions: Map<String, Value> = rows
+        .into_iter()
+        .filter_map(|(id, content, hash, legacy)| {
+            let (readme, _) = stored_readme(content, hash, legacy)?;
+            Some((id.to_string(), json!(description_from_readme(&readme)?)))
+        })
+        .collect();
+    if descriptions.is_empty() {
+        return Ok(Some((last_id, 0, 0)));
+    }
+
+    let ids: Vec<i32> = descriptions
+        .keys()
+        .filter_map(|id| id.parse().ok())
+        .collect();
+    let values: Vec<&str> = descriptions.values().filter_map(Value::as_str).collect();
+    let releases_updated = sqlx::query(
+        "UPDATE release SET description = backfill.description \
+            FROM UNNEST($1::int[], $2::varchar[]) AS backfill (id, description) \
+            WHERE release.id = backfill.id",
+    )
+    .bind(&ids)
+    .bind(&values)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to update release descriptions in database")?
+    .rows_affected();
+
+    let documents_updated = update_documents(
+        state,
+        json!({ "ids": { "values": descriptions.keys().collect::<Vec<_>>() } }),
+        json!({
+            "source": "ctx._source.description = params.descriptions[ctx._id]",
+            "params": { "descriptions": descriptions },
+        }),
+    )
+    .await?;
+
+    tx.commit()
+        .await
+        .context("Failed to commit backfill transaction")?;
+
+    Ok(Some((last_id, releases_updated, documents_updated)))
+}
+
+// Repo names both owners use, which can't be moved without merging their releases
+async fn clashing_repos(
+    from_id: i32,
+    to_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<String>, AppError> {
+    let names = sqlx::query_scalar(
+        "SELECT name FROM githubrepo WHERE owner_id = $1 \
+            AND name IN (SELECT name FROM githubrepo WHERE owner_id = $2) \
+            ORDER BY name",
+    )
+    .bind(from_id)
+    .bind(to_id)
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to fetch repos from database")?;
+
+    Ok(names)
+}
+
+// Points the search documents of `from` at `to`, returning how many were updated
+async fn rename_owner_documents(state: &AppState, from: &str, to: &str) -> Result<i64, AppError> {
+    update_documents(
+        state,
+        json!({ "term": { "owner.keyword": from } }),
+        json!({
+            "source": "ctx._source.owner = params.to; \
+                ctx._source.full_name = params.to + '/' + ctx._source.repo",
+            "params": { "to": to },
+        }),
+    )
+    .await
+}
+
+// How long OpenSearch gets to update the documents matching a query, apart from `DB_TIMEOUT`
+const UPDATE_DOCUMENTS_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Runs `script` on the search documents matching `query`, returning how many were updated
+pub(crate) async fn update_documents(
+    state: &AppState,
+    query: Value,
+    script: Value,
+) -> Result<i64, AppError> {
+    let update = state
+        .opensearch
+        .update_by_query(UpdateByQueryParts::Index(&["flakes"]))
+        .body(json!({ "query": query, "script": script }))
+        .send();
+    let response = match tokio::time::timeout(UPDATE_DOCUMENTS_TIMEOUT, update).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            tracing::error!("Failed to update search documents: {err}");
+            return Err(AppError::search_unavailable());
+        }
+        Err(_) => {
+            tracing::error!("Failed to update search documents: timed out");
+            return Err(AppError::search_unavailable());
+        }
+    };
+
+    let status = response.status_code();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(%status, body, "Failed to update search documents");
+        return Err(AppError::search_unavailable());
+    }
+    let body = response
+        .json::<Value>()
+        .await
+        .context("Failed to decode opensearch response as json")?;
+
+    Ok(body["updated"].as_i64().unwrap_or(0))
+}
+
+// Publish failures listed per page when no limit is given
+const PUBLISH_FAILURES_LIMIT: i64 = 50;
+
+#[derive(FromRow, serde::Serialize, ToSchema)]
+pub struct PublishFailure {
+    owner: String,
+    repo: String,
+    // As it was published, possibly with a `v` prefix or not a valid version at all
+    version: String,
+    commit: String,
+    // What the publisher got back, with the reason as the message
+    status: i16,
+    reason: String,
+    created_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    total: i64,
+}
+
+// Rejected publishes, most recent first, to find out why a publisher's releases don't show up.
+// They're kept for 30 days.
+#[utoipa::path(
+    get,
+    path = "/api/admin/publish-failures",
+    params(
+        ("owner" = Option<String>, Query, description = "Only failures of this owner"),
+        ("limit" = Option<i64>, Query, description = "Number of failures"),
+        ("offset" = Option<i64>, Query, description = "Number of failures to skip"),
+    ),
+    responses(
+        (status = 200, body = PaginatedPublishFailure),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+    )
+)]
+pub async fn get_publish_failures(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<PublishFailure>>, AppError> {
+    state.authorize_admin(&headers)?;
+    let owner = params.get("owner").map(|owner| normalize_name(owner));
+    let limit = int_param(&params, "limit", 1)?
+        .map_or(PUBLISH_FAILURES_LIMIT, |limit| limit.min(MAX_LIST_LIMIT));
+    let offset = int_param(&params, "offset", 0)?.unwrap_or(0);
+
+    let failures = with_db_timeout(
+        state.db_timeout,
+        "list_publish_failures",
+        list_publish_failures(owner.as_deref(), limit, offset, &state.pool),
+    )
+    .await?;
+    Ok(Json(Paginated {
+        total: failures.first().map_or(0, |failure| failure.total),
+        items: failures,
+        limit,
+        offset,
+    }))
+}
+
+async fn list_publish_failures(
+    owner: Option<&str>,
+    limit: i64,
+    offset: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<PublishFailure>, AppError> {
+    let failures = sqlx::query_as(
+        "SELECT owner, repo, version, commit, status, reason, created_at, \
+            COUNT(*) OVER () AS total \
+            FROM publish_failure \
+            WHERE $1::VARCHAR IS NULL OR owner = $1 \
+            ORDER BY created_at DESC, id DESC \
+            LIMIT $2 OFFSET $3",
+    )
+    .bind(owner)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch publish failures from database")?;
+
+    Ok(failures)
+}
+
+// Whether the document existed
+async fn delete_document(state: &AppState, release_id: i32) -> Result<bool, AppError> {
+    let id = release_id.to_string();
+    let response = state
+        .opensearch
+        .delete(DeleteParts::IndexId("flakes", &id))
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to delete search document {id}: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    match response.status_code() {
+        StatusCode::NOT_FOUND => Ok(false),
+        status if status.is_success() => Ok(true),
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            tracing::error!(%status, body, "Failed to delete search document {id}");
+            Err(AppError::search_unavailable())
+        }
+    }
+}
+
+async fn get_release_id(
+    owner: &str,
+    repo: &str,
+    version: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<i32>, AppError> {
+    let release_id = sqlx::query_scalar(
+        "SELECT release.id FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 AND release.version = $3",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch release id from database")?;
+
+    Ok(release_id)
+}
+
+// Whether the release existed, a concurrent delete may have beaten this one
+async fn delete_release_row(release_id: i32, pool: &Pool<Postgres>) -> Result<bool, AppError> {
+    let result = sqlx::query("DELETE FROM release WHERE id = $1")
+        .bind(release_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete release from database")?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,101 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use sqlx::{FromRow, Pool, Postgres};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::api::flake::int_param;
+use crate::api::MAX_LIST_LIMIT;
+use crate::common::{with_db_timeout, AppError, AppState, Paginated, QueryParams};
+
+#[derive(FromRow, serde::Serialize, ToSchema)]
+pub struct CatalogRepo {
+    owner: String,
+    repo: String,
+    description: Option<String>,
+    latest_version: String,
+    #[serde(skip_serializing)]
+    total: i64,
+}
+
+// Every repo once by name, for browsing all of them rather than what was released lately.
+// Archived repos are left out like in the other listings.
+#[utoipa::path(
+    get,
+    path = "/api/catalog",
+    params(
+        ("limit" = Option<i64>, Query, description = "Number of repos"),
+        ("offset" = Option<i64>, Query, description = "Number of repos to skip"),
+    ),
+    responses(
+        (status = 200, body = PaginatedCatalogRepo),
+        (status = 400, description = "Invalid query parameters"),
+    )
+)]
+pub async fn get_catalog(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<CatalogRepo>>, AppError> {
+    let limit = int_param(&params, "limit", 1)?
+        .map_or(state.default_list_limit, |limit| limit.min(MAX_LIST_LIMIT));
+    let offset = int_param(&params, "offset", 0)?.unwrap_or(0);
+
+    let repos = with_db_timeout(
+        state.db_timeout,
+        "get_catalog_repos",
+        get_catalog_repos(limit, offset, &state.pool),
+    )
+    .await?;
+    let total = repos.first().map_or(0, |repo| repo.total);
+
+    Ok(Json(Paginated {
+        items: repos,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+// `DISTINCT ON` keeps the highest version of each repo. Like `parse_version`, a leading `v` and
+// a missing patch are accepted and a pre-release comes before its release. Versions that don't
+// parse have no components, so they come after those that do, and the rest by publish time.
+async fn get_catalog_repos(
+    limit: i64,
+    offset: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<CatalogRepo>, AppError> {
+    let repos: Vec<CatalogRepo> = sqlx::query_as(
+        r"SELECT owner, repo, description, latest_version, COUNT(*) OVER () AS total
+            FROM (
+                SELECT DISTINCT ON (githubowner.name, githubrepo.name)
+                    githubowner.name AS owner,
+                    githubrepo.name AS repo,
+                    githubrepo.description AS description,
+                    release.version AS latest_version
+                    FROM release
+                    INNER JOIN githubrepo ON githubrepo.id = release.repo_id
+                    INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id
+                    WHERE NOT githubrepo.archived
+                    ORDER BY githubowner.name, githubrepo.name,
+                        ARRAY(
+                            SELECT COALESCE(component, '0')::numeric
+                                FROM unnest(regexp_match(
+                                    release.version,
+                                    '^v?(\d+)\.(\d+)(?:\.(\d+))?(?:[-+]|$)'
+                                )) AS component
+                        ) DESC,
+                        release.version ~ '^v?[\d.]+-' ASC,
+                        release.created_at DESC,
+                        release.id DESC
+            ) AS latest
+            ORDER BY owner, repo
+            LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch catalog repos from database")?;
+
+    Ok(repos)
+}
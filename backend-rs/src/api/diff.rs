@@ -0,0 +1,148 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use std::{collections::BTreeMap, sync::Arc};
+use utoipa::ToSchema;
+
+use crate::api::publish::normalize_name;
+use crate::common::{with_db_timeout, AppError, AppState, QueryParams};
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct OutputsDiff {
+    from: String,
+    to: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/diff",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+        ("from" = String, Query, description = "Version to compare from"),
+        ("to" = String, Query, description = "Version to compare to"),
+    ),
+    responses(
+        (status = 200, body = OutputsDiff),
+        (status = 400, description = "Missing versions"),
+        (status = 404, description = "Unknown release or release without outputs"),
+    )
+)]
+pub async fn get_outputs_diff(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<OutputsDiff>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let (Some(from), Some(to)) = (params.get("from"), params.get("to")) else {
+        return Err(AppError::BadRequest(
+            "from and to versions are required".to_string(),
+        ));
+    };
+
+    let from_outputs = with_db_timeout(
+        state.db_timeout,
+        "get_release_outputs",
+        get_release_outputs(&owner, &repo, from, &state.pool),
+    )
+    .await?;
+    let to_outputs = with_db_timeout(
+        state.db_timeout,
+        "get_release_outputs",
+        get_release_outputs(&owner, &repo, to, &state.pool),
+    )
+    .await?;
+    let (Some(from_outputs), Some(to_outputs)) = (from_outputs, to_outputs) else {
+        return Err(AppError::NotFound);
+    };
+
+    let (added, removed, changed) = diff_outputs(&from_outputs, &to_outputs);
+    Ok(Json(OutputsDiff {
+        from: from.clone(),
+        to: to.clone(),
+        added,
+        removed,
+        changed,
+    }))
+}
+
+// Compares outputs by their attribute path, e.g. `packages.x86_64-linux.default`
+fn diff_outputs(from: &Value, to: &Value) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let from = flatten_outputs(from);
+    let to = flatten_outputs(to);
+
+    let added = to
+        .keys()
+        .filter(|name| !from.contains_key(*name))
+        .cloned()
+        .collect();
+    let removed = from
+        .keys()
+        .filter(|name| !to.contains_key(*name))
+        .cloned()
+        .collect();
+    let changed = from
+        .iter()
+        .filter(|(name, output)| to.get(*name).is_some_and(|other| other != *output))
+        .map(|(name, _)| name.clone())
+        .collect();
+    (added, removed, changed)
+}
+
+// `nix flake show --json` nests outputs by category and system, the leaves are the
+// objects describing an output with their `type`
+fn flatten_outputs(outputs: &Value) -> BTreeMap<String, &Value> {
+    fn walk<'a>(path: String, value: &'a Value, leaves: &mut BTreeMap<String, &'a Value>) {
+        match value.as_object() {
+            Some(children) if !children.contains_key("type") => {
+                for (name, child) in children {
+                    let child_path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{path}.{name}")
+                    };
+                    walk(child_path, child, leaves);
+                }
+            }
+            _ if !path.is_empty() => {
+                leaves.insert(path, value);
+            }
+            _ => {}
+        }
+    }
+
+    let mut leaves = BTreeMap::new();
+    walk(String::new(), outputs, &mut leaves);
+    leaves
+}
+
+// `None` if the release doesn't exist, releases published without outputs have none
+async fn get_release_outputs(
+    owner: &str,
+    repo: &str,
+    version: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<Value>, AppError> {
+    let outputs: Option<Option<Value>> = sqlx::query_scalar(
+        "SELECT release.outputs \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 AND release.version = $3",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch release outputs from database")?;
+
+    Ok(outputs.map(|outputs| outputs.unwrap_or_else(|| json!({}))))
+}
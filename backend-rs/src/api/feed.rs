@@ -0,0 +1,137 @@
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{NaiveDateTime, Utc};
+use std::{fmt::Write, sync::Arc};
+
+use crate::api::flake::{get_flakes, int_param, FlakeReleaseCompact};
+use crate::common::{with_db_timeout, AppError, AppState, QueryParams};
+
+// Releases per page of the feed
+const FEED_LIMIT: i64 = 50;
+
+/// Atom feed of the newest releases across all flakes. Older releases are on the following
+/// pages, linked from each page like RFC 5005 paged feeds.
+#[utoipa::path(
+    get,
+    path = "/api/releases.atom",
+    params(("page" = Option<i64>, Query, description = "Page of the feed, starting at 1")),
+    responses(
+        (status = 200, body = String, content_type = "application/atom+xml"),
+        (status = 400, description = "Invalid page"),
+    )
+)]
+pub async fn get_releases_feed(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Response, AppError> {
+    let page = int_param(&params, "page", 1)?.unwrap_or(1);
+    let offset = (page - 1).saturating_mul(FEED_LIMIT);
+    // One more release than shown tells whether there's a next page
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes",
+        get_flakes(FEED_LIMIT + 1, offset, false, &state.pool),
+    )
+    .await?;
+    let has_next = releases.len() > FEED_LIMIT as usize;
+    releases.truncate(FEED_LIMIT as usize);
+    let feed = render_feed(&state.base_url, &releases, page, has_next);
+
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml")], feed).into_response())
+}
+
+// The URL of a page of the feed, the first one without a page number
+fn page_url(base_url: &str, page: i64) -> String {
+    if page == 1 {
+        format!("{base_url}/api/releases.atom")
+    } else {
+        format!("{base_url}/api/releases.atom?page={page}")
+    }
+}
+
+fn render_feed(
+    base_url: &str,
+    releases: &[FlakeReleaseCompact],
+    page: i64,
+    has_next: bool,
+) -> String {
+    // Releases are sorted newest first
+    let updated = releases
+        .first()
+        .map_or_else(|| Utc::now().naive_utc(), |release| release.created_at);
+
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str("  <title>Flakestry releases</title>\n");
+    let _ = writeln!(feed, "  <id>{}/</id>", escape(base_url));
+    let _ = writeln!(
+        feed,
+        "  <link rel=\"self\" href=\"{}\"/>",
+        escape(&page_url(base_url, page))
+    );
+    if page > 1 {
+        let _ = writeln!(
+            feed,
+            "  <link rel=\"previous\" href=\"{}\"/>",
+            escape(&page_url(base_url, page - 1))
+        );
+    }
+    if has_next {
+        let _ = writeln!(
+            feed,
+            "  <link rel=\"next\" href=\"{}\"/>",
+            escape(&page_url(base_url, page + 1))
+        );
+    }
+    let _ = writeln!(feed, "  <link href=\"{}/\"/>", escape(base_url));
+    let _ = writeln!(feed, "  <updated>{}</updated>", atom_date(updated));
+
+    for release in releases {
+        let url = escape(&format!(
+            "{base_url}/flake/github/{}/{}/{}",
+            release.owner, release.repo, release.version
+        ));
+        feed.push_str("  <entry>\n");
+        let _ = writeln!(
+            feed,
+            "    <title>{}/{} {}</title>",
+            escape(&release.owner),
+            escape(&release.repo),
+            escape(&release.version)
+        );
+        let _ = writeln!(feed, "    <id>{url}</id>");
+        let _ = writeln!(feed, "    <link href=\"{url}\"/>");
+        let _ = writeln!(
+            feed,
+            "    <updated>{}</updated>",
+            atom_date(release.created_at)
+        );
+        let _ = writeln!(
+            feed,
+            "    <summary>{}</summary>",
+            escape(&release.description)
+        );
+        feed.push_str("  </entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+// Release timestamps are stored in UTC
+fn atom_date(date: NaiveDateTime) -> String {
+    date.and_utc()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
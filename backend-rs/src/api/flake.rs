@@ -1,140 +1,2668 @@
 use anyhow::Context;
 use axum::{
-    extract::{Query, State},
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use chrono::NaiveDateTime;
-use opensearch::{OpenSearch, SearchParts};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use futures_util::TryStreamExt;
+use opensearch::{CreatePitParts, OpenSearch, SearchParts};
 use serde_json::{json, Value};
 use sqlx::{postgres::PgRow, FromRow, Pool, Postgres, Row};
-use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+use std::{borrow::Cow, cmp::Reverse, collections::HashMap, sync::Arc, time::Duration};
+use utoipa::ToSchema;
 
-use crate::common::{AppError, AppState};
+use crate::api::publish::{
+    decompress_readme, is_valid_commit, is_valid_license, is_valid_system, is_valid_tag,
+    normalize_name, readme_hash, truncate_readme, ReadmeType,
+};
+use crate::api::Outputs;
+use crate::common::{
+    with_db_timeout, AppError, AppState, Paginated, QueryParams, ServerTiming, WithReleases,
+};
+use crate::metrics::{self, Backend};
+
+// A compact subset of a FlakeRelease for use in search results
+#[derive(serde::Serialize, ToSchema)]
+pub(crate) struct FlakeReleaseCompact {
+    #[serde(skip_serializing)]
+    id: i32,
+    pub(crate) owner: String,
+    // The owner's GitHub profile and, with `ENRICH_AVATARS`, avatar
+    owner_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_avatar_url: Option<String>,
+    pub(crate) repo: String,
+    pub(crate) version: String,
+    pub(crate) description: String,
+    pub(crate) created_at: NaiveDateTime,
+    // The same as `created_at` until the release is changed, e.g. by an admin
+    updated_at: NaiveDateTime,
+    // Only set for search results in explain mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<Value>,
+}
+
+impl FromRow<'_, PgRow> for FlakeReleaseCompact {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let owner: String = row.try_get("owner")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            owner_url: owner_url(&owner),
+            owner_avatar_url: None,
+            owner,
+            repo: row.try_get("repo")?,
+            version: row.try_get("version")?,
+            description: row.try_get("description").unwrap_or_default(),
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            score: None,
+            explanation: None,
+        })
+    }
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub(crate) struct FlakeRelease {
+    #[serde(skip_serializing)]
+    id: i32,
+    owner: String,
+    owner_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_avatar_url: Option<String>,
+    repo: String,
+    version: String,
+    version_scheme: VersionScheme,
+    description: String,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    commit: String,
+    // Copy-paste references to the release, pinned to its commit
+    flake_ref: String,
+    nix_run: String,
+    readme: String,
+    // sha256 of the full readme, the same across versions with an unchanged readme
+    readme_hash: Option<String>,
+    readme_type: ReadmeType,
+    // Empty for releases published without outputs
+    outputs: Outputs,
+    systems: Vec<String>,
+    tags: Vec<String>,
+    // Chosen by an admin as the version to use, at most one release of a repo is
+    recommended: bool,
+    // Only known when the publisher sent them
+    closure_size: Option<i64>,
+    nar_hash: Option<String>,
+    // Only shown to admins, for moderation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published_by: Option<String>,
+}
+
+impl FromRow<'_, PgRow> for FlakeRelease {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let version: String = row.try_get("version")?;
+        let readme = stored_readme(
+            row.try_get("readme_content")?,
+            row.try_get("readme_hash")?,
+            row.try_get("readme")?,
+        );
+        let owner: String = row.try_get("owner")?;
+        let repo: String = row.try_get("repo")?;
+        // A single release with a NULL commit or readme shouldn't fail the whole repo
+        let commit: String = row.try_get("commit").unwrap_or_default();
+        let flake_ref = flake_ref(&owner, &repo, &commit);
+        Ok(Self {
+            id: row.try_get("id")?,
+            nix_run: format!("nix run {flake_ref}"),
+            flake_ref,
+            owner_url: owner_url(&owner),
+            owner_avatar_url: None,
+            owner,
+            repo,
+            version_scheme: VersionScheme::detect(&version),
+            version,
+            description: row.try_get("description").unwrap_or_default(),
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            commit,
+            readme_hash: readme.as_ref().map(|(_, hash)| hash.clone()),
+            readme: readme.map(|(readme, _)| readme).unwrap_or_default(),
+            readme_type: ReadmeType::parse(row.try_get("readme_type")?)
+                .unwrap_or(ReadmeType::Markdown),
+            // Outputs published before they were validated may not fit the model
+            outputs: row
+                .try_get::<Option<sqlx::types::Json<Outputs>>, _>("outputs")
+                .ok()
+                .flatten()
+                .map(|outputs| outputs.0)
+                .unwrap_or_default(),
+            systems: row.try_get("systems")?,
+            tags: row.try_get("tags")?,
+            recommended: row.try_get("recommended")?,
+            closure_size: row.try_get("closure_size")?,
+            nar_hash: row.try_get("nar_hash")?,
+            published_by: row.try_get("published_by")?,
+        })
+    }
+}
+
+impl From<FlakeRelease> for FlakeReleaseCompact {
+    fn from(release: FlakeRelease) -> Self {
+        FlakeReleaseCompact {
+            id: release.id,
+            owner: release.owner,
+            owner_url: release.owner_url,
+            owner_avatar_url: release.owner_avatar_url,
+            repo: release.repo,
+            version: release.version,
+            description: release.description,
+            created_at: release.created_at,
+            updated_at: release.updated_at,
+            score: None,
+            explanation: None,
+        }
+    }
+}
+
+/// Which shape releases are returned in, picked with the `view` parameter. `compact` has the
+/// owner, repo, version, description and creation time, plus scores in explain mode. `full`
+/// adds the commit, flake references, readme, outputs, systems and tags.
+#[derive(Clone, Copy, PartialEq)]
+enum ReleaseView {
+    Compact,
+    Full,
+}
+
+impl ReleaseView {
+    fn parse(params: &HashMap<String, String>, default: ReleaseView) -> Result<Self, AppError> {
+        match params.get("view").map(String::as_str) {
+            None => Ok(default),
+            Some("compact") => Ok(ReleaseView::Compact),
+            Some("full") => Ok(ReleaseView::Full),
+            Some(_) => Err(AppError::BadRequest(
+                "view must be compact or full".to_string(),
+            )),
+        }
+    }
+}
+
+/// What get_flake responds with, picked with the `format` parameter. `text` lists a release
+/// per line as `owner/repo version`, for shell pipelines without a JSON parser.
+#[derive(Clone, Copy, PartialEq)]
+enum ListingFormat {
+    Json,
+    Text,
+}
+
+impl ListingFormat {
+    fn parse(params: &HashMap<String, String>) -> Result<Self, AppError> {
+        match params.get("format").map(String::as_str) {
+            None | Some("json") => Ok(ListingFormat::Json),
+            Some("text") => Ok(ListingFormat::Text),
+            Some(_) => Err(AppError::BadRequest(
+                "format must be json or text".to_string(),
+            )),
+        }
+    }
+}
+
+// The `text` format of releases, which has nothing but their owner, repo and version
+fn releases_text(releases: &[FlakeReleaseCompact]) -> String {
+    releases
+        .iter()
+        .map(|release| format!("{}/{} {}\n", release.owner, release.repo, release.version))
+        .collect()
+}
+
+fn owner_url(owner: &str) -> String {
+    format!("https://github.com/{owner}")
+}
+
+// The avatars of `owners` when releases are enriched with them, which is off by default as it
+// takes GitHub requests
+async fn owner_avatars<'a>(
+    state: &AppState,
+    owners: impl IntoIterator<Item = &'a String>,
+) -> HashMap<String, String> {
+    let Some(ref avatars) = state.avatars else {
+        return HashMap::new();
+    };
+    let mut owners: Vec<String> = owners.into_iter().cloned().collect();
+    owners.sort();
+    owners.dedup();
+    avatars.lookup(owners).await
+}
+
+async fn add_avatars(state: &AppState, releases: &mut [FlakeReleaseCompact]) {
+    let avatars = owner_avatars(state, releases.iter().map(|release| &release.owner)).await;
+    for release in releases {
+        release.owner_avatar_url = avatars.get(&release.owner).cloned();
+    }
+}
+
+/// The `github:` flake reference of a release, pinned to its commit. Releases stored without
+/// a commit can only refer to the repo.
+fn flake_ref(owner: &str, repo: &str, commit: &str) -> String {
+    if commit.is_empty() {
+        format!("github:{owner}/{repo}")
+    } else {
+        format!("github:{owner}/{repo}/{commit}")
+    }
+}
+
+// The readme of a release with its hash, from the compressed copy it refers to or else the
+// text stored with releases published before readmes were compressed. A copy that can't be
+// decompressed is left out rather than failing the request.
+pub(crate) fn stored_readme(
+    content: Option<Vec<u8>>,
+    hash: Option<String>,
+    legacy: Option<String>,
+) -> Option<(String, String)> {
+    match (content, hash) {
+        (Some(content), Some(hash)) => match decompress_readme(&content) {
+            Ok(readme) => Some((readme, hash)),
+            Err(err) => {
+                tracing::error!(hash, "Failed to decompress readme: {err}");
+                None
+            }
+        },
+        _ => legacy.map(|readme| {
+            let hash = readme_hash(&readme);
+            (readme, hash)
+        }),
+    }
+}
+
+// Upper bound for the number of releases returned when listing without a search
+pub const MAX_LIST_LIMIT: i64 = 250;
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct GetFlakeResponse {
+    #[serde(flatten)]
+    #[schema(value_type = PaginatedFlakeReleaseCompact)]
+    releases: WithReleases<FlakeReleaseCompact>,
+    // Kept for clients written before the pagination envelope, same as `items.len()`
+    count: usize,
+    query: Option<String>,
+    source: ResultSource,
+    total_relation: TotalRelation,
+    // Only for searches answered by OpenSearch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    facets: Option<Facets>,
+    // Set when some shards failed to search, so matches may be missing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+    // The point in time searched with `pit`, to pass along for the next page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pit_id: Option<String>,
+    // The request sent to OpenSearch, only in debug mode and for searches it answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_query: Option<Value>,
+    // Why the results may not be what was asked for, for the UI to show
+    warnings: Vec<String>,
+    // Left out when nothing was filtered by
+    #[serde(skip_serializing_if = "AppliedFilters::is_empty")]
+    filters: AppliedFilters,
+}
+
+/// The filters a search was narrowed down by, for the UI to show as active facets.
+#[derive(Default, serde::Serialize, ToSchema)]
+pub struct AppliedFilters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provides: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+}
+
+impl AppliedFilters {
+    fn new(options: &SearchOptions) -> Self {
+        AppliedFilters {
+            owner: options.owner.clone(),
+            repo: options.repo.clone(),
+            provides: options.provides.clone(),
+            license: options.license.clone(),
+            system: options.system.clone(),
+            tag: options.tag.clone(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.owner.is_none()
+            && self.repo.is_none()
+            && self.provides.is_none()
+            && self.license.is_none()
+            && self.system.is_none()
+            && self.tag.is_none()
+    }
+}
+
+const SEARCH_FALLBACK_WARNING: &str =
+    "search is unavailable, these are the matches of a simpler database search";
+const PARTIAL_RESULTS_WARNING: &str =
+    "part of the search index couldn't be searched, some matches may be missing";
+const TRUNCATED_RELEASES_WARNING: &str =
+    "this repo has more releases than these, page through them with offset";
+
+/// Which backend produced the results, searches fall back to the database while OpenSearch is
+/// unavailable.
+#[derive(Clone, Copy, serde::Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultSource {
+    Search,
+    Database,
+}
+
+/// Whether `total` is the exact number of matches or only a lower bound, as OpenSearch stops
+/// counting after `MAX_COUNTED_HITS`.
+#[derive(Clone, Copy, serde::Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TotalRelation {
+    Eq,
+    Gte,
+}
+
+/// How the matches of a search are distributed, to offer them as filters.
+#[derive(Clone, serde::Serialize, ToSchema)]
+pub struct Facets {
+    owners: Vec<FacetCount>,
+    outputs: Vec<FacetCount>,
+}
+
+#[derive(Clone, serde::Serialize, ToSchema)]
+pub struct FacetCount {
+    pub(crate) value: String,
+    pub(crate) count: i64,
+}
+
+// Number of values returned per facet
+const FACET_SIZE: usize = 10;
+
+// Number of hits requested from OpenSearch per page of search results by default
+const SEARCH_PAGE_SIZE: i64 = 10;
+const MAX_SEARCH_SIZE: i64 = 50;
+// Deep paging gets expensive for OpenSearch, so only the top results can be paged through
+const MAX_SEARCH_WINDOW: i64 = 1000;
+// Counting every match of a broad query gets expensive too, so totals beyond this are a lower bound
+const MAX_COUNTED_HITS: i64 = 100_000;
+
+// How long browsers and CDNs may reuse search results and the listing of the newest releases
+const SEARCH_CACHE_CONTROL: &str = "public, max-age=30";
+const LISTING_CACHE_CONTROL: &str = "public, max-age=60";
+
+// Standard flake output categories that can be looked up with `provides`, and by default the
+// only ones a release can be published with
+pub const FLAKE_OUTPUTS: &[&str] = &[
+    "apps",
+    "checks",
+    "darwinModules",
+    "devShells",
+    "formatter",
+    "homeManagerModules",
+    "hydraJobs",
+    "legacyPackages",
+    "lib",
+    "nixosConfigurations",
+    "nixosModules",
+    "overlays",
+    "packages",
+    "templates",
+];
+
+// Fields a search query can match and how much they weigh by default, `SEARCH_FIELDS` in the
+// environment picks which of them are searched
+pub const SEARCH_FIELDS: [(&str, f64); 5] = [
+    ("description", 2.0),
+    ("readme", 1.0),
+    ("outputs", 1.0),
+    ("repo", 2.0),
+    ("owner", 2.0),
+];
+const MAX_BOOST: f64 = 10.0;
+
+/// How searching with `freshness=true` lowers the scores of older releases, following
+/// OpenSearch's `gauss` decay: releases up to `offset` old keep their score, and the score of
+/// ones `offset + scale` old is multiplied by `decay`.
+#[derive(Clone)]
+pub struct FreshnessDecay {
+    // OpenSearch time units like `30d` or `12h`
+    pub offset: String,
+    pub scale: String,
+    pub decay: f64,
+}
+
+impl Default for FreshnessDecay {
+    fn default() -> Self {
+        FreshnessDecay {
+            offset: "30d".to_string(),
+            scale: "180d".to_string(),
+            decay: 0.5,
+        }
+    }
+}
+
+impl FreshnessDecay {
+    // Multiplying keeps textual relevance in charge, a release loses at most part of its score.
+    // Documents indexed before they had a creation date keep their score.
+    fn function_score(&self, query: Value) -> Value {
+        json!({
+            "function_score": {
+                "query": query,
+                "functions": [{
+                    "gauss": {
+                        "created_at": {
+                            "origin": "now",
+                            "offset": self.offset,
+                            "scale": self.scale,
+                            "decay": self.decay,
+                        }
+                    }
+                }],
+                "boost_mode": "multiply",
+            }
+        })
+    }
+}
+
+/// The amount of an OpenSearch time value like `30d`, which has to be a whole number of days,
+/// hours, minutes, seconds or milliseconds.
+pub fn parse_time_value(value: &str) -> Option<u64> {
+    let unit_start = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(unit_start);
+    if !matches!(unit, "d" | "h" | "m" | "s" | "ms") {
+        return None;
+    }
+    amount.parse().ok()
+}
+
+struct SearchOptions {
+    query: Option<String>,
+    // `AppState::search_fields` with their boosts, possibly overridden by the request
+    fields: Vec<(&'static str, f64)>,
+    // Only readmes are searched, which the database can't fall back to
+    readme_only: bool,
+    provides: Option<String>,
+    license: Option<String>,
+    // Only releases targeting this Nix system
+    system: Option<String>,
+    tag: Option<String>,
+    // Only releases of this owner
+    owner: Option<String>,
+    // Only releases of this repo of `owner`
+    repo: Option<String>,
+    from: i64,
+    size: i64,
+    explain: bool,
+    // The request sent to OpenSearch is returned along with the results
+    debug_query: bool,
+    minimum_should_match: Option<String>,
+    // Only terms matching exactly, without the fuzziness that tolerates typos
+    exact: bool,
+    // Releases of archived repos are left out otherwise
+    include_archived: bool,
+    // Only the best scoring release of each repo is returned
+    group_by_repo: bool,
+    // Set to have older releases score lower
+    freshness: Option<FreshnessDecay>,
+    // Searches a snapshot of the index, so that paging isn't thrown off by a reindex
+    pit: Option<Pit>,
+}
+
+enum Pit {
+    New,
+    Existing(String),
+}
+
+// How long a point in time is kept after each search using it
+const PIT_KEEP_ALIVE: &str = "5m";
+
+impl SearchOptions {
+    // Debugging output is only valid for the one request and never cached
+    fn debugging(&self) -> bool {
+        self.explain || self.debug_query
+    }
+
+    // Without any criteria the newest releases are listed from the database instead
+    fn has_criteria(&self) -> bool {
+        self.query.is_some()
+            || self.provides.is_some()
+            || self.license.is_some()
+            || self.system.is_some()
+            || self.tag.is_some()
+            || self.owner.is_some()
+    }
+
+    // Queries differing only in case or whitespace share their cached results
+    fn cache_key(&self) -> String {
+        let query = self.query.as_ref().map(|q| {
+            q.split_whitespace()
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        json!([
+            query,
+            self.fields,
+            self.provides,
+            self.license,
+            self.system,
+            self.tag,
+            self.owner,
+            self.repo,
+            self.from,
+            self.size,
+            self.minimum_should_match,
+            self.exact,
+            self.include_archived,
+            self.group_by_repo,
+            self.freshness.is_some(),
+        ])
+        .to_string()
+    }
+}
+
+// The validated query parameters of get_flake. `include_archived` applies to listing without
+// search criteria too.
+struct FlakeParams {
+    search: SearchOptions,
+    // Number of releases when listing without any search criteria
+    limit: i64,
+    // Number of newer releases skipped by the listing
+    offset: i64,
+    // Only these fields of each release are returned, all of them when unset
+    fields: Option<Vec<&'static str>>,
+    view: ReleaseView,
+    format: ListingFormat,
+}
+
+// The fields of either view of a release a client can ask get_flake for
+const RELEASE_FIELDS: [&str; 24] = [
+    "owner",
+    "owner_url",
+    "owner_avatar_url",
+    "repo",
+    "version",
+    "description",
+    "created_at",
+    "updated_at",
+    "score",
+    "explanation",
+    "version_scheme",
+    "commit",
+    "flake_ref",
+    "nix_run",
+    "readme",
+    "readme_hash",
+    "readme_type",
+    "outputs",
+    "systems",
+    "tags",
+    "recommended",
+    "closure_size",
+    "nar_hash",
+    "published_by",
+];
+
+fn parse_flake_params(
+    mut params: HashMap<String, String>,
+    state: &AppState,
+) -> Result<FlakeParams, AppError> {
+    let query = params.remove("q");
+    if query
+        .as_ref()
+        .is_some_and(|q| q.chars().count() > state.max_query_length)
+    {
+        return Err(AppError::BadRequest(format!(
+            "q must be at most {} characters",
+            state.max_query_length
+        )));
+    }
+    let provides = params
+        .remove("provides")
+        .map(|output| normalize_output(&output));
+    // Releases are only indexed with the output kinds they may be published with
+    if provides
+        .as_ref()
+        .is_some_and(|output| !state.output_kinds.contains(output))
+    {
+        return Err(AppError::BadRequest(format!(
+            "provides must be one of {}",
+            state.output_kinds.join(", ")
+        )));
+    }
+    let license = params.remove("license");
+    let pit = params.remove("pit").map(|pit| match pit.as_str() {
+        "new" => Pit::New,
+        _ => Pit::Existing(pit),
+    });
+    if license
+        .as_deref()
+        .is_some_and(|license| !is_valid_license(license))
+    {
+        return Err(AppError::BadRequest(
+            "license must be a SPDX license id".to_string(),
+        ));
+    }
+    let system = params.remove("system");
+    if system
+        .as_deref()
+        .is_some_and(|system| !is_valid_system(system))
+    {
+        return Err(AppError::BadRequest(
+            "system must be a Nix system like x86_64-linux".to_string(),
+        ));
+    }
+    // Exact names like in repo URLs, `nixos` doesn't match `nixos-community`
+    let owner = params.remove("owner").map(|owner| normalize_name(&owner));
+    let repo = params.remove("repo").map(|repo| normalize_name(&repo));
+    if repo.is_some() && owner.is_none() {
+        return Err(AppError::BadRequest(
+            "repo can only be filtered by together with owner".to_string(),
+        ));
+    }
+    // Tags are stored lowercased
+    let tag = params.remove("tag").map(|tag| tag.trim().to_lowercase());
+    if tag.as_deref().is_some_and(|tag| !is_valid_tag(tag)) {
+        return Err(AppError::BadRequest(
+            "tag must consist of letters, digits and -".to_string(),
+        ));
+    }
+
+    // `per_page` sizes the pages of searches and of the listing alike, capped where each has its
+    // limit. The more specific `size` and `limit` win over it.
+    let per_page = int_param(&params, "per_page", 1)?;
+    let size = match int_param(&params, "size", 1)? {
+        Some(size) => size,
+        None => per_page.map_or(SEARCH_PAGE_SIZE, |per_page| per_page.min(MAX_SEARCH_SIZE)),
+    };
+    if size > MAX_SEARCH_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "size must be at most {MAX_SEARCH_SIZE}"
+        )));
+    }
+    // `page` is a shorthand for `from` in multiples of `size`
+    let from = match int_param(&params, "from", 0)? {
+        Some(from) => from,
+        None => (int_param(&params, "page", 1)?.unwrap_or(1) - 1).saturating_mul(size),
+    };
+
+    let readme_only = match params.get("scope").map(String::as_str) {
+        None | Some("all") => false,
+        Some("readme") => true,
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "scope must be all or readme".to_string(),
+            ))
+        }
+    };
+    // Boosts don't matter when the readme is the only field searched
+    let fields = match params.get("boost") {
+        _ if readme_only => readme_fields(),
+        Some(boost) => parse_boost(boost, &state.search_fields)?,
+        None => state.search_fields.clone(),
+    };
+
+    let limit = int_param(&params, "limit", 1)?
+        .or(per_page)
+        .map_or(state.default_list_limit, |limit| limit.min(MAX_LIST_LIMIT));
+    // Like for searches, `page` is a shorthand for `offset` in multiples of `limit`
+    let offset = match int_param(&params, "offset", 0)? {
+        Some(offset) => offset,
+        None => (int_param(&params, "page", 1)?.unwrap_or(1) - 1).saturating_mul(limit),
+    };
+
+    let search = SearchOptions {
+        query,
+        fields,
+        readme_only,
+        provides,
+        license,
+        system,
+        tag,
+        owner,
+        repo,
+        from,
+        size,
+        explain: state.search_debug && params.get("explain").is_some_and(|e| e == "true"),
+        debug_query: state.search_debug && params.get("debug_query").is_some_and(|d| d == "true"),
+        minimum_should_match: state.minimum_should_match.clone(),
+        exact: params.get("exact").is_some_and(|e| e == "true"),
+        include_archived: params.get("include_archived").is_some_and(|a| a == "true"),
+        group_by_repo: params.get("group_by_repo").is_some_and(|g| g == "true"),
+        freshness: params
+            .get("freshness")
+            .is_some_and(|f| f == "true")
+            .then(|| state.freshness.clone()),
+        pit,
+    };
+    // Listings without a search can be paged through to the end
+    if search.has_criteria() && from.saturating_add(size) > MAX_SEARCH_WINDOW {
+        return Err(AppError::BadRequest(format!(
+            "search results beyond the first {MAX_SEARCH_WINDOW} can't be paged through"
+        )));
+    }
+
+    Ok(FlakeParams {
+        search,
+        limit,
+        offset,
+        fields: params.get("fields").map(|f| parse_fields(f)).transpose()?,
+        view: ReleaseView::parse(&params, ReleaseView::Compact)?,
+        format: ListingFormat::parse(&params)?,
+    })
+}
+
+// Searching only readmes, even when they're left out of the configured fields
+fn readme_fields() -> Vec<(&'static str, f64)> {
+    SEARCH_FIELDS
+        .into_iter()
+        .filter(|(field, _)| *field == "readme")
+        .collect()
+}
+
+// Parses a comma separated list of release fields like `owner,repo,version`
+fn parse_fields(value: &str) -> Result<Vec<&'static str>, AppError> {
+    value
+        .split(',')
+        .map(|name| {
+            RELEASE_FIELDS
+                .into_iter()
+                .find(|field| *field == name.trim())
+                .ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "fields must be a list of {}",
+                        RELEASE_FIELDS.join(", ")
+                    ))
+                })
+        })
+        .collect()
+}
+
+// Parses boosts like `readme:3,description:1` for the searched `fields`, fields which aren't
+// mentioned keep their default
+fn parse_boost(
+    boost: &str,
+    fields: &[(&'static str, f64)],
+) -> Result<Vec<(&'static str, f64)>, AppError> {
+    let mut fields = fields.to_vec();
+    for part in boost.split(',') {
+        let invalid = || {
+            AppError::BadRequest(format!(
+                "boost must be a list of field:weight pairs with weights between 0 and {MAX_BOOST}"
+            ))
+        };
+        let (name, weight) = part.split_once(':').ok_or_else(invalid)?;
+        let weight: f64 = weight.trim().parse().map_err(|_| invalid())?;
+        if !(weight > 0.0 && weight <= MAX_BOOST) {
+            return Err(invalid());
+        }
+        let field = fields
+            .iter_mut()
+            .find(|(field, _)| *field == name.trim())
+            .ok_or_else(|| AppError::BadRequest(format!("{name} can't be boosted")))?;
+        field.1 = weight;
+    }
+    Ok(fields)
+}
+
+// An optional integer query parameter which has to be at least `min`
+pub(crate) fn int_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    min: i64,
+) -> Result<Option<i64>, AppError> {
+    match params.get(name).map(|value| value.parse::<i64>()) {
+        None => Ok(None),
+        Some(Ok(value)) if value >= min => Ok(Some(value)),
+        Some(_) => Err(AppError::BadRequest(format!(
+            "{name} must be an integer of at least {min}"
+        ))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake",
+    params(
+        ("q" = Option<String>, Query, description = "Search query, with phrases to match exactly in double quotes. The newest releases are listed without one"),
+        ("provides" = Option<String>, Query, description = "Only flakes with this output, e.g. `packages` or `overlay`, one of the output kinds releases are published with"),
+        ("license" = Option<String>, Query, description = "Only flakes with this SPDX license id"),
+        ("system" = Option<String>, Query, description = "Only flakes supporting this Nix system, e.g. `aarch64-darwin`"),
+        ("tag" = Option<String>, Query, description = "Only flakes tagged with this keyword, e.g. `cli`"),
+        ("owner" = Option<String>, Query, description = "Only flakes of this GitHub owner, searched without a query too"),
+        ("repo" = Option<String>, Query, description = "Only flakes of this repo of `owner`"),
+        ("boost" = Option<String>, Query, description = "Field weights like `readme:3,description:1`"),
+        ("scope" = Option<String>, Query, description = "`all`, the default, to match the query against every searched field, or `readme` to only match readmes"),
+        ("size" = Option<i64>, Query, description = "Search results per page"),
+        ("from" = Option<i64>, Query, description = "Offset of the first search result"),
+        ("page" = Option<i64>, Query, description = "Page of search results, or of releases listed without a search, starting at 1"),
+        ("per_page" = Option<i64>, Query, description = "Results per page, of searches and of releases listed without one, at most 50 for searches and 250 otherwise"),
+        ("limit" = Option<i64>, Query, description = "Number of releases listed without a search"),
+        ("offset" = Option<i64>, Query, description = "Number of releases skipped by the listing without a search"),
+        ("group_by_repo" = Option<bool>, Query, description = "Only return the best release of each repo"),
+        ("exact" = Option<bool>, Query, description = "Only match the query terms exactly, without tolerating typos"),
+        ("freshness" = Option<bool>, Query, description = "Favour recent releases over older ones"),
+        ("include_archived" = Option<bool>, Query, description = "Include releases of archived repos"),
+        ("fields" = Option<String>, Query, description = "Only return these fields of each release, like `owner,repo,version`"),
+        ("view" = Option<String>, Query, description = "`compact`, the default, for the owner, repo, version, description and creation time, or `full` to add the commit, flake references, readme, outputs, systems and tags"),
+        ("format" = Option<String>, Query, description = "`json`, the default, or `text` for a plain text line of `owner/repo version` per release, which ignores `fields` and `view`"),
+        ("pit" = Option<String>, Query, description = "`new` to search a point in time snapshot of the index, or the `pit_id` of a previous page to keep paging through the same snapshot"),
+        ("debug_query" = Option<bool>, Query, description = "Return the request sent to OpenSearch as `debug_query`, ignored unless `SEARCH_DEBUG` is set"),
+    ),
+    responses(
+        (status = 200, description = "Releases, as plain text with `format=text`", content(
+            ("application/json" = GetFlakeResponse),
+            ("text/plain" = String),
+        )),
+        (status = 400, description = "Invalid query parameters, a parameter given more than once, or an expired `pit`"),
+        (status = 503, description = "Search or database unavailable"),
+    )
+)]
+pub async fn get_flake(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Response, AppError> {
+    let params = parse_flake_params(params, &state)?;
+    let query = params.search.query.clone();
+    let filters = AppliedFilters::new(&params.search);
+    let (searched, debugging) = (params.search.has_criteria(), params.search.debugging());
+    let (fields, view, format) = (params.fields, params.view, params.format);
+    let mut timing = ServerTiming::default();
+
+    let (releases, source, total_relation, facets, partial, pit_id, debug_query) =
+        if params.search.has_criteria() {
+            let options = params.search;
+            let results = timing.search(cached_search_flakes(&state, &options)).await;
+            let mut results = match results {
+                Ok(results) => results,
+                // Licenses are only stored in the search index, so there's nothing to fall back to
+                Err(err) if options.license.is_some() => return Err(err),
+                // Neither are readmes matched by the database search
+                Err(err) if options.readme_only && options.query.is_some() => return Err(err),
+                // An expired point in time has to be replaced by the client
+                Err(err @ AppError::BadRequest(_)) => return Err(err),
+                Err(_) => {
+                    tracing::warn!("Falling back to searching the database");
+                    let search = search_flakes_pg(&options, &state.pool);
+                    timing
+                        .db(with_db_timeout(
+                            state.db_timeout,
+                            "search_flakes_pg",
+                            search,
+                        ))
+                        .await?
+                }
+            };
+
+            let mut releases = timing
+                .db(with_db_timeout(
+                    state.db_timeout,
+                    "get_flakes_by_ids",
+                    get_flakes_by_ids(results.hits.keys().collect(), &state.pool),
+                ))
+                .await?;
+
+            sort_by_score(&mut releases, &results.hits);
+
+            if options.explain {
+                for release in &mut releases {
+                    release.score = results
+                        .hits
+                        .get(&release.id)
+                        .map(|&score| round_score(score, state.score_decimals));
+                    release.explanation = results.explanations.remove(&release.id);
+                }
+            }
+
+            let releases = Paginated {
+                items: releases,
+                total: results.total,
+                limit: options.size,
+                offset: options.from,
+            };
+            (
+                releases,
+                results.source,
+                results.total_relation,
+                results.facets,
+                results.partial,
+                results.pit_id,
+                results.debug_query,
+            )
+        } else {
+            let releases = timing
+                .db(with_db_timeout(
+                    state.db_timeout,
+                    "get_flakes",
+                    get_flakes(
+                        params.limit,
+                        params.offset,
+                        params.search.include_archived,
+                        &state.pool,
+                    ),
+                ))
+                .await?;
+            let total = timing
+                .db(with_db_timeout(
+                    state.db_timeout,
+                    "count_flakes",
+                    count_flakes(params.search.include_archived, &state.pool),
+                ))
+                .await?;
+
+            let releases = Paginated {
+                items: releases,
+                total,
+                limit: params.limit,
+                offset: params.offset,
+            };
+            (
+                releases,
+                ResultSource::Database,
+                TotalRelation::Eq,
+                None,
+                false,
+                None,
+                None,
+            )
+        };
+    // Degraded results shouldn't outlive the outage, caches keep them apart by query string.
+    // Points in time expire, so neither should their pages.
+    let cache_control = match source {
+        _ if !searched => LISTING_CACHE_CONTROL,
+        ResultSource::Search if !debugging && !partial && pit_id.is_none() => SEARCH_CACHE_CONTROL,
+        _ => "no-store",
+    };
+    if format == ListingFormat::Text {
+        return Ok((
+            timing.header(),
+            [
+                (header::CACHE_CONTROL, cache_control),
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            ],
+            releases_text(&releases.items),
+        )
+            .into_response());
+    }
+    let mut releases = releases;
+    add_avatars(&state, &mut releases.items).await;
+    let full = match view {
+        ReleaseView::Compact => None,
+        ReleaseView::Full => Some(
+            timing
+                .db(with_db_timeout(
+                    state.db_timeout,
+                    "get_full_flakes",
+                    get_full_flakes(&releases.items, &state.pool),
+                ))
+                .await?,
+        ),
+    };
+    let mut warnings = Vec::new();
+    if searched && matches!(source, ResultSource::Database) {
+        warnings.push(SEARCH_FALLBACK_WARNING.to_string());
+    }
+    if partial {
+        warnings.push(PARTIAL_RESULTS_WARNING.to_string());
+    }
+    let count = releases.items.len();
+    let response = GetFlakeResponse {
+        releases: WithReleases(releases),
+        count,
+        query,
+        source,
+        total_relation,
+        facets,
+        partial,
+        pit_id,
+        debug_query,
+        warnings,
+        filters,
+    };
+    let body = if full.is_none() && fields.is_none() {
+        Json(response).into_response()
+    } else {
+        let mut body = json!(response);
+        if let Some(full) = full {
+            body["items"] = json!(full);
+        }
+        if let Some(fields) = fields {
+            body = project_releases(body, &fields);
+        }
+        // `releases` has to list the same releases as `items`
+        body["releases"] = body["items"].clone();
+        Json(body).into_response()
+    };
+    Ok((
+        timing.header(),
+        [(header::CACHE_CONTROL, cache_control)],
+        body,
+    )
+        .into_response())
+}
+
+// Drops the fields of the serialized releases of a get_flake response that weren't asked for
+fn project_releases(mut response: Value, fields: &[&str]) -> Value {
+    for release in response["items"].as_array_mut().into_iter().flatten() {
+        if let Some(release) = release.as_object_mut() {
+            release.retain(|field, _| fields.contains(&field.as_str()));
+        }
+    }
+    response
+}
+
+// Searches the readmes of a single owner's releases, for the search box of an owner's page.
+// The database fallback can't search readmes, so this fails while OpenSearch is down.
+#[utoipa::path(
+    get,
+    path = "/api/owner/{owner}/search",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("q" = String, Query, description = "Search query, with phrases in double quotes"),
+        ("size" = Option<i64>, Query, description = "Search results per page"),
+        ("from" = Option<i64>, Query, description = "Offset of the first search result"),
+        ("page" = Option<i64>, Query, description = "Page of search results, starting at 1"),
+        ("exact" = Option<bool>, Query, description = "Only match the query terms exactly, without tolerating typos"),
+        ("freshness" = Option<bool>, Query, description = "Favour recent releases over older ones"),
+        ("include_archived" = Option<bool>, Query, description = "Include releases of archived repos"),
+    ),
+    responses(
+        (status = 200, body = PaginatedFlakeReleaseCompact),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 503, description = "Search unavailable"),
+    )
+)]
+pub async fn get_owner_search(
+    State(state): State<Arc<AppState>>,
+    Path(owner): Path<String>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<FlakeReleaseCompact>>, AppError> {
+    let mut options = parse_flake_params(params, &state)?.search;
+    if options.query.is_none() {
+        return Err(AppError::BadRequest("q is required".to_string()));
+    }
+    options.owner = Some(normalize_name(&owner));
+    // The paginated envelope has no room for a point in time id, owner pages are short anyway
+    options.pit = None;
+    options.fields = readme_fields();
+    options.readme_only = true;
+
+    let results = cached_search_flakes(&state, &options).await?;
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes_by_ids",
+        get_flakes_by_ids(results.hits.keys().collect(), &state.pool),
+    )
+    .await?;
+    sort_by_score(&mut releases, &results.hits);
+    add_avatars(&state, &mut releases).await;
+
+    Ok(Json(Paginated {
+        items: releases,
+        total: results.total,
+        limit: options.size,
+        offset: options.from,
+    }))
+}
+
+// Searches the descriptions and readmes of a repo's releases, e.g. for the release that fixed
+// something. Like owner searches this fails while OpenSearch is down.
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/search",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+        ("q" = String, Query, description = "Search query, with phrases in double quotes"),
+        ("size" = Option<i64>, Query, description = "Search results per page"),
+        ("from" = Option<i64>, Query, description = "Offset of the first search result"),
+        ("page" = Option<i64>, Query, description = "Page of search results, starting at 1"),
+        ("exact" = Option<bool>, Query, description = "Only match the query terms exactly, without tolerating typos"),
+        ("freshness" = Option<bool>, Query, description = "Favour recent releases over older ones"),
+    ),
+    responses(
+        (status = 200, body = PaginatedFlakeReleaseCompact),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 404, description = "Unknown repo"),
+        (status = 503, description = "Search unavailable"),
+    )
+)]
+pub async fn get_repo_search(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<FlakeReleaseCompact>>, AppError> {
+    let mut options = parse_flake_params(params, &state)?.search;
+    if options.query.is_none() {
+        return Err(AppError::BadRequest("q is required".to_string()));
+    }
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    lookup_repo_id(&owner, &repo, &state).await?;
+    options.owner = Some(owner);
+    options.repo = Some(repo);
+    options.pit = None;
+    // Every release of a repo has the same name and owner, so only these tell them apart
+    options.fields = SEARCH_FIELDS
+        .into_iter()
+        .filter(|(field, _)| matches!(*field, "description" | "readme"))
+        .collect();
+    // Searching an archived repo's releases is asking for them
+    options.include_archived = true;
+    options.group_by_repo = false;
+
+    let results = cached_search_flakes(&state, &options).await?;
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes_by_ids",
+        get_flakes_by_ids(results.hits.keys().collect(), &state.pool),
+    )
+    .await?;
+    sort_by_score(&mut releases, &results.hits);
+    add_avatars(&state, &mut releases).await;
+
+    Ok(Json(Paginated {
+        items: releases,
+        total: results.total,
+        limit: options.size,
+        offset: options.from,
+    }))
+}
+
+// Accept the singular form of an output category too, e.g. `overlay` for `overlays`
+fn normalize_output(output: &str) -> String {
+    let plural = format!("{output}s");
+    if FLAKE_OUTPUTS.contains(&plural.as_str()) {
+        plural
+    } else {
+        output.to_string()
+    }
+}
+
+// Upper bound for the number of releases fetched by a single batch request
+const BATCH_LIMIT: usize = 100;
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct BatchRequest {
+    ids: Vec<i32>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/flakes/batch",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, body = PaginatedFlakeReleaseCompact),
+        (status = 400, description = "Too many ids"),
+    )
+)]
+pub async fn post_flakes_batch(
+    State(state): State<Arc<AppState>>,
+    Json(batch): Json<BatchRequest>,
+) -> Result<Json<Paginated<FlakeReleaseCompact>>, AppError> {
+    if batch.ids.len() > BATCH_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "at most {BATCH_LIMIT} ids can be requested at once"
+        )));
+    }
+
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes_by_ids",
+        get_flakes_by_ids(batch.ids.iter().collect(), &state.pool),
+    )
+    .await?;
+    // Return the releases in the order they were asked for, unknown ids are skipped
+    releases.sort_by_key(|release| batch.ids.iter().position(|id| *id == release.id));
+
+    let total = releases.len() as i64;
+    Ok(Json(Paginated {
+        items: releases,
+        total,
+        limit: BATCH_LIMIT as i64,
+        offset: 0,
+    }))
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReleasesAfterResponse {
+    #[serde(flatten)]
+    #[schema(value_type = PaginatedFlakeReleaseCompact)]
+    releases: Paginated<FlakeReleaseCompact>,
+    // The `after_id` to continue syncing from, stays the same when nothing is new
+    max_id: i32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/releases",
+    params(
+        ("after_id" = Option<i32>, Query, description = "Only releases with a higher id"),
+        ("limit" = Option<i64>, Query, description = "Number of releases"),
+    ),
+    responses(
+        (status = 200, body = ReleasesAfterResponse),
+        (status = 400, description = "Invalid query parameters"),
+    )
+)]
+// Every release in the order they were published, for mirrors to incrementally sync the
+// registry by passing the last `max_id` as `after_id`
+pub async fn get_releases_after(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<ReleasesAfterResponse>, AppError> {
+    let after_id = int_param(&params, "after_id", 0)?.unwrap_or(0);
+    let after_id = i32::try_from(after_id)
+        .map_err(|_| AppError::BadRequest("after_id is too large".to_string()))?;
+    let limit = int_param(&params, "limit", 1)?
+        .map_or(state.default_list_limit, |limit| limit.min(MAX_LIST_LIMIT));
+
+    let releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes_after",
+        get_flakes_after(after_id, limit, &state.pool),
+    )
+    .await?;
+    let total = with_db_timeout(
+        state.db_timeout,
+        "count_flakes_after",
+        count_flakes_after(after_id, &state.pool),
+    )
+    .await?;
+
+    let max_id = releases.last().map_or(after_id, |release| release.id);
+    Ok(Json(ReleasesAfterResponse {
+        releases: Paginated {
+            items: releases,
+            total,
+            limit,
+            offset: 0,
+        },
+        max_id,
+    }))
+}
+
+// Rows are read ahead of a slow client by at most this many
+const EXPORT_BUFFER: usize = 64;
+
+/// A line of the export, with the id releases aren't otherwise shown with.
+#[derive(serde::Serialize, ToSchema)]
+pub struct ExportedRelease {
+    id: i32,
+    #[serde(flatten)]
+    #[schema(value_type = FlakeRelease)]
+    release: FlakeRelease,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export.ndjson",
+    params(("since_id" = Option<i32>, Query, description = "Only releases with a higher id")),
+    responses(
+        (status = 200, content_type = "application/x-ndjson", body = ExportedRelease,
+            description = "One release per line"),
+        (status = 400, description = "Invalid query parameters"),
+    )
+)]
+// Every release in the order they were published, streamed for full dumps of the registry.
+// Like with `after_id` for `/api/releases`, the id of the last line can be passed as
+// `since_id` to export what was published since.
+pub async fn get_export(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Response, AppError> {
+    let since_id = int_param(&params, "since_id", 0)?.unwrap_or(0);
+    let since_id = i32::try_from(since_id)
+        .map_err(|_| AppError::BadRequest("since_id is too large".to_string()))?;
+
+    // The rows are read by a task of its own, which stops once the client goes away and the
+    // receiving end of the body is dropped
+    let (lines, mut receiver) = tokio::sync::mpsc::channel(EXPORT_BUFFER);
+    tokio::spawn(export_releases(since_id, state.pool.clone(), lines));
+    let body = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response())
+}
+
+// Sends a line per release, or an error cutting the response short since the status is sent
+// already
+async fn export_releases(
+    since_id: i32,
+    pool: Pool<Postgres>,
+    lines: tokio::sync::mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+) {
+    let export = async {
+        let mut tx = pool
+            .begin()
+            .await
+            .context("Failed to start export transaction")?;
+        // Dumping the whole registry takes longer than a query is otherwise allowed to
+        sqlx::query("SET LOCAL statement_timeout = 0")
+            .execute(&mut *tx)
+            .await
+            .context("Failed to lift statement timeout")?;
+        let query = format!("{FULL_RELEASES} WHERE release.id > $1 ORDER BY release.id");
+        let mut releases = sqlx::query_as::<_, FlakeRelease>(&query)
+            .bind(since_id)
+            .fetch(&mut *tx);
+        while let Some(mut release) = releases
+            .try_next()
+            .await
+            .context("Failed to fetch exported release from database")?
+        {
+            release.published_by = None;
+            let release = ExportedRelease {
+                id: release.id,
+                release,
+            };
+            let mut line = serde_json::to_vec(&release).context("Failed to encode release")?;
+            line.push(b'\n');
+            if lines.send(Ok(line)).await.is_err() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    if let Err(err) = export.await {
+        tracing::error!(since_id, "Failed to export releases: {err:#}");
+        let _ = lines.send(Err(std::io::Error::other(err))).await;
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/commit/{sha}",
+    params(("sha" = String, Path, description = "Full or abbreviated commit SHA")),
+    responses(
+        (status = 200, body = PaginatedFlakeReleaseCompact),
+        (status = 400, description = "Invalid commit SHA"),
+        (status = 404, description = "No release of this commit"),
+    )
+)]
+// The releases published from a commit, an abbreviated SHA matches every commit it's a prefix of
+pub async fn get_commit_releases(
+    State(state): State<Arc<AppState>>,
+    Path(sha): Path<String>,
+) -> Result<Json<Paginated<FlakeReleaseCompact>>, AppError> {
+    let sha = sha.to_lowercase();
+    if !is_valid_commit(&sha) {
+        return Err(AppError::BadRequest(
+            "sha must be a commit SHA of at least 7 characters".to_string(),
+        ));
+    }
+
+    let releases = with_db_timeout(
+        state.db_timeout,
+        "get_flakes_by_commit",
+        get_flakes_by_commit(&sha, MAX_LIST_LIMIT, &state.pool),
+    )
+    .await?;
+    if releases.is_empty() {
+        return Err(AppError::NotFound);
+    }
+
+    let total = releases.len() as i64;
+    Ok(Json(Paginated {
+        items: releases,
+        total,
+        limit: MAX_LIST_LIMIT,
+        offset: 0,
+    }))
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct RepoResponse {
+    #[serde(flatten)]
+    #[schema(value_type = PaginatedFlakeRelease)]
+    releases: WithReleases<FlakeRelease>,
+    // Whether releases after this page were left out, `offset` pages through them
+    truncated: bool,
+    // Why the releases may not be all there are, for the UI to show
+    warnings: Vec<String>,
+    meta: RepoMeta,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct RepoMeta {
+    // Number of repos published by the owner of this repo
+    owner_repos: i64,
+    // Number of releases of this repo, regardless of filters
+    releases: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}",
+    params(
+        ("owner" = String, Path, description = "GitHub owner"),
+        ("repo" = String, Path, description = "GitHub repo"),
+        ("include_prerelease" = Option<bool>, Query, description = "Whether to include prereleases, defaults to true"),
+        ("readme" = Option<String>, Query, description = "`full` or a truncated `preview`"),
+        ("min_version" = Option<String>, Query, description = "Only releases at or above this semver version"),
+        ("view" = Option<String>, Query, description = "`full`, the default, or `compact` for the fields search results have"),
+        ("limit" = Option<i64>, Query, description = "Number of releases, at most and by default `MAX_REPO_RELEASES`"),
+        ("offset" = Option<i64>, Query, description = "Number of newer releases to skip"),
+    ),
+    responses(
+        (status = 200, body = RepoResponse),
+        (status = 301, description = "The owner was renamed, the new URL is in Location"),
+        (status = 304, description = "Not modified since If-Modified-Since"),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 404, description = "Unknown repo"),
+    )
+)]
+pub async fn read_repo(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    QueryParams(params): QueryParams,
+) -> Result<Response, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let include_prerelease = match params.get("include_prerelease").map(String::as_str) {
+        None | Some("true") => true,
+        Some("false") => false,
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "include_prerelease must be true or false".to_string(),
+            ));
+        }
+    };
+    // Previews keep the response small for long readmes, detail views want the full text
+    let readme_preview = match params.get("readme").map(String::as_str) {
+        None | Some("full") => false,
+        Some("preview") => true,
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "readme must be full or preview".to_string(),
+            ));
+        }
+    };
+    let view = ReleaseView::parse(&params, ReleaseView::Full)?;
+    let min_version = params
+        .get("min_version")
+        .map(|version| {
+            semver::Version::parse(version).map_err(|_| {
+                AppError::BadRequest(format!(
+                    "min_version {version} isn't a valid semver version"
+                ))
+            })
+        })
+        .transpose()?;
+    let limit = int_param(&params, "limit", 1)?.map_or(state.max_repo_releases, |limit| {
+        limit.min(state.max_repo_releases)
+    });
+    let offset = int_param(&params, "offset", 0)?.unwrap_or(0);
+
+    let mut timing = ServerTiming::default();
+    let repo_id = timing.db(lookup_repo_id(&owner, &repo, &state)).await;
+    let repo_id = match repo_id {
+        // Only the former names of merged owners redirect, anything else is just unknown
+        Err(AppError::NotFound) => {
+            let canonical = timing
+                .db(with_db_timeout(
+                    state.db_timeout,
+                    "get_aliased_owner",
+                    get_aliased_owner(&owner, &state.pool),
+                ))
+                .await?
+                .ok_or(AppError::NotFound)?;
+            let mut location = format!("/api/flake/github/{canonical}/{repo}");
+            if let Some(query) = query {
+                location = format!("{location}?{query}");
+            }
+            return Ok((
+                StatusCode::MOVED_PERMANENTLY,
+                timing.header(),
+                [(header::LOCATION, location)],
+            )
+                .into_response());
+        }
+        repo_id => repo_id?,
+    };
+
+    let mut releases = timing
+        .db(with_db_timeout(
+            state.db_timeout,
+            "get_repo_releases",
+            get_repo_releases(repo_id, &state.pool),
+        ))
+        .await?;
+
+    let release_count = releases.len() as i64;
+    // Releases changed in place bump their own `updated_at`, archiving the repo or deleting one of
+    // its releases the one of the repo
+    let repo_updated_at = timing
+        .db(with_db_timeout(
+            state.db_timeout,
+            "get_repo_updated_at",
+            get_repo_updated_at(repo_id, &state.pool),
+        ))
+        .await?;
+    let last_modified = releases
+        .iter()
+        .map(|release| release.updated_at)
+        .chain([repo_updated_at])
+        .max();
+    if let Some(last_modified) = last_modified {
+        if !modified_since(&headers, last_modified) {
+            return Ok((
+                StatusCode::NOT_MODIFIED,
+                timing.header(),
+                [(header::LAST_MODIFIED, http_date(last_modified))],
+            )
+                .into_response());
+        }
+    }
+
+    let owner_repos = timing
+        .db(with_db_timeout(
+            state.db_timeout,
+            "count_owner_repos",
+            count_owner_repos(repo_id, &state.pool),
+        ))
+        .await?;
+
+    if !include_prerelease {
+        releases.retain(|release| {
+            parse_version(&release.version).is_none_or(|version| version.pre.is_empty())
+        });
+    }
+    // Versions which can't be compared to the floor don't satisfy it
+    if let Some(ref min_version) = min_version {
+        releases.retain(|release| {
+            parse_version(&release.version).is_some_and(|version| version >= *min_version)
+        });
+    }
+    sort_releases(&mut releases);
+    // Newest first, so a prolific repo's oldest releases are the ones paged through
+    let total = releases.len() as i64;
+    let mut releases: Vec<FlakeRelease> = releases
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    let truncated = offset + (releases.len() as i64) < total;
+    let avatars = owner_avatars(&state, [&owner]).await;
+    for release in &mut releases {
+        release.owner_avatar_url = avatars.get(&owner).cloned();
+    }
+    if state.authorize_admin(&headers).is_err() {
+        for release in &mut releases {
+            release.published_by = None;
+        }
+    }
+    if readme_preview {
+        for release in &mut releases {
+            if let Cow::Owned(preview) = truncate_readme(&release.readme, state.readme_max_bytes) {
+                release.readme = preview;
+            }
+        }
+    }
+
+    let compact: Option<Vec<FlakeReleaseCompact>> = (view == ReleaseView::Compact)
+        .then(|| releases.drain(..).map(FlakeReleaseCompact::from).collect());
+    let repo_response = RepoResponse {
+        releases: WithReleases(Paginated {
+            items: releases,
+            total,
+            limit,
+            offset,
+        }),
+        truncated,
+        warnings: truncated
+            .then(|| TRUNCATED_RELEASES_WARNING.to_string())
+            .into_iter()
+            .collect(),
+        meta: RepoMeta {
+            owner_repos,
+            releases: release_count,
+        },
+    };
+    let body = match compact {
+        Some(compact) => {
+            let mut body = json!(repo_response);
+            body["items"] = json!(compact);
+            body["releases"] = body["items"].clone();
+            Json(body).into_response()
+        }
+        None => Json(repo_response).into_response(),
+    };
+    let mut response = (timing.header(), body).into_response();
+    if let Some(last_modified) = last_modified {
+        response.headers_mut().insert(
+            header::LAST_MODIFIED,
+            http_date(last_modified).parse().unwrap(),
+        );
+    }
+    Ok(response)
+}
+
+#[derive(FromRow, serde::Serialize, ToSchema)]
+pub struct RepoOwner {
+    owner: String,
+    // Highest version by semver, `None` when none of the versions parse
+    #[sqlx(default)]
+    latest_version: Option<String>,
+    releases: i64,
+    #[serde(skip)]
+    versions: Vec<String>,
+}
+
+// Every owner that published a repo with this name, e.g. to tell forks from the original
+#[utoipa::path(
+    get,
+    path = "/api/repo/{repo}",
+    params(("repo" = String, Path, description = "GitHub repo")),
+    responses((status = 200, body = PaginatedRepoOwner))
+)]
+pub async fn get_repo_owners(
+    State(state): State<Arc<AppState>>,
+    Path(repo): Path<String>,
+) -> Result<Json<Paginated<RepoOwner>>, AppError> {
+    let repo = normalize_name(&repo);
+    let mut owners = with_db_timeout(
+        state.db_timeout,
+        "get_repo_owners_by_name",
+        get_repo_owners_by_name(&repo, &state.pool),
+    )
+    .await?;
+    for owner in &mut owners {
+        owner.latest_version = owner
+            .versions
+            .iter()
+            .filter_map(|version| Some((parse_version(version)?, version)))
+            .max()
+            .map(|(_, version)| version.clone());
+    }
+
+    let total = owners.len() as i64;
+    Ok(Json(Paginated {
+        items: owners,
+        total,
+        limit: total,
+        offset: 0,
+    }))
+}
+
+// Release timestamps are stored in UTC
+fn http_date(date: NaiveDateTime) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// An unparseable If-Modified-Since is ignored, as required by RFC 9110
+fn modified_since(headers: &HeaderMap, last_modified: NaiveDateTime) -> bool {
+    let Some(since) = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+    else {
+        return true;
+    };
+
+    // HTTP dates only have a precision of seconds
+    last_modified.and_utc().timestamp() > since.timestamp()
+}
+
+// https://shields.io/badges/endpoint-badge
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldsResponse {
+    schema_version: u8,
+    label: &'static str,
+    message: String,
+    color: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/shields.json",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo")),
+    responses((status = 200, body = ShieldsResponse))
+)]
+pub async fn get_shields(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> Result<Json<ShieldsResponse>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let versions = with_db_timeout(
+        state.db_timeout,
+        "get_repo_versions",
+        get_repo_versions(&owner, &repo, &state.pool),
+    )
+    .await?;
+    let latest = latest_version(&versions);
+
+    // shields.io needs a successful response to render anything, so unknown repos get a badge too
+    let (message, color) = match latest {
+        Some(version) => (format!("v{}", version.trim_start_matches('v')), "blue"),
+        None => ("no release".to_string(), "lightgrey"),
+    };
+
+    Ok(Json(ShieldsResponse {
+        schema_version: 1,
+        label: "flakestry",
+        message,
+        color,
+    }))
+}
+
+/// Number of releases published in a month, formatted like `2024-07`.
+#[derive(FromRow, serde::Serialize, ToSchema)]
+pub struct TimelineMonth {
+    month: String,
+    releases: i64,
+}
+
+// Release cadence of a repo for a sparkline, from the month of its first release to the month of
+// its last one. Months without releases are included with a count of 0, so they can be plotted
+// as they are.
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/timeline",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo")),
+    responses(
+        (status = 200, body = Vec<TimelineMonth>),
+        (status = 404, description = "Unknown repo or repo without releases"),
+    )
+)]
+pub async fn get_timeline(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> Result<Json<Vec<TimelineMonth>>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let months = with_db_timeout(
+        state.db_timeout,
+        "get_repo_timeline",
+        get_repo_timeline(&owner, &repo, &state.pool),
+    )
+    .await?;
+    if months.is_empty() {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Json(months))
+}
+
+/// How often a repo releases, to judge how actively it's maintained.
+#[derive(Debug, PartialEq, serde::Serialize, ToSchema)]
+pub struct ReleaseCadence {
+    releases: i64,
+    releases_last_year: i64,
+    // Over the last 12 months, months without releases included
+    releases_per_month: f64,
+    // Between the first and the last release, unset for repos with a single release
+    average_gap_days: Option<f64>,
+    days_since_last_release: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/cadence",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo")),
+    responses(
+        (status = 200, body = ReleaseCadence),
+        (status = 404, description = "Unknown repo or repo without releases"),
+    )
+)]
+pub async fn get_cadence(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> Result<Json<ReleaseCadence>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let created_at = with_db_timeout(
+        state.db_timeout,
+        "get_release_times",
+        get_release_times(&owner, &repo, &state.pool),
+    )
+    .await?;
+
+    release_cadence(&created_at, chrono::Utc::now().naive_utc())
+        .map(Json)
+        .ok_or(AppError::NotFound)
+}
+
+// `None` without releases. A release in the future, e.g. from a skewed clock, was released
+// 0 days ago.
+fn release_cadence(created_at: &[NaiveDateTime], now: NaiveDateTime) -> Option<ReleaseCadence> {
+    let first = *created_at.iter().min()?;
+    let last = *created_at.iter().max()?;
+    let releases = created_at.len() as i64;
+    let year_ago = now - chrono::Duration::days(365);
+    let releases_last_year = created_at
+        .iter()
+        .filter(|created_at| **created_at > year_ago)
+        .count() as i64;
+    let average_gap_days = (releases > 1).then(|| {
+        let span = last - first;
+        span.num_seconds() as f64 / 86_400.0 / (releases - 1) as f64
+    });
+
+    Some(ReleaseCadence {
+        releases,
+        releases_last_year,
+        releases_per_month: releases_last_year as f64 / 12.0,
+        average_gap_days,
+        days_since_last_release: (now - last).num_days().max(0),
+    })
+}
+
+// A single release for its detail page, without loading the rest of the repo like read_repo
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/{version}",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo"), ("version" = String, Path, description = "Release version")),
+    responses(
+        (status = 200, body = FlakeRelease),
+        (status = 404, description = "Unknown release"),
+    )
+)]
+pub async fn get_release(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo, version)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<FlakeRelease>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let mut release = with_db_timeout(
+        state.db_timeout,
+        "get_release_by_version",
+        get_release_by_version(&owner, &repo, &version, &state.pool),
+    )
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    release.owner_avatar_url = owner_avatars(&state, [&owner]).await.remove(&owner);
+    // Like on repo pages, only admins see who published it
+    if state.authorize_admin(&headers).is_err() {
+        release.published_by = None;
+    }
+    Ok(Json(release))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/{version}/readme",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo"), ("version" = String, Path, description = "Release version")),
+    responses(
+        (status = 200, body = String, content_type = ["text/markdown", "text/x-rst", "text/plain"]),
+        (status = 404, description = "Unknown release or release without a readme"),
+    )
+)]
+pub async fn get_readme(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo, version)): Path<(String, String, String)>,
+) -> Result<Response, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let readme = with_db_timeout(
+        state.db_timeout,
+        "get_release_readme",
+        get_release_readme(&owner, &repo, &version, &state.pool),
+    )
+    .await?;
+    let Some((readme, readme_type)) = readme.filter(|(readme, _)| !readme.is_empty()) else {
+        return Err(AppError::NotFound);
+    };
+    let readme_type = ReadmeType::parse(&readme_type).unwrap_or(ReadmeType::Markdown);
+
+    Ok(([(header::CONTENT_TYPE, readme_type.content_type())], readme).into_response())
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct VersionStatus {
+    is_latest: bool,
+    latest: String,
+}
+
+// Lets clients pinning `version` know whether there's a newer one
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/{version}/status",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo"), ("version" = String, Path, description = "Release version")),
+    responses(
+        (status = 200, body = VersionStatus),
+        (status = 404, description = "Unknown release"),
+    )
+)]
+pub async fn get_version_status(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo, version)): Path<(String, String, String)>,
+) -> Result<Json<VersionStatus>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let versions = with_db_timeout(
+        state.db_timeout,
+        "get_repo_versions",
+        get_repo_versions(&owner, &repo, &state.pool),
+    )
+    .await?;
+    if !versions.iter().any(|(stored, _)| *stored == version) {
+        return Err(AppError::NotFound);
+    }
+
+    // Versions like `1.2` and `1.2.0` are different releases even though they parse the same
+    let latest = latest_version(&versions).unwrap_or_default().to_string();
+    Ok(Json(VersionStatus {
+        is_latest: version == latest,
+        latest,
+    }))
+}
+
+// A `github:owner/repo` flake reference, which is all the registry has
+#[derive(Debug, PartialEq)]
+struct GithubFlakeRef {
+    owner: String,
+    repo: String,
+    // A version tag or commit, from the path or the `ref` and `rev` attributes
+    reference: Option<String>,
+}
+
+// Parses flake references like `github:owner/repo/ref?dir=sub`. Other attributes than `ref`,
+// `rev`, `dir` and `host` aren't valid for the `github:` scheme.
+fn parse_github_flake_ref(flake_ref: &str) -> Result<GithubFlakeRef, String> {
+    let malformed = || format!("{flake_ref} isn't of the form github:owner/repo[/ref]");
+    let flake_url = flake_ref.strip_prefix("github:").ok_or_else(malformed)?;
+    let (path, attributes) = flake_url.split_once('?').unwrap_or((flake_url, ""));
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+    };
+    let mut segments = path.split('/');
+    let (Some(owner), Some(repo)) = (segments.next(), segments.next()) else {
+        return Err(malformed());
+    };
+    let mut reference = segments.next().map(str::to_string);
+    if !valid_name(owner)
+        || !valid_name(repo)
+        || reference.as_deref() == Some("")
+        || segments.next().is_some()
+    {
+        return Err(malformed());
+    }
+
+    for attribute in attributes.split('&').filter(|pair| !pair.is_empty()) {
+        match attribute.split_once('=') {
+            Some(("ref" | "rev", value)) if !value.is_empty() => {
+                if reference.replace(value.to_string()).is_some() {
+                    return Err(format!("{flake_ref} has more than one ref or rev"));
+                }
+            }
+            Some(("dir" | "host", _)) => {}
+            _ => {
+                return Err(format!(
+                    "{flake_ref} has an unsupported attribute {attribute}"
+                ))
+            }
+        }
+    }
+
+    Ok(GithubFlakeRef {
+        owner: normalize_name(owner),
+        repo: normalize_name(repo),
+        reference,
+    })
+}
+
+// Lets tooling holding a flake reference look up its registry entry
+#[utoipa::path(
+    get,
+    path = "/api/resolve",
+    params(("ref" = String, Query, description = "A flake reference like `github:owner/repo`, pinned to a version or commit for that release rather than the latest")),
+    responses(
+        (status = 200, body = FlakeRelease),
+        (status = 400, description = "Missing or malformed flake reference"),
+        (status = 404, description = "No release matches the flake reference"),
+    )
+)]
+pub async fn get_resolve(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<FlakeRelease>, AppError> {
+    let flake_ref = params
+        .get("ref")
+        .ok_or_else(|| AppError::BadRequest("ref is required".to_string()))?;
+    let flake_ref = parse_github_flake_ref(flake_ref).map_err(AppError::BadRequest)?;
+
+    let repo_id = lookup_repo_id(&flake_ref.owner, &flake_ref.repo, &state).await?;
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_repo_releases",
+        get_repo_releases(repo_id, &state.pool),
+    )
+    .await?;
+    sort_releases(&mut releases);
+    let mut release = match flake_ref.reference {
+        None => releases.into_iter().next(),
+        // Commits may be abbreviated like in git
+        Some(reference) => releases.into_iter().find(|release| {
+            release.version == reference
+                || release.commit == reference
+                || (is_valid_commit(&reference) && release.commit.starts_with(&reference))
+        }),
+    }
+    .ok_or(AppError::NotFound)?;
+
+    release.published_by = None;
+    release.owner_avatar_url = owner_avatars(&state, [&release.owner])
+        .await
+        .remove(&release.owner);
+    Ok(Json(release))
+}
+
+// The release to use: the one an admin recommended, otherwise the newest stable release, or the
+// newest prerelease of repos without any
+#[utoipa::path(
+    get,
+    path = "/api/flake/github/{owner}/{repo}/recommended",
+    params(("owner" = String, Path, description = "GitHub owner"), ("repo" = String, Path, description = "GitHub repo")),
+    responses(
+        (status = 200, body = FlakeRelease),
+        (status = 404, description = "Unknown repo"),
+    )
+)]
+pub async fn get_recommended(
+    State(state): State<Arc<AppState>>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> Result<Json<FlakeRelease>, AppError> {
+    let (owner, repo) = (normalize_name(&owner), normalize_name(&repo));
+    let repo_id = lookup_repo_id(&owner, &repo, &state).await?;
+    let mut releases = with_db_timeout(
+        state.db_timeout,
+        "get_repo_releases",
+        get_repo_releases(repo_id, &state.pool),
+    )
+    .await?;
+    sort_releases(&mut releases);
+    let is_stable = |release: &FlakeRelease| {
+        parse_version(&release.version).is_none_or(|version| version.pre.is_empty())
+    };
+    let index = releases
+        .iter()
+        .position(|release| release.recommended)
+        .or_else(|| releases.iter().position(is_stable))
+        .unwrap_or(0);
+    if index >= releases.len() {
+        return Err(AppError::NotFound);
+    }
+    let mut release = releases.swap_remove(index);
+
+    release.published_by = None;
+    release.owner_avatar_url = owner_avatars(&state, [&owner]).await.remove(&owner);
+    Ok(Json(release))
+}
+
+// A random release for discovering flakes, drawn from unarchived repos with those having a
+// description or readme twice as likely each. The same `seed` draws the same release as long
+// as no releases are published or removed.
+#[utoipa::path(
+    get,
+    path = "/api/flake/random",
+    params(("seed" = Option<String>, Query, description = "Draws the same release for the same seed")),
+    responses(
+        (status = 200, body = FlakeRelease),
+        (status = 404, description = "No releases to draw from"),
+    )
+)]
+pub async fn get_random_release(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Response, AppError> {
+    let seed = params.get("seed").cloned();
+    let mut release = with_db_timeout(
+        state.db_timeout,
+        "get_random_release",
+        draw_release(seed.as_deref(), &state.pool),
+    )
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    release.published_by = None;
+    release.owner_avatar_url = owner_avatars(&state, [&release.owner])
+        .await
+        .remove(&release.owner);
+    let cache_control = match seed {
+        Some(_) => LISTING_CACHE_CONTROL,
+        None => "no-store",
+    };
+    Ok(([(header::CACHE_CONTROL, cache_control)], Json(release)).into_response())
+}
+
+// Weighted sampling picking the highest `uniform ^ (1 / weight)`, where the uniform number is
+// either random or derived from the release id and seed
+async fn draw_release(
+    seed: Option<&str>,
+    pool: &Pool<Postgres>,
+) -> Result<Option<FlakeRelease>, AppError> {
+    let release = sqlx::query_as(&format!(
+        "{FULL_RELEASES} WHERE NOT githubrepo.archived \
+            ORDER BY power( \
+                CASE WHEN $1::text IS NULL THEN random() \
+                    ELSE ('x' || substr(md5(release.id || ':' || $1), 1, 8))::bit(32)::bigint \
+                        / 4294967296.0 \
+                END, \
+                1.0 / (1 \
+                    + (COALESCE(release.description, '') <> '')::int \
+                    + (release.readme_hash IS NOT NULL OR COALESCE(release.readme, '') <> '')::int) \
+            ) DESC, release.id \
+            LIMIT 1"
+    ))
+    .bind(seed)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to draw a release from database")?;
+
+    Ok(release)
+}
+
+/// How a release is versioned. Only semver versions can be compared with each other.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionScheme {
+    Semver,
+    // A date like `2024-01-15`
+    Date,
+    // Anything else, e.g. a raw git tag
+    Tag,
+}
+
+impl VersionScheme {
+    fn detect(version: &str) -> Self {
+        if parse_version(version).is_some() {
+            VersionScheme::Semver
+        } else if NaiveDate::parse_from_str(version, "%Y-%m-%d").is_ok() {
+            VersionScheme::Date
+        } else {
+            VersionScheme::Tag
+        }
+    }
+}
+
+// Newest version first. Versions that can't be parsed go last, the most recently published
+// first since there's nothing else to order them by.
+fn sort_releases(releases: &mut [FlakeRelease]) {
+    releases.sort_by_cached_key(|release| release_order(&release.version, release.created_at));
+}
+
+fn release_order(version: &str, created_at: NaiveDateTime) -> impl Ord {
+    let parsed = parse_version(version);
+    (
+        parsed.is_none(),
+        Reverse(parsed),
+        Reverse(created_at),
+        // Versions like `1.0` and `v1.0` parse the same
+        version.to_string(),
+    )
+}
+
+// The version of the releases listed first, by version and creation time
+fn latest_version(versions: &[(String, NaiveDateTime)]) -> Option<&str> {
+    versions
+        .iter()
+        .min_by_key(|(version, created_at)| release_order(version, *created_at))
+        .map(|(version, _)| version.as_str())
+}
+
+// Flake versions are validated on publish against `v?MAJOR.MINOR[.PATCH]`, so they aren't
+// always strict semver: accept a leading `v`, a missing patch and zero padded components.
+fn parse_version(version: &str) -> Option<semver::Version> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let (version, pre) = match version.split_once('-') {
+        Some((version, pre)) => (version, Some(pre)),
+        None => (version, None),
+    };
+
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let mut parsed = semver::Version::new(major, minor, patch);
+    if let Some(pre) = pre {
+        parsed.pre = semver::Prerelease::new(pre).ok()?;
+    }
+    if let Some(build) = build {
+        parsed.build = semver::BuildMetadata::new(build).ok()?;
+    }
+    Some(parsed)
+}
+
+// Like `get_repo_id`, looking the repo up in `AppState::repo_ids` first
+async fn lookup_repo_id(owner: &str, repo: &str, state: &AppState) -> Result<i32, AppError> {
+    let key = (owner.to_string(), repo.to_string());
+    if let Some(repo_id) = state.repo_ids.get(&key).await {
+        return Ok(repo_id);
+    }
+
+    let repo_id = with_db_timeout(
+        state.db_timeout,
+        "get_repo_id",
+        get_repo_id(owner, repo, &state.pool),
+    )
+    .await?;
+    state.repo_ids.insert(key, repo_id).await;
+    Ok(repo_id)
+}
+
+// Fails with `NotFound` for unknown repos
+async fn get_repo_id(owner: &str, repo: &str, pool: &Pool<Postgres>) -> Result<i32, AppError> {
+    let repo_id = sqlx::query_scalar(
+        "SELECT githubrepo.id \
+            FROM githubrepo \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2",
+    )
+    .bind(owner)
+    .bind(repo)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch repo id from database")?;
+
+    repo_id.ok_or(AppError::NotFound)
+}
+
+// Versions are unique per repo, so this is a lookup of one row by the unique_repo_version index
+async fn get_release_by_version(
+    owner: &str,
+    repo: &str,
+    version: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<FlakeRelease>, AppError> {
+    let release = sqlx::query_as(&format!(
+        "{FULL_RELEASES} WHERE githubowner.name = $1 AND githubrepo.name = $2 \
+            AND release.version = $3"
+    ))
+    .bind(owner)
+    .bind(repo)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch release from database")?;
+
+    Ok(release)
+}
+
+// The owner a former name was merged into
+async fn get_aliased_owner(alias: &str, pool: &Pool<Postgres>) -> Result<Option<String>, AppError> {
+    let owner = sqlx::query_scalar(
+        "SELECT githubowner.name \
+            FROM owner_alias \
+            INNER JOIN githubowner ON githubowner.id = owner_alias.owner_id \
+            WHERE owner_alias.alias = $1",
+    )
+    .bind(alias)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch owner alias from database")?;
+
+    Ok(owner)
+}
+
+async fn get_release_readme(
+    owner: &str,
+    repo: &str,
+    version: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<(String, String)>, AppError> {
+    type Row = (Option<Vec<u8>>, Option<String>, Option<String>, String);
+    let readme: Option<Row> = sqlx::query_as(
+        "SELECT readme.content, release.readme_hash, release.readme, release.readme_type \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            LEFT JOIN readme ON readme.hash = release.readme_hash \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 AND release.version = $3",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch release readme from database")?;
+
+    Ok(readme.and_then(|(content, hash, legacy, readme_type)| {
+        let (readme, _) = stored_readme(content, hash, legacy)?;
+        Some((readme, readme_type))
+    }))
+}
+
+// Owners with the most releases come first
+async fn get_repo_owners_by_name(
+    repo: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<RepoOwner>, AppError> {
+    let owners = sqlx::query_as(
+        "SELECT githubowner.name AS owner, \
+            COUNT(*) AS releases, \
+            array_agg(release.version) AS versions \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubrepo.name = $1 \
+            GROUP BY githubowner.name \
+            ORDER BY releases DESC, githubowner.name",
+    )
+    .bind(repo)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch repo owners from database")?;
 
-#[derive(serde::Serialize)]
-struct FlakeRelease {
-    #[serde(skip_serializing)]
-    id: i32,
-    owner: String,
-    repo: String,
-    version: String,
-    description: String,
-    created_at: NaiveDateTime,
+    Ok(owners)
 }
 
-impl Eq for FlakeRelease {}
+// Counts the repos of whoever owns `repo_id`
+async fn count_owner_repos(repo_id: i32, pool: &Pool<Postgres>) -> Result<i64, AppError> {
+    let count = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM githubrepo \
+            WHERE owner_id = (SELECT owner_id FROM githubrepo WHERE id = $1)",
+    )
+    .bind(repo_id)
+    .fetch_one(pool)
+    .await
+    .context("Failed to count owner repos in database")?;
 
-impl Ord for FlakeRelease {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.id.cmp(&other.id)
-    }
+    Ok(count)
 }
 
-impl PartialOrd for FlakeRelease {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+async fn get_repo_updated_at(
+    repo_id: i32,
+    pool: &Pool<Postgres>,
+) -> Result<NaiveDateTime, AppError> {
+    let updated_at = sqlx::query_scalar("SELECT updated_at FROM githubrepo WHERE id = $1")
+        .bind(repo_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to fetch repo from database")?;
+
+    Ok(updated_at)
 }
 
-impl PartialEq for FlakeRelease {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    }
+async fn get_repo_versions(
+    owner: &str,
+    repo: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<(String, NaiveDateTime)>, AppError> {
+    let versions = sqlx::query_as(
+        "SELECT release.version, release.created_at \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2",
+    )
+    .bind(owner)
+    .bind(repo)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch repo versions from database")?;
+
+    Ok(versions)
 }
 
-impl FromRow<'_, PgRow> for FlakeRelease {
-    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
-        Ok(Self {
-            id: row.try_get("id")?,
-            owner: row.try_get("owner")?,
-            repo: row.try_get("repo")?,
-            version: row.try_get("version")?,
-            description: row.try_get("description").unwrap_or_default(),
-            created_at: row.try_get("created_at")?,
-        })
-    }
+async fn get_repo_timeline(
+    owner: &str,
+    repo: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<TimelineMonth>, AppError> {
+    let months = sqlx::query_as(
+        "WITH releases AS ( \
+            SELECT date_trunc('month', release.created_at) AS month \
+                FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                WHERE githubowner.name = $1 AND githubrepo.name = $2 \
+        ) \
+        SELECT to_char(series.month, 'YYYY-MM') AS month, COUNT(releases.month) AS releases \
+            FROM generate_series( \
+                (SELECT MIN(month) FROM releases), \
+                (SELECT MAX(month) FROM releases), \
+                interval '1 month' \
+            ) AS series(month) \
+            LEFT JOIN releases ON releases.month = series.month \
+            GROUP BY series.month \
+            ORDER BY series.month",
+    )
+    .bind(owner)
+    .bind(repo)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch repo timeline from database")?;
+
+    Ok(months)
 }
 
-#[derive(serde::Serialize)]
-pub struct GetFlakeResponse {
-    releases: Vec<FlakeRelease>,
-    count: usize,
-    query: Option<String>,
+async fn get_release_times(
+    owner: &str,
+    repo: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<NaiveDateTime>, AppError> {
+    let created_at = sqlx::query_scalar(
+        "SELECT release.created_at \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2",
+    )
+    .bind(owner)
+    .bind(repo)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch release times from database")?;
+
+    Ok(created_at)
 }
 
-pub async fn get_flake(
-    State(state): State<Arc<AppState>>,
-    Query(mut params): Query<HashMap<String, String>>,
-) -> Result<Json<GetFlakeResponse>, AppError> {
-    let query = params.remove("q");
-    let releases = if let Some(ref q) = query {
-        let hits = search_flakes(&state.opensearch, q).await?;
+async fn get_repo_releases(
+    repo_id: i32,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<FlakeRelease>, AppError> {
+    let releases: Vec<FlakeRelease> =
+        sqlx::query_as(&format!("{FULL_RELEASES} WHERE release.repo_id = $1"))
+            .bind(repo_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to fetch repo releases from database")?;
 
-        let mut releases = get_flakes_by_ids(hits.keys().collect(), &state.pool).await?;
+    Ok(releases)
+}
 
-        if !releases.is_empty() {
-            // Should this be done by the DB?
-            releases.sort();
-        }
+// The full view of the `releases`, in the same order and with the same avatars. Who published
+// them is left out, it's only shown to admins on repo pages.
+async fn get_full_flakes(
+    releases: &[FlakeReleaseCompact],
+    pool: &Pool<Postgres>,
+) -> Result<Vec<FlakeRelease>, AppError> {
+    let ids: Vec<i32> = releases.iter().map(|release| release.id).collect();
+    let mut full: HashMap<i32, FlakeRelease> =
+        sqlx::query_as(&format!("{FULL_RELEASES} WHERE release.id = ANY($1)"))
+            .bind(&ids)
+            .fetch_all(pool)
+            .await
+            .context("Failed to fetch releases from database")?
+            .into_iter()
+            .map(|release: FlakeRelease| (release.id, release))
+            .collect();
 
-        releases
-    } else {
-        get_flakes(&state.pool).await?
-    };
-    let count = releases.len();
-    return Ok(Json(GetFlakeResponse {
-        releases,
-        count,
-        query,
-    }));
+    Ok(releases
+        .iter()
+        .filter_map(|release| {
+            let mut full_release = full.remove(&release.id)?;
+            full_release.owner_avatar_url = release.owner_avatar_url.clone();
+            full_release.published_by = None;
+            Some(full_release)
+        })
+        .collect())
 }
 
+// Selects the columns of `FlakeRelease`, to be followed by a WHERE clause
+const FULL_RELEASES: &str = "SELECT release.id AS id, \
+    githubowner.name AS owner, \
+    githubrepo.name AS repo, \
+    release.version AS version, \
+    release.description AS description, \
+    release.created_at AS created_at, \
+    release.updated_at AS updated_at, \
+    release.commit AS commit, \
+    release.readme AS readme, \
+    readme.content AS readme_content, \
+    release.readme_hash AS readme_hash, \
+    release.readme_type AS readme_type, \
+    release.outputs AS outputs, \
+    release.systems AS systems, \
+    release.tags AS tags, \
+    release.recommended AS recommended, \
+    release.closure_size AS closure_size, \
+    release.nar_hash AS nar_hash, \
+    release.published_by AS published_by \
+    FROM release \
+    INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+    INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+    LEFT JOIN readme ON readme.hash = release.readme_hash";
+
+// Neither a page of search results nor a batch request asks for more releases than this
+const MAX_FLAKE_IDS: usize = if BATCH_LIMIT > MAX_SEARCH_SIZE as usize {
+    BATCH_LIMIT
+} else {
+    MAX_SEARCH_SIZE as usize
+};
+
 async fn get_flakes_by_ids(
-    flake_ids: Vec<&i32>,
+    mut flake_ids: Vec<&i32>,
     pool: &Pool<Postgres>,
-) -> Result<Vec<FlakeRelease>, AppError> {
+) -> Result<Vec<FlakeReleaseCompact>, AppError> {
     if flake_ids.is_empty() {
         return Ok(vec![]);
     }
+    if flake_ids.len() > MAX_FLAKE_IDS {
+        tracing::warn!(
+            count = flake_ids.len(),
+            "Too many releases requested by id, only fetching the first {MAX_FLAKE_IDS}"
+        );
+        flake_ids.truncate(MAX_FLAKE_IDS);
+    }
 
-    let param_string = flake_ids.iter().fold(String::new(), |acc, &id| {
-        format!("{acc}{}{id}", if acc.is_empty() { "" } else { "," })
-    });
-    let query = format!(
+    let flake_ids: Vec<i32> = flake_ids.into_iter().copied().collect();
+    let releases: Vec<FlakeReleaseCompact> = sqlx::query_as(
         "SELECT release.id AS id, \
             githubowner.name AS owner, \
             githubrepo.name AS repo, \
             release.version AS version, \
             release.description AS description, \
-            release.created_at AS created_at \
+            release.created_at AS created_at, \
+            release.updated_at AS updated_at \
             FROM release \
             INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
             INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
-            WHERE release.id IN ({param_string})",
-    );
+            WHERE release.id = ANY($1) \
+            ORDER BY release.id",
+    )
+    .bind(&flake_ids)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch flakes by id from database")?;
 
-    let releases: Vec<FlakeRelease> = 
-        sqlx::query_as(&query)
-        .fetch_all(pool)
-        .await
-        .context("Failed to fetch flakes by id from database")?;
+    Ok(releases)
+}
+
+pub(crate) async fn get_flakes(
+    limit: i64,
+    offset: i64,
+    include_archived: bool,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<FlakeReleaseCompact>, AppError> {
+    let releases: Vec<FlakeReleaseCompact> = sqlx::query_as(
+        "SELECT release.id AS id, \
+            githubowner.name AS owner, \
+            githubrepo.name AS repo, \
+            release.version AS version, \
+            release.description AS description, \
+            release.created_at AS created_at, \
+            release.updated_at AS updated_at \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE $2 OR NOT githubrepo.archived \
+            ORDER BY release.created_at DESC, release.id DESC LIMIT $1 OFFSET $3",
+    )
+    .bind(limit)
+    .bind(include_archived)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch flakes from database")?;
 
     Ok(releases)
 }
 
-async fn get_flakes(pool: &Pool<Postgres>) -> Result<Vec<FlakeRelease>, AppError> {
-    let releases: Vec<FlakeRelease> = sqlx::query_as(
+// `sha` has to be validated, it's used as a LIKE pattern
+async fn get_flakes_by_commit(
+    sha: &str,
+    limit: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<FlakeReleaseCompact>, AppError> {
+    let releases: Vec<FlakeReleaseCompact> = sqlx::query_as(
+        "SELECT release.id AS id, \
+            githubowner.name AS owner, \
+            githubrepo.name AS repo, \
+            release.version AS version, \
+            release.description AS description, \
+            release.created_at AS created_at, \
+            release.updated_at AS updated_at \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE release.commit LIKE $1 || '%' \
+            ORDER BY release.created_at DESC, release.id DESC LIMIT $2",
+    )
+    .bind(sha)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch flakes by commit from database")?;
+
+    Ok(releases)
+}
+
+async fn get_flakes_after(
+    after_id: i32,
+    limit: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<FlakeReleaseCompact>, AppError> {
+    let releases: Vec<FlakeReleaseCompact> = sqlx::query_as(
         "SELECT release.id AS id, \
             githubowner.name AS owner, \
             githubrepo.name AS repo, \
             release.version AS version, \
             release.description AS description, \
-            release.created_at AS created_at \
+            release.created_at AS created_at, \
+            release.updated_at AS updated_at \
             FROM release \
             INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
             INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
-            ORDER BY release.created_at DESC LIMIT 100",
+            WHERE release.id > $1 \
+            ORDER BY release.id LIMIT $2",
     )
+    .bind(after_id)
+    .bind(limit)
     .fetch_all(pool)
     .await
     .context("Failed to fetch flakes from database")?;
@@ -142,51 +2670,783 @@ async fn get_flakes(pool: &Pool<Postgres>) -> Result<Vec<FlakeRelease>, AppError
     Ok(releases)
 }
 
-async fn search_flakes(opensearch: &OpenSearch, q: &String) -> Result<HashMap<i32, f64>, AppError> {
-    let res = opensearch
-        .search(SearchParts::Index(&["flakes"]))
-        .size(10)
-        .body(json!({
-            "query": {
-                "multi_match": {
-                    "query": q,
-                    "fuzziness": "AUTO",
-                    "fields": [
-                        "description^2",
-                        "readme",
-                        "outputs",
-                        "repo^2",
-                        "owner^2",
-                    ],
+async fn count_flakes_after(after_id: i32, pool: &Pool<Postgres>) -> Result<i64, AppError> {
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM release WHERE id > $1")
+        .bind(after_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to count flakes in database")?;
+
+    Ok(count)
+}
+
+async fn count_flakes(include_archived: bool, pool: &Pool<Postgres>) -> Result<i64, AppError> {
+    let count = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            WHERE $1 OR NOT githubrepo.archived",
+    )
+    .bind(include_archived)
+    .fetch_one(pool)
+    .await
+    .context("Failed to count flakes in database")?;
+
+    Ok(count)
+}
+
+// Best match first, the newest of equally good matches first. Scores are looked up by id as the
+// database returns the releases in no particular order.
+fn sort_by_score(releases: &mut [FlakeReleaseCompact], scores: &HashMap<i32, f64>) {
+    let score = |release: &FlakeReleaseCompact| scores.get(&release.id).copied().unwrap_or(0.0);
+    releases.sort_by(|a, b| {
+        score(b)
+            .total_cmp(&score(a))
+            .then_with(|| b.created_at.cmp(&a.created_at))
+            .then_with(|| b.id.cmp(&a.id))
+    });
+}
+
+#[derive(Clone)]
+pub struct SearchResults {
+    // A map of release ids to search scores
+    hits: HashMap<i32, f64>,
+    // Why each hit got its score, only requested in explain mode
+    explanations: HashMap<i32, Value>,
+    total: i64,
+    total_relation: TotalRelation,
+    source: ResultSource,
+    facets: Option<Facets>,
+    // Some shards failed, the hits of the others are still returned
+    partial: bool,
+    pit_id: Option<String>,
+    // The body of the search request, with `from` and `size` which are sent in its URL
+    debug_query: Option<Value>,
+}
+
+// A response OpenSearch shouldn't have sent fails like OpenSearch being unavailable rather than
+// like a bug, so that clients retry and searches fall back to the database
+pub(crate) fn malformed_search_response(err: impl std::fmt::Display) -> AppError {
+    tracing::error!("Malformed OpenSearch response: {err}");
+    AppError::search_unavailable()
+}
+
+// The buckets of a terms aggregation, missing ones count as empty
+// Most frequent first and then by value, rather than relying on the order of the buckets, so
+// that the same results always serialize the same
+pub(crate) fn facet_counts(aggregation: &Value) -> Vec<FacetCount> {
+    let mut counts: Vec<FacetCount> = aggregation["buckets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|bucket| {
+            Some(FacetCount {
+                value: bucket["key"].as_str()?.to_string(),
+                count: bucket["doc_count"].as_i64()?,
+            })
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts
+}
+
+// Scores are only compared to each other, the digits beyond the first few are noise
+fn round_score(score: f64, decimals: u32) -> f64 {
+    let factor = 10_f64.powi(decimals as i32);
+    (score * factor).round() / factor
+}
+
+/// Parses a comma separated list of fields to search, like `description,repo,owner`, with their
+/// default boosts. The fields have to be among `SEARCH_FIELDS`.
+pub fn parse_search_fields(value: &str) -> Option<Vec<(&'static str, f64)>> {
+    let fields: Vec<_> = value
+        .split(',')
+        .map(|name| {
+            SEARCH_FIELDS
+                .into_iter()
+                .find(|(field, _)| *field == name.trim())
+        })
+        .collect::<Option<_>>()?;
+    (!fields.is_empty()).then_some(fields)
+}
+
+/// OpenSearch accepts a number of terms or a percentage of them, negative to count the
+/// terms which may be missing instead.
+pub fn is_valid_minimum_should_match(value: &str) -> bool {
+    let (count, max) = match value.strip_suffix('%') {
+        Some(percentage) => (percentage, 100),
+        None => (value, i64::MAX),
+    };
+    let count = count.strip_prefix('-').unwrap_or(count);
+    !count.starts_with('+') && count.parse::<i64>().is_ok_and(|count| count <= max)
+}
+
+// A degraded search over owner, repo and description for when OpenSearch is down,
+// ranked by trigram similarity to the query
+async fn search_flakes_pg(
+    options: &SearchOptions,
+    pool: &Pool<Postgres>,
+) -> Result<SearchResults, AppError> {
+    let pattern = options.query.as_ref().map(|q| {
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    });
+    let rows = sqlx::query(
+        "WITH matches AS ( \
+            SELECT release.id AS id, \
+                release.repo_id AS repo_id, \
+                COALESCE(GREATEST( \
+                    similarity(githubowner.name, $1), \
+                    similarity(githubrepo.name, $1), \
+                    similarity(release.description, $1) \
+                ), 0)::float8 AS score \
+                FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                WHERE ($2::text IS NULL \
+                    OR githubowner.name ILIKE $2 \
+                    OR githubrepo.name ILIKE $2 \
+                    OR release.description ILIKE $2) \
+                AND ($3::text IS NULL OR release.outputs ? $3) \
+                AND ($7 OR NOT githubrepo.archived) \
+                AND ($8::text IS NULL OR $8 = ANY(release.systems)) \
+                AND ($9::text IS NULL OR $9 = ANY(release.tags)) \
+                AND ($10::text IS NULL OR githubowner.name = $10) \
+                AND ($11::text IS NULL OR githubrepo.name = $11) \
+        ), ranked AS ( \
+            SELECT *, ROW_NUMBER() OVER (PARTITION BY repo_id ORDER BY score DESC, id) AS repo_rank \
+                FROM matches \
+        ) \
+        SELECT id, score, COUNT(*) OVER () AS total \
+            FROM ranked \
+            WHERE NOT $6 OR repo_rank = 1 \
+            ORDER BY score DESC, id \
+            LIMIT $4 OFFSET $5",
+    )
+    .bind(&options.query)
+    .bind(pattern)
+    .bind(&options.provides)
+    .bind(options.size)
+    .bind(options.from)
+    .bind(options.group_by_repo)
+    .bind(options.include_archived)
+    .bind(&options.system)
+    .bind(&options.tag)
+    .bind(&options.owner)
+    .bind(&options.repo)
+    .fetch_all(pool)
+    .await
+    .context("Failed to search flakes in database")?;
+
+    let total = rows.first().map_or(0, |row| row.get("total"));
+    let hits = rows
+        .iter()
+        .map(|row| (row.get("id"), row.get("score")))
+        .collect();
+    Ok(SearchResults {
+        hits,
+        explanations: HashMap::new(),
+        total,
+        total_relation: TotalRelation::Eq,
+        source: ResultSource::Database,
+        facets: None,
+        partial: false,
+        pit_id: None,
+        debug_query: None,
+    })
+}
+
+// Explanations are only wanted fresh, so explain mode skips the cache. So do searches of a
+// point in time, which would otherwise be answered from a newer snapshot.
+async fn cached_search_flakes(
+    state: &AppState,
+    options: &SearchOptions,
+) -> Result<SearchResults, AppError> {
+    if options.debugging() || options.pit.is_some() {
+        return limited_search_flakes(state, options).await;
+    }
+
+    let key = options.cache_key();
+    if let Some(results) = state.search_cache.get(&key).await {
+        return Ok(results);
+    }
+    let results = limited_search_flakes(state, options).await?;
+    // Partial results would stick around after the shards recovered
+    if !results.partial {
+        state.search_cache.insert(key, results.clone()).await;
+    }
+    Ok(results)
+}
+
+// How long a search waits for one of `AppState::search_permits` before giving up
+const SEARCH_QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Searches OpenSearch once a permit is free. Searches still waiting after
+// `SEARCH_QUEUE_TIMEOUT` fail as if OpenSearch was down, so a spike of traffic is shed
+// instead of piling up on the cluster. While the breaker is open they fail right away.
+async fn limited_search_flakes(
+    state: &AppState,
+    options: &SearchOptions,
+) -> Result<SearchResults, AppError> {
+    if !state.search_breaker.allow() {
+        return Err(AppError::search_unavailable());
+    }
+    let permit = tokio::time::timeout(SEARCH_QUEUE_TIMEOUT, state.search_permits.acquire()).await;
+    let Ok(Ok(_permit)) = permit else {
+        tracing::warn!("Too many concurrent searches, rejecting search");
+        return Err(AppError::search_unavailable());
+    };
+
+    let search = search_flakes(&state.opensearch, options);
+    let results = metrics::time(Backend::OpenSearch, "search_flakes", search).await;
+    // Only OpenSearch being unavailable counts, not requests it rejects
+    match results {
+        Ok(_) => state.search_breaker.record_success(),
+        Err(AppError::Upstream(_)) => state.search_breaker.record_failure(),
+        Err(_) => {}
+    }
+    results
+}
+
+// Characters with a meaning in OpenSearch query strings
+const QUERY_OPERATORS: &[char] = &[
+    '+', '-', '=', '&', '|', '>', '<', '!', '(', ')', '{', '}', '[', ']', '^', '"', '~', '*', '?',
+    ':', '\\', '/',
+];
+
+// `multi_match` doesn't parse operators, but they're still replaced with spaces, as are control
+// characters, so that no query can depend on how OpenSearch treats them. The analyzers split
+// words on them anyway.
+fn sanitize_query(query: &str) -> String {
+    query
+        .split(|c: char| QUERY_OPERATORS.contains(&c) || c.is_control())
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Segments of the query in double quotes are phrases, whose words have to follow each other in
+// a field, and the rest are terms. A quote without a closing one is left out like the other
+// operators. Both are sanitized.
+fn split_phrases(query: &str) -> (Vec<String>, String) {
+    let segments: Vec<&str> = query.split('"').collect();
+    let mut phrases = Vec::new();
+    let mut terms = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        if index % 2 == 1 && index + 1 < segments.len() {
+            phrases.push(sanitize_query(segment));
+        } else {
+            terms.push(*segment);
+        }
+    }
+    phrases.retain(|phrase| !phrase.is_empty());
+    (phrases, sanitize_query(&terms.join(" ")))
+}
+
+async fn search_flakes(
+    opensearch: &OpenSearch,
+    options: &SearchOptions,
+) -> Result<SearchResults, AppError> {
+    let must = match options.query.as_deref().map(split_phrases) {
+        // Nothing but operators was searched for, which matches nothing
+        Some((phrases, terms)) if phrases.is_empty() && terms.is_empty() => {
+            json!({ "match_none": {} })
+        }
+        Some((phrases, terms)) => {
+            let fields: Vec<String> = options
+                .fields
+                .iter()
+                .map(|(field, boost)| format!("{field}^{boost}"))
+                .collect();
+            let mut multi_match = json!({ "query": terms, "fields": fields });
+            if !options.exact {
+                multi_match["fuzziness"] = json!("AUTO");
+            }
+            if let Some(ref minimum_should_match) = options.minimum_should_match {
+                multi_match["minimum_should_match"] = json!(minimum_should_match);
+            }
+            let terms = (!terms.is_empty()).then(|| json!({ "multi_match": multi_match }));
+            // Each phrase has to match in one of the fields, without fuzziness
+            let mut must: Vec<Value> = phrases
+                .iter()
+                .map(|phrase| {
+                    json!({
+                        "multi_match": { "query": phrase, "type": "phrase", "fields": fields }
+                    })
+                })
+                .collect();
+            match terms {
+                Some(terms) if must.is_empty() => terms,
+                Some(terms) => {
+                    must.push(terms);
+                    json!(must)
                 }
+                None => json!(must),
             }
-        }))
+        }
+        None => json!({ "match_all": {} }),
+    };
+    let mut filter: Vec<Value> = Vec::new();
+    if let Some(ref output) = options.provides {
+        filter.push(json!({ "terms": { "provides": [output] } }));
+    }
+    if let Some(ref license) = options.license {
+        filter.push(json!({ "term": { "license": license } }));
+    }
+    if let Some(ref system) = options.system {
+        filter.push(json!({ "term": { "systems": system } }));
+    }
+    if let Some(ref tag) = options.tag {
+        filter.push(json!({ "term": { "tags": tag } }));
+    }
+    if let Some(ref owner) = options.owner {
+        filter.push(json!({ "term": { "owner.keyword": owner } }));
+    }
+    if let (Some(owner), Some(repo)) = (&options.owner, &options.repo) {
+        filter.push(json!({ "term": { "full_name": format!("{owner}/{repo}") } }));
+    }
+    // Documents without the field belong to repos which were never archived
+    let must_not: Vec<Value> = if options.include_archived {
+        Vec::new()
+    } else {
+        vec![json!({ "term": { "archived": true } })]
+    };
+
+    let mut query = json!({
+        "bool": {
+            "must": must,
+            "filter": filter,
+            "must_not": must_not,
+        }
+    });
+    if let Some(ref freshness) = options.freshness {
+        query = freshness.function_score(query);
+    }
+
+    let mut body = json!({
+        "track_total_hits": MAX_COUNTED_HITS,
+        "query": query,
+    });
+    body["aggs"] = json!({
+        "owners": { "terms": { "field": "owner.keyword", "size": FACET_SIZE } },
+        "outputs": { "terms": { "field": "provides", "size": FACET_SIZE } },
+    });
+    if options.group_by_repo {
+        // Documents indexed before `full_name` existed need a reindex to be grouped
+        body["collapse"] = json!({ "field": "full_name" });
+        body["aggs"]["repos"] = json!({ "cardinality": { "field": "full_name" } });
+    }
+
+    // Searches of a point in time name it instead of the index
+    let pit_id = match options.pit {
+        None => None,
+        Some(Pit::New) => Some(create_pit(opensearch).await?),
+        Some(Pit::Existing(ref pit_id)) => Some(pit_id.clone()),
+    };
+    let parts = match pit_id {
+        Some(ref pit_id) => {
+            body["pit"] = json!({ "id": pit_id, "keep_alive": PIT_KEEP_ALIVE });
+            SearchParts::None
+        }
+        None => SearchParts::Index(&["flakes"]),
+    };
+    // Only the body, the URL would give away the cluster's address
+    let debug_query = options.debug_query.then(|| {
+        let mut debug_query = body.clone();
+        debug_query["from"] = json!(options.from);
+        debug_query["size"] = json!(options.size);
+        debug_query
+    });
+
+    let response = opensearch
+        .search(parts)
+        .from(options.from)
+        .size(options.size)
+        .explain(options.explain)
+        .body(body)
         .send()
         .await
-        .context("Failed to send opensearch request")?
+        .map_err(|err| {
+            tracing::error!("Failed to send opensearch request: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    let status = response.status_code();
+    if matches!(options.pit, Some(Pit::Existing(_))) && matches!(status.as_u16(), 400 | 404) {
+        return Err(AppError::BadRequest(
+            "pit is unknown or expired, start over with pit=new".to_string(),
+        ));
+    }
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(%status, body, "OpenSearch returned an error");
+        return Err(AppError::search_unavailable());
+    }
+
+    let res = response
         .json::<Value>()
         .await
-        .context("Failed to decode opensearch response as json")?;
+        .map_err(malformed_search_response)?;
+
+    let failed_shards = res["_shards"]["failed"].as_i64().unwrap_or(0);
+    if failed_shards > 0 {
+        tracing::warn!(
+            failed_shards,
+            failures = %res["_shards"]["failures"],
+            "OpenSearch returned partial results"
+        );
+    }
 
-    // TODO: Remove this unwrap, use fold or map to create the HashMap
     let mut hits: HashMap<i32, f64> = HashMap::new();
+    let mut explanations: HashMap<i32, Value> = HashMap::new();
 
     let hit_res = res["hits"]["hits"]
         .as_array()
-        .context("failed to extract hits from open search response")?;
+        .ok_or_else(|| malformed_search_response("no hits"))?;
 
     for hit in hit_res {
-        let id = hit["_id"]
-            .as_str()
-            .context("failed to read id as string from open search hit")?
-            .parse()
-            .context("failed to parse id from open search hit")?;
+        // Documents that aren't releases can end up in the index, they are left out rather
+        // than failing the whole search
+        let Some(id) = hit["_id"].as_str().and_then(|id| id.parse().ok()) else {
+            tracing::warn!(id = %hit["_id"], "Skipping search hit without a release id");
+            continue;
+        };
         let score = hit["_score"]
             .as_f64()
-            .context("failed to parse score from open search hit")?;
+            .ok_or_else(|| malformed_search_response("a hit without a score"))?;
 
         hits.insert(id, score);
+        if options.explain {
+            explanations.insert(id, hit["_explanation"].clone());
+        }
+    }
+
+    // Collapsing doesn't change the hit count, so grouped searches count the repos instead. That
+    // count is an estimate, but it isn't capped.
+    let total = if options.group_by_repo {
+        res["aggregations"]["repos"]["value"].as_i64()
+    } else {
+        res["hits"]["total"]["value"].as_i64()
+    }
+    .ok_or_else(|| malformed_search_response("no total hits"))?;
+    let total_relation = if !options.group_by_repo && res["hits"]["total"]["relation"] == "gte" {
+        TotalRelation::Gte
+    } else {
+        TotalRelation::Eq
+    };
+
+    let facets = Facets {
+        owners: facet_counts(&res["aggregations"]["owners"]),
+        outputs: facet_counts(&res["aggregations"]["outputs"]),
+    };
+
+    Ok(SearchResults {
+        hits,
+        explanations,
+        total,
+        total_relation,
+        source: ResultSource::Search,
+        facets: Some(facets),
+        partial: failed_shards > 0,
+        // OpenSearch may hand out a new id for the same point in time
+        pit_id: pit_id.map(|pit_id| res["pit_id"].as_str().map_or(pit_id, str::to_string)),
+        debug_query,
+    })
+}
+
+// Opens a point in time of the index, which is kept for `PIT_KEEP_ALIVE` after each search
+async fn create_pit(opensearch: &OpenSearch) -> Result<String, AppError> {
+    let response = opensearch
+        .create_pit(CreatePitParts::Index(&["flakes"]))
+        .keep_alive(PIT_KEEP_ALIVE)
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to send opensearch request: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    let status = response.status_code();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(%status, body, "OpenSearch failed to create a point in time");
+        return Err(AppError::search_unavailable());
+    }
+
+    let res = response
+        .json::<Value>()
+        .await
+        .map_err(malformed_search_response)?;
+    res["pit_id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| malformed_search_response("no pit id"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_cadence() {
+        let at = |date: &str| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        let now = at("2024-07-01");
+        assert_eq!(release_cadence(&[], now), None);
+
+        let created_at = [
+            at("2024-06-01"),
+            at("2022-01-01"),
+            at("2023-11-01"),
+            at("2024-01-31"),
+        ];
+        assert_eq!(
+            release_cadence(&created_at, now),
+            Some(ReleaseCadence {
+                releases: 4,
+                releases_last_year: 3,
+                releases_per_month: 0.25,
+                // 882 days from the first to the last release
+                average_gap_days: Some(294.0),
+                days_since_last_release: 30,
+            })
+        );
+
+        assert_eq!(
+            release_cadence(&[at("2024-07-02")], now),
+            Some(ReleaseCadence {
+                releases: 1,
+                releases_last_year: 1,
+                releases_per_month: 1.0 / 12.0,
+                average_gap_days: None,
+                days_since_last_release: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_github_flake_ref() {
+        let parsed = |owner: &str, repo: &str, reference: Option<&str>| {
+            Ok(GithubFlakeRef {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                reference: reference.map(str::to_string),
+            })
+        };
+        assert_eq!(
+            parse_github_flake_ref("github:NixOS/nixpkgs"),
+            parsed("nixos", "nixpkgs", None)
+        );
+        assert_eq!(
+            parse_github_flake_ref("github:nix-community/home-manager/23.05"),
+            parsed("nix-community", "home-manager", Some("23.05"))
+        );
+        assert_eq!(
+            parse_github_flake_ref("github:numtide/flake-utils?rev=abc1234&dir=lib"),
+            parsed("numtide", "flake-utils", Some("abc1234"))
+        );
+        for malformed in [
+            "",
+            "github:",
+            "github:nixos",
+            "github:nixos/",
+            "github:/nixpkgs",
+            "github:nixos/nixpkgs/",
+            "github:nixos/nixpkgs/a/b",
+            "github:nixos/nix pkgs",
+            "gitlab:nixos/nixpkgs",
+            "https://github.com/nixos/nixpkgs",
+            "github:nixos/nixpkgs/23.05?ref=23.11",
+            "github:nixos/nixpkgs?narHash=sha256-abc",
+        ] {
+            assert!(parse_github_flake_ref(malformed).is_err(), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn test_sanitize_query() {
+        assert_eq!(sanitize_query("home-manager"), "home manager");
+        assert_eq!(sanitize_query("\"nix flakes\""), "nix flakes");
+        assert_eq!(
+            sanitize_query("owner:nixos AND (repo:[a TO z])"),
+            "owner nixos AND repo a TO z"
+        );
+        assert_eq!(
+            sanitize_query("nix~2 || rust^3 -go +zig*"),
+            "nix 2 rust 3 go zig"
+        );
+        assert_eq!(sanitize_query("path/to\\file\u{0}"), "path to file");
+        assert_eq!(sanitize_query("émacs  überlay"), "émacs überlay");
+        assert_eq!(sanitize_query("()[]{}\"\""), "");
+    }
+
+    #[test]
+    fn test_split_phrases() {
+        assert_eq!(
+            split_phrases("nix flake"),
+            (vec![], "nix flake".to_string())
+        );
+        assert_eq!(
+            split_phrases("\"nix flake\" rust"),
+            (vec!["nix flake".to_string()], "rust".to_string())
+        );
+        assert_eq!(
+            split_phrases("home \"dot-files\" manager \"nix  darwin\""),
+            (
+                vec!["dot files".to_string(), "nix darwin".to_string()],
+                "home manager".to_string()
+            )
+        );
+        // Unclosed and empty quotes
+        assert_eq!(
+            split_phrases("\"nix flake"),
+            (vec![], "nix flake".to_string())
+        );
+        assert_eq!(
+            split_phrases("\"\" rust \"a\" \"b"),
+            (vec!["a".to_string()], "rust b".to_string())
+        );
+        assert_eq!(split_phrases("\"()\""), (vec![], String::new()));
+    }
+
+    #[test]
+    fn test_facet_counts() {
+        let aggregation = json!({
+            "buckets": [
+                { "key": "packages", "doc_count": 2 },
+                { "key": "overlays", "doc_count": 5 },
+                { "key": "apps", "doc_count": 2 },
+            ]
+        });
+        let counts: Vec<(String, i64)> = facet_counts(&aggregation)
+            .into_iter()
+            .map(|count| (count.value, count.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("overlays".to_string(), 5),
+                ("apps".to_string(), 2),
+                ("packages".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flake_ref() {
+        assert_eq!(
+            flake_ref("nixos", "nixpkgs", "f8f9f95"),
+            "github:nixos/nixpkgs/f8f9f95"
+        );
+        assert_eq!(flake_ref("nixos", "nixpkgs", ""), "github:nixos/nixpkgs");
+    }
+
+    #[test]
+    fn test_parse_boost() {
+        let fields = parse_boost("readme:3, description:0.5", &SEARCH_FIELDS)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            fields,
+            [
+                ("description", 0.5),
+                ("readme", 3.0),
+                ("outputs", 1.0),
+                ("repo", 2.0),
+                ("owner", 2.0),
+            ]
+        );
+
+        for boost in [
+            "",
+            "readme",
+            "readme:",
+            "readme:0",
+            "readme:11",
+            "readme:NaN",
+            "commit:2",
+        ] {
+            assert!(parse_boost(boost, &SEARCH_FIELDS).is_err(), "{boost}");
+        }
+        // Fields which aren't searched can't be boosted either
+        assert!(parse_boost("readme:3", &SEARCH_FIELDS[..1]).is_err());
+    }
+
+    #[test]
+    fn test_round_score() {
+        assert_eq!(round_score(1.234_567_8, 3), 1.235);
+        assert_eq!(round_score(1.234_567_8, 0), 1.0);
+        assert_eq!(round_score(0.1 + 0.2, 2), 0.3);
+    }
+
+    #[test]
+    fn test_parse_search_fields() {
+        assert_eq!(
+            parse_search_fields("description, repo").unwrap(),
+            [("description", 2.0), ("repo", 2.0)]
+        );
+        assert_eq!(
+            parse_search_fields("description,readme,outputs,repo,owner").unwrap(),
+            SEARCH_FIELDS
+        );
+
+        assert!(parse_search_fields("").is_none());
+        assert!(parse_search_fields("description,").is_none());
+        assert!(parse_search_fields("commit").is_none());
+    }
+
+    #[test]
+    fn test_version_scheme_detect() {
+        assert_eq!(VersionScheme::detect("1.2.3"), VersionScheme::Semver);
+        assert_eq!(VersionScheme::detect("v23.05"), VersionScheme::Semver);
+        assert_eq!(VersionScheme::detect("2024-01-15"), VersionScheme::Date);
+        assert_eq!(VersionScheme::detect("2024-13-01"), VersionScheme::Tag);
+        assert_eq!(VersionScheme::detect("nightly"), VersionScheme::Tag);
+    }
+
+    #[test]
+    fn test_freshness_function_score() {
+        let query = json!({ "match_all": {} });
+        let decay = FreshnessDecay::default().function_score(query.clone());
+        assert_eq!(decay["function_score"]["query"], query);
+        assert_eq!(decay["function_score"]["boost_mode"], "multiply");
+        assert_eq!(
+            decay["function_score"]["functions"][0]["gauss"]["created_at"],
+            json!({ "origin": "now", "offset": "30d", "scale": "180d", "decay": 0.5 })
+        );
+    }
+
+    #[test]
+    fn test_parse_time_value() {
+        assert_eq!(parse_time_value("30d"), Some(30));
+        assert_eq!(parse_time_value("0h"), Some(0));
+        assert_eq!(parse_time_value("500ms"), Some(500));
+
+        assert_eq!(parse_time_value("30"), None);
+        assert_eq!(parse_time_value("d"), None);
+        assert_eq!(parse_time_value("1.5d"), None);
+        assert_eq!(parse_time_value("-1d"), None);
+        assert_eq!(parse_time_value("2y"), None);
     }
 
-    Ok(hits)
+    #[test]
+    fn test_is_valid_minimum_should_match() {
+        assert!(is_valid_minimum_should_match("2"));
+        assert!(is_valid_minimum_should_match("-1"));
+        assert!(is_valid_minimum_should_match("75%"));
+        assert!(is_valid_minimum_should_match("-25%"));
+
+        assert!(!is_valid_minimum_should_match(""));
+        assert!(!is_valid_minimum_should_match("%"));
+        assert!(!is_valid_minimum_should_match("101%"));
+        assert!(!is_valid_minimum_should_match("+2"));
+        assert!(!is_valid_minimum_should_match("0.5"));
+        assert!(!is_valid_minimum_should_match("3<90%"));
+    }
 }
@@ -0,0 +1,136 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::{sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+use crate::common::AppState;
+
+// For OpenSearch to answer a ping, the database has `AppState::db_timeout`. Short enough for a
+// hung cluster not to hang the probe of a load balancer as well.
+const SEARCH_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    database: bool,
+    search: bool,
+}
+
+/// Status of each dependency, `ok` when it answered and otherwise `timeout` or `error`.
+#[derive(serde::Serialize, ToSchema)]
+pub struct HealthResponse {
+    #[schema(example = "ok")]
+    database: &'static str,
+    #[schema(example = "ok")]
+    opensearch: &'static str,
+}
+
+// Why a dependency didn't answer, the details are logged
+#[derive(Clone, Copy, PartialEq)]
+enum Unhealthy {
+    Timeout,
+    Error,
+}
+
+fn status(check: Result<(), Unhealthy>) -> &'static str {
+    match check {
+        Ok(()) => "ok",
+        Err(Unhealthy::Timeout) => "timeout",
+        Err(Unhealthy::Error) => "error",
+    }
+}
+
+// Liveness probe, the process is up as soon as it answers. It doesn't check anything else, so a
+// flaky dependency doesn't get the process restarted.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses((status = 200, description = "The process is serving requests"))
+)]
+pub async fn get_live() -> StatusCode {
+    StatusCode::OK
+}
+
+// Readiness probe. Only the database is required, searches fall back to it while OpenSearch is
+// down, so an OpenSearch outage is reported without taking every instance out of service.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, body = ReadinessResponse),
+        (status = 503, body = ReadinessResponse, description = "The database is unreachable"),
+    )
+)]
+pub async fn get_ready(State(state): State<Arc<AppState>>) -> Response {
+    let (database, search) = tokio::join!(check_database(&state), check_search(&state));
+    let (database, search) = (database.is_ok(), search.is_ok());
+    let status = if database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(ReadinessResponse { database, search })).into_response()
+}
+
+// Health of every dependency for uptime monitoring, unlike the readiness probe also failing
+// while OpenSearch is down
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    responses(
+        (status = 200, body = HealthResponse),
+        (status = 503, body = HealthResponse, description = "The database or OpenSearch is unreachable"),
+    )
+)]
+pub async fn get_health(State(state): State<Arc<AppState>>) -> Response {
+    let (database, search) = tokio::join!(check_database(&state), check_search(&state));
+    let status_code = if database.is_ok() && search.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let health = HealthResponse {
+        database: status(database),
+        opensearch: status(search),
+    };
+    (status_code, Json(health)).into_response()
+}
+
+async fn check_database(state: &AppState) -> Result<(), Unhealthy> {
+    let ping = sqlx::query("SELECT 1").execute(&state.pool);
+    match tokio::time::timeout(state.db_timeout, ping).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => {
+            tracing::warn!("Database unreachable: {err}");
+            Err(Unhealthy::Error)
+        }
+        Err(_) => {
+            tracing::warn!("Database unreachable: ping timed out");
+            Err(Unhealthy::Timeout)
+        }
+    }
+}
+
+async fn check_search(state: &AppState) -> Result<(), Unhealthy> {
+    let ping = state.opensearch.ping().send();
+    match tokio::time::timeout(SEARCH_PING_TIMEOUT, ping).await {
+        Ok(Ok(response)) if response.status_code().is_success() => Ok(()),
+        Ok(Ok(response)) => {
+            tracing::warn!(status = %response.status_code(), "OpenSearch unreachable");
+            Err(Unhealthy::Error)
+        }
+        Ok(Err(err)) => {
+            tracing::warn!("OpenSearch unreachable: {err}");
+            Err(Unhealthy::Error)
+        }
+        Err(_) => {
+            tracing::warn!("OpenSearch unreachable: ping timed out");
+            Err(Unhealthy::Timeout)
+        }
+    }
+}
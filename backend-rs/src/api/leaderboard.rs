@@ -0,0 +1,107 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use sqlx::{FromRow, Pool, Postgres};
+use std::{sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+use crate::api::flake::int_param;
+use crate::api::MAX_LIST_LIMIT;
+use crate::common::{with_db_timeout, AppError, AppState, Paginated, QueryParams};
+
+const LEADERBOARD_LIMIT: i64 = 10;
+pub const LEADERBOARD_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+// Pages kept, one for each ranking, limit and offset requested
+pub const LEADERBOARD_CACHE_CAPACITY: usize = 100;
+
+#[derive(Clone, FromRow, serde::Serialize, ToSchema)]
+pub struct LeaderboardOwner {
+    owner: String,
+    releases: i64,
+    repos: i64,
+    #[serde(skip_serializing)]
+    total: i64,
+}
+
+// Owners ranked by how many releases or how many distinct repos they published
+#[utoipa::path(
+    get,
+    path = "/api/leaderboard",
+    params(
+        ("by" = Option<String>, Query, description = "`releases`, the default, or `repos`"),
+        ("limit" = Option<i64>, Query, description = "Number of owners"),
+        ("offset" = Option<i64>, Query, description = "Number of owners to skip"),
+    ),
+    responses(
+        (status = 200, body = PaginatedLeaderboardOwner),
+        (status = 400, description = "Invalid query parameters"),
+    )
+)]
+pub async fn get_leaderboard(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<LeaderboardOwner>>, AppError> {
+    let by_repos = match params.get("by").map(String::as_str) {
+        None | Some("releases") => false,
+        Some("repos") => true,
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "by must be releases or repos".to_string(),
+            ));
+        }
+    };
+    let limit = int_param(&params, "limit", 1)?
+        .map_or(LEADERBOARD_LIMIT, |limit| limit.min(MAX_LIST_LIMIT));
+    let offset = int_param(&params, "offset", 0)?.unwrap_or(0);
+
+    let key = format!("{by_repos}:{limit}:{offset}");
+    if let Some(leaderboard) = state.leaderboard.get(&key).await {
+        return Ok(Json(leaderboard));
+    }
+    let owners = with_db_timeout(
+        state.db_timeout,
+        "get_leaderboard_owners",
+        get_leaderboard_owners(by_repos, limit, offset, &state.pool),
+    )
+    .await?;
+    let total = owners.first().map_or(0, |owner| owner.total);
+
+    let leaderboard = Paginated {
+        items: owners,
+        total,
+        limit,
+        offset,
+    };
+    state.leaderboard.insert(key, leaderboard.clone()).await;
+    Ok(Json(leaderboard))
+}
+
+// Ties are ranked by the other count and then by name, so that pages are stable
+async fn get_leaderboard_owners(
+    by_repos: bool,
+    limit: i64,
+    offset: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<LeaderboardOwner>, AppError> {
+    let owners: Vec<LeaderboardOwner> = sqlx::query_as(
+        "SELECT githubowner.name AS owner, \
+            COUNT(release.id) AS releases, \
+            COUNT(DISTINCT githubrepo.id) AS repos, \
+            COUNT(*) OVER () AS total \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            GROUP BY githubowner.name \
+            ORDER BY CASE WHEN $1 THEN COUNT(DISTINCT githubrepo.id) ELSE COUNT(release.id) END DESC, \
+                CASE WHEN $1 THEN COUNT(release.id) ELSE COUNT(DISTINCT githubrepo.id) END DESC, \
+                owner \
+            LIMIT $2 OFFSET $3",
+    )
+    .bind(by_repos)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch leaderboard from database")?;
+
+    Ok(owners)
+}
@@ -1,5 +1,42 @@
+//! The `/api` routes. Their JSON responses follow the same conventions for missing values:
+//!
+//! - Lists and maps are always there, empty rather than `null` when there's nothing in them, e.g.
+//!   the `outputs`, `systems` and `tags` of a release published without any.
+//! - Unknown scalars, like the hash of a missing readme, are `null`. Release descriptions are
+//!   `""` instead, as they always have been.
+//! - Fields only some requests get, like scores in explain mode or the facets of results from
+//!   the search index, are left out of the others rather than `null`.
+
+mod admin;
+mod catalog;
+mod diff;
+mod feed;
 mod flake;
+mod health;
+mod leaderboard;
+mod openapi;
+mod outputs;
 mod publish;
+mod recent;
+mod stats;
+mod tags;
+mod trending;
+mod version;
+mod webhooks;
 
+pub use admin::*;
+pub use catalog::*;
+pub use diff::*;
+pub use feed::*;
 pub use flake::*;
+pub use health::*;
+pub use leaderboard::*;
+pub use openapi::*;
+pub use outputs::*;
 pub use publish::*;
+pub use recent::*;
+pub use stats::*;
+pub use tags::*;
+pub use trending::*;
+pub use version::*;
+pub use webhooks::*;
@@ -0,0 +1,128 @@
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::api::{
+    admin, catalog, diff, feed, flake, health, leaderboard, publish, recent, stats, tags, trending,
+    version, webhooks, AppliedFilters, BackfillDescriptionsResponse, BatchRequest, CatalogRepo,
+    DeleteDocumentResponse, DeleteReleaseResponse, ExportedRelease, FacetCount, Facets,
+    FlakeRelease, FlakeReleaseCompact, GetFlakeResponse, HealthResponse, LeaderboardOwner,
+    MergeOwnersRequest, MergeOwnersResponse, Output, Outputs, OutputsDiff, Publish,
+    PublishBatchResult, PublishFailure, ReadinessResponse, ReadmeType, RecentRepo, ReleaseCadence,
+    ReleasesAfterResponse, RepoMeta, RepoOwner, RepoResponse, ResultSource, SetArchivedRequest,
+    SetArchivedResponse, SetRecommendedRequest, SetRecommendedResponse, ShieldsResponse,
+    TimelineMonth, TotalRelation, TrendingRepo, VersionResponse, VersionScheme, VersionStatus,
+    Webhook, WebhookRequest,
+};
+use crate::common::{
+    PaginatedCatalogRepo, PaginatedFlakeRelease, PaginatedFlakeReleaseCompact,
+    PaginatedLeaderboardOwner, PaginatedPublishFailure, PaginatedRecentRepo, PaginatedRepoOwner,
+    PaginatedTrendingRepo,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Flakestry API"),
+    paths(
+        admin::delete_index_document,
+        admin::post_backfill_descriptions,
+        admin::get_index_document,
+        admin::get_publish_failures,
+        admin::delete_release,
+        admin::post_merge_owners,
+        admin::put_repo_archived,
+        admin::put_repo_recommended,
+        catalog::get_catalog,
+        diff::get_outputs_diff,
+        feed::get_releases_feed,
+        flake::get_cadence,
+        flake::get_commit_releases,
+        flake::get_export,
+        flake::get_flake,
+        flake::get_owner_search,
+        flake::get_release,
+        flake::get_recommended,
+        flake::get_random_release,
+        flake::get_repo_search,
+        flake::get_readme,
+        flake::get_repo_owners,
+        flake::get_shields,
+        flake::get_timeline,
+        flake::get_version_status,
+        flake::post_flakes_batch,
+        flake::get_releases_after,
+        flake::get_resolve,
+        flake::read_repo,
+        health::get_health,
+        health::get_live,
+        health::get_ready,
+        leaderboard::get_leaderboard,
+        publish::post_publish,
+        publish::post_publish_batch,
+        recent::get_recent_repos,
+        stats::get_output_stats,
+        tags::get_tags,
+        trending::get_trending,
+        version::get_version,
+        webhooks::post_webhook,
+    ),
+    components(schemas(
+        AppliedFilters,
+        BackfillDescriptionsResponse,
+        BatchRequest,
+        CatalogRepo,
+        DeleteDocumentResponse,
+        DeleteReleaseResponse,
+        ExportedRelease,
+        Facets,
+        FacetCount,
+        FlakeRelease,
+        FlakeReleaseCompact,
+        GetFlakeResponse,
+        HealthResponse,
+        LeaderboardOwner,
+        MergeOwnersRequest,
+        MergeOwnersResponse,
+        Output,
+        Outputs,
+        OutputsDiff,
+        PaginatedCatalogRepo,
+        PaginatedFlakeRelease,
+        PaginatedFlakeReleaseCompact,
+        PaginatedLeaderboardOwner,
+        PaginatedPublishFailure,
+        PaginatedRecentRepo,
+        PaginatedRepoOwner,
+        PaginatedTrendingRepo,
+        Publish,
+        PublishBatchResult,
+        PublishFailure,
+        ReadinessResponse,
+        ReleasesAfterResponse,
+        ReadmeType,
+        RecentRepo,
+        ReleaseCadence,
+        RepoMeta,
+        RepoOwner,
+        RepoResponse,
+        ResultSource,
+        SetArchivedRequest,
+        SetArchivedResponse,
+        SetRecommendedRequest,
+        SetRecommendedResponse,
+        ShieldsResponse,
+        TimelineMonth,
+        TotalRelation,
+        TrendingRepo,
+        VersionResponse,
+        VersionScheme,
+        VersionStatus,
+        Webhook,
+        WebhookRequest,
+    ))
+)]
+struct ApiDoc;
+
+/// OpenAPI 3 document describing the `/api` routes, e.g. to generate clients.
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,77 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+// Outputs of a category keyed by system and then by name, e.g. `packages.x86_64-linux.default`
+type PerSystem = BTreeMap<String, BTreeMap<String, Output>>;
+
+/// The outputs of a flake in the shape of `nix flake show --json`, validated on publish.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Outputs {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Output>>)]
+    pub apps: PerSystem,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Output>>)]
+    pub checks: PerSystem,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Output>>)]
+    pub dev_shells: PerSystem,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Output>>)]
+    pub legacy_packages: PerSystem,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Output>>)]
+    pub packages: PerSystem,
+    // A single formatter per system
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub formatter: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub darwin_modules: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub home_manager_modules: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nixos_configurations: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nixos_modules: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overlays: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Output>,
+    // Non-standard outputs such as `lib` or `hydraJobs` are kept as they are
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// A single output, e.g. a derivation or a NixOS module.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, ToSchema)]
+pub struct Output {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl Outputs {
+    /// The kinds of outputs there are, named like in flakes, e.g. `devShells`.
+    pub fn kinds(&self) -> Vec<String> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(outputs)) => outputs.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Drops the outputs of the kinds `keep` returns false for.
+    pub fn retain_kinds(&mut self, keep: impl Fn(&str) -> bool) {
+        let Ok(Value::Object(mut outputs)) = serde_json::to_value(&*self) else {
+            return;
+        };
+        outputs.retain(|kind, _| keep(kind));
+        if let Ok(outputs) = serde_json::from_value(Value::Object(outputs)) {
+            *self = outputs;
+        }
+    }
+}
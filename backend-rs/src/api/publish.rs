@@ -1,3 +1,1403 @@
-pub async fn post_publish() -> &'static str {
-    "Publish"
+use anyhow::Context;
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDateTime;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    sync::Arc,
+};
+use utoipa::ToSchema;
+
+use crate::api::{queue_deliveries, update_documents, Outputs};
+use crate::common::{with_db_timeout, AppError, AppState, QueryParams};
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct Publish {
+    owner: String,
+    repo: String,
+    version: String,
+    commit: String,
+    description: Option<String>,
+    readme: Option<String>,
+    // `markdown` when missing, normalized to the name of a `ReadmeType` before storing
+    readme_type: Option<String>,
+    outputs: Option<Outputs>,
+    // SPDX license identifier, only stored in the search index
+    license: Option<String>,
+    // Nix systems like `x86_64-linux` the outputs target, stored sorted and deduplicated
+    #[serde(default)]
+    systems: Vec<String>,
+    // Keywords like `cli` or `nixos`, lowercased and stored sorted and deduplicated
+    #[serde(default)]
+    tags: Vec<String>,
+    // Bytes of the closure of the flake's default package, for users to gauge its download
+    closure_size: Option<i64>,
+    // SRI hash of the flake's source, like the `narHash` of its flake.lock entries
+    nar_hash: Option<String>,
+}
+
+// Same as the limit axum puts on uncompressed bodies, so compressing doesn't allow for more
+const MAX_DECOMPRESSED_BODY_BYTES: u64 = 2 * 1024 * 1024;
+
+/// A JSON body which may be gzip compressed with `Content-Encoding: gzip`, e.g. by CI agents on
+/// slow links publishing large readmes. Other encodings are rejected with a 415, and bodies
+/// expanding beyond `MAX_DECOMPRESSED_BODY_BYTES` with a 413.
+pub struct PublishJson<T>(pub T);
+
+#[async_trait]
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for PublishJson<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let encoding = req
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap_or_default().trim().to_lowercase());
+        let req = match encoding.as_deref() {
+            None | Some("identity") => req,
+            Some("gzip") => {
+                let content_type = req.headers().get(header::CONTENT_TYPE).cloned();
+                let compressed = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                let mut json = Vec::new();
+                GzDecoder::new(&compressed[..])
+                    .take(MAX_DECOMPRESSED_BODY_BYTES + 1)
+                    .read_to_end(&mut json)
+                    .map_err(|_| {
+                        Rejection::new(StatusCode::BAD_REQUEST, "Body is not valid gzip")
+                            .into_response()
+                    })?;
+                if json.len() as u64 > MAX_DECOMPRESSED_BODY_BYTES {
+                    return Err(Rejection::new(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "Body must be at most {MAX_DECOMPRESSED_BODY_BYTES} bytes decompressed"
+                        ),
+                    )
+                    .into_response());
+                }
+
+                let mut decompressed = Request::new(Body::from(json));
+                if let Some(content_type) = content_type {
+                    decompressed
+                        .headers_mut()
+                        .insert(header::CONTENT_TYPE, content_type);
+                }
+                decompressed
+            }
+            Some(encoding) => {
+                return Err(Rejection::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Content-Encoding {encoding} is not supported, only gzip is"),
+                )
+                .into_response());
+            }
+        };
+
+        let Json(value) = Json::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(PublishJson(value))
+    }
+}
+
+// TODO: authenticate the publisher with the GitHub OIDC token like the Python backend does,
+// and record who it is in `release.published_by`
+#[utoipa::path(
+    post,
+    path = "/api/publish",
+    params(
+        ("dry_run" = Option<bool>, Query, description = "Only validate the release without storing it"),
+    ),
+    request_body = Publish,
+    responses(
+        (status = 200, description = "Dry run of a valid release, or with `REPUBLISH_POLICY=update` a published version updated in place"),
+        (status = 201, description = "Release published"),
+        (status = 204, description = "Release already published with the same content"),
+        (status = 400, description = "Invalid owner, repo, version, license, system or tags"),
+        (status = 403, description = "Owner not allowed to publish"),
+        (status = 409, description = "Version already published with different content unless `REPUBLISH_POLICY=update`, or with `VERIFY_COMMIT_ANCESTRY` a commit not descending from the latest release"),
+        (status = 413, description = "Body too large once decompressed"),
+        (status = 415, description = "Unsupported Content-Encoding, only gzip is"),
+        (status = 422, description = "Invalid commit, unknown readme type or rejected by GitHub"),
+        (status = 503, description = "Read-only mode"),
+    )
+)]
+pub async fn post_publish(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+    PublishJson(mut publish): PublishJson<Publish>,
+) -> Result<Response, AppError> {
+    // A dry run only validates the release, so it's fine during maintenance too
+    let dry_run = params.get("dry_run").is_some_and(|d| d == "true");
+    if !dry_run {
+        state.ensure_writable()?;
+    }
+
+    let version = match check_publish(&mut publish, &state).await? {
+        Ok(version) => version,
+        Err(rejection) => {
+            if !dry_run {
+                record_failure(&state, &publish, &rejection).await;
+            }
+            return Ok(rejection.into_response());
+        }
+    };
+
+    if dry_run {
+        let existing = with_db_timeout(
+            state.db_timeout,
+            "existing_release",
+            existing_release(&publish, &version, &state),
+        )
+        .await?;
+        if existing == Some(false) && state.republish == Republish::Reject {
+            return Ok((
+                StatusCode::CONFLICT,
+                Json(json!({ "detail": format!("Version {version} already exists") })),
+            )
+                .into_response());
+        }
+        return Ok((
+            StatusCode::OK,
+            Json(json!({
+                "owner": publish.owner,
+                "repo": publish.repo,
+                "version": version,
+                "commit": publish.commit,
+                // Publishing it for real would have nothing left to do
+                "unchanged": existing == Some(true),
+            })),
+        )
+            .into_response());
+    }
+
+    let created = with_db_timeout(
+        state.db_timeout,
+        "create_release",
+        create_release(&publish, &version, &state),
+    )
+    .await?;
+    let release_id = match created {
+        Created::Release(release_id) => release_id,
+        // A retried publish of the same release has nothing left to do
+        Created::Unchanged => return Ok(StatusCode::NO_CONTENT.into_response()),
+        Created::Updated(release_id, created_at) => {
+            index_release(&state, release_id, &publish, created_at).await;
+            return Ok((StatusCode::OK, Json(json!({}))).into_response());
+        }
+        Created::Conflict => {
+            let rejection = Rejection::conflict(&version);
+            record_failure(&state, &publish, &rejection).await;
+            return Ok(rejection.into_response());
+        }
+    };
+
+    announce_release(&state, release_id, &publish, &version).await;
+
+    Ok((StatusCode::CREATED, Json(json!({}))).into_response())
+}
+
+// Upper bound for the number of releases published by a single batch request
+const PUBLISH_BATCH_LIMIT: usize = 100;
+
+/// The outcome of one release of a batch, with the status and message publishing it alone
+/// would have gotten.
+#[derive(serde::Serialize, ToSchema)]
+pub struct PublishBatchResult {
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+// Publishes many releases at once, e.g. to backfill a migrated dataset. The valid ones are
+// stored in a single transaction, so a database failure publishes none of them, while invalid
+// or conflicting ones only fail their own result.
+#[utoipa::path(
+    post,
+    path = "/api/publish/batch",
+    request_body = Vec<Publish>,
+    responses(
+        (status = 200, body = Vec<PublishBatchResult>, description = "Results in the order of the releases"),
+        (status = 400, description = "Too many releases"),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 413, description = "Body too large once decompressed"),
+        (status = 415, description = "Unsupported Content-Encoding, only gzip is"),
+        (status = 503, description = "Read-only mode"),
+    )
+)]
+pub async fn post_publish_batch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PublishJson(batch): PublishJson<Vec<Publish>>,
+) -> Result<Json<Vec<PublishBatchResult>>, AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+    if batch.len() > PUBLISH_BATCH_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "at most {PUBLISH_BATCH_LIMIT} releases can be published at once"
+        )));
+    }
+
+    let mut checked = Vec::with_capacity(batch.len());
+    for mut publish in batch {
+        let version = check_publish(&mut publish, &state).await?;
+        checked.push((publish, version));
+    }
+
+    let created = with_db_timeout(
+        state.db_timeout,
+        "create_releases",
+        create_releases(&checked, &state),
+    )
+    .await?;
+
+    let mut results = Vec::with_capacity(checked.len());
+    for ((publish, version), created) in checked.iter().zip(created) {
+        let result = match (version, created) {
+            (Err(rejection), _) => {
+                record_failure(&state, publish, rejection).await;
+                PublishBatchResult {
+                    status: rejection.status.as_u16(),
+                    message: Some(rejection.message.clone()),
+                }
+            }
+            (Ok(version), Some(Created::Release(release_id))) => {
+                announce_release(&state, release_id, publish, version).await;
+                PublishBatchResult {
+                    status: StatusCode::CREATED.as_u16(),
+                    message: None,
+                }
+            }
+            (Ok(_), Some(Created::Updated(release_id, created_at))) => {
+                index_release(&state, release_id, publish, created_at).await;
+                PublishBatchResult {
+                    status: StatusCode::OK.as_u16(),
+                    message: None,
+                }
+            }
+            (Ok(_), Some(Created::Unchanged) | None) => PublishBatchResult {
+                status: StatusCode::NO_CONTENT.as_u16(),
+                message: None,
+            },
+            (Ok(version), Some(Created::Conflict)) => {
+                let rejection = Rejection::conflict(version);
+                record_failure(&state, publish, &rejection).await;
+                PublishBatchResult {
+                    status: rejection.status.as_u16(),
+                    message: Some(rejection.message),
+                }
+            }
+        };
+        results.push(result);
+    }
+
+    Ok(Json(results))
+}
+
+/// Why a release can't be published, answered with its status and a `detail` like other errors.
+pub(crate) struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn conflict(version: &str) -> Self {
+        Rejection::new(
+            StatusCode::CONFLICT,
+            format!("Version {version} already exists"),
+        )
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "detail": self.message }))).into_response()
+    }
+}
+
+// Rejected publishes are kept this many days for admins to look into
+const PUBLISH_FAILURE_RETENTION_DAYS: i32 = 30;
+
+// Records why a publish was rejected, pruning the failures older than the retention period. It's
+// only logged when that fails, the publisher still gets the rejection.
+async fn record_failure(state: &AppState, publish: &Publish, rejection: &Rejection) {
+    let insert = sqlx::query(
+        "WITH pruned AS ( \
+            DELETE FROM publish_failure \
+            WHERE created_at < (now() AT TIME ZONE 'utc') - make_interval(days => $7) \
+        ) \
+        INSERT INTO publish_failure (owner, repo, version, commit, status, reason, created_at) \
+            VALUES ($1, $2, $3, $4, $5, $6, now() AT TIME ZONE 'utc')",
+    )
+    .bind(&publish.owner)
+    .bind(&publish.repo)
+    .bind(&publish.version)
+    .bind(&publish.commit)
+    .bind(rejection.status.as_u16() as i16)
+    .bind(&rejection.message)
+    .bind(PUBLISH_FAILURE_RETENTION_DAYS)
+    .execute(&state.pool);
+
+    match tokio::time::timeout(state.db_timeout, insert).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => tracing::error!(
+            owner = publish.owner,
+            repo = publish.repo,
+            "Failed to record publish failure: {err}"
+        ),
+        Err(_) => tracing::error!(
+            owner = publish.owner,
+            repo = publish.repo,
+            "Timed out recording publish failure"
+        ),
+    }
+}
+
+// Normalizes the owner, repo and readme type of `publish` and checks that it can be published,
+// returning its version without the `v` prefix
+async fn check_publish(
+    publish: &mut Publish,
+    state: &AppState,
+) -> Result<Result<String, Rejection>, AppError> {
+    publish.owner = normalize_name(&publish.owner);
+    publish.repo = normalize_name(&publish.repo);
+    if publish.owner.is_empty() || publish.repo.is_empty() {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            "owner and repo must not be empty",
+        )));
+    }
+
+    if !is_allowed_owner(&state.allowed_publish_owners, &publish.owner) {
+        return Ok(Err(Rejection::new(
+            StatusCode::FORBIDDEN,
+            format!("{} is not allowed to publish", publish.owner),
+        )));
+    }
+
+    let Some(version) = parse_publish_version(&publish.version) else {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} doesn't match regex {VERSION_REGEX}", publish.version),
+        )));
+    };
+    let version = version.to_string();
+
+    if !is_valid_commit(&publish.commit) {
+        return Ok(Err(Rejection::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{} is not a valid commit SHA", publish.commit),
+        )));
+    }
+
+    if let Some(ref license) = publish.license {
+        if !is_valid_license(license) {
+            return Ok(Err(Rejection::new(
+                StatusCode::BAD_REQUEST,
+                format!("{license} is not a valid SPDX license id"),
+            )));
+        }
+    }
+
+    let readme_type = match publish.readme_type.as_deref() {
+        None => Some(ReadmeType::Markdown),
+        Some(name) => ReadmeType::parse(name),
+    };
+    let Some(readme_type) = readme_type else {
+        return Ok(Err(Rejection::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "{} is not a known readme type, expected markdown, rst or plain",
+                publish.readme_type.as_deref().unwrap_or_default()
+            ),
+        )));
+    };
+    publish.readme_type = Some(readme_type.as_str().to_string());
+
+    // Listings and search results show the description, so one is made up from the readme
+    if publish
+        .description
+        .as_deref()
+        .is_none_or(|description| description.trim().is_empty())
+    {
+        if let Some(description) = publish.readme.as_deref().and_then(description_from_readme) {
+            publish.description = Some(description);
+        }
+    }
+
+    if let Some(system) = publish
+        .systems
+        .iter()
+        .find(|system| !is_valid_system(system))
+    {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            format!("{system} is not a Nix system like x86_64-linux"),
+        )));
+    }
+    publish.systems.sort();
+    publish.systems.dedup();
+
+    for tag in publish.tags.iter_mut() {
+        *tag = tag.trim().to_lowercase();
+    }
+    publish.tags.sort();
+    publish.tags.dedup();
+    if let Some(tag) = publish.tags.iter().find(|tag| !is_valid_tag(tag)) {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            format!("{tag} is not a tag of letters, digits and -"),
+        )));
+    }
+    if publish.tags.len() > MAX_TAGS {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            format!("A release can have at most {MAX_TAGS} tags"),
+        )));
+    }
+
+    if publish.closure_size.is_some_and(|size| size < 0) {
+        return Ok(Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            "closure_size must not be negative",
+        )));
+    }
+    if let Some(ref nar_hash) = publish.nar_hash {
+        if !is_valid_nar_hash(nar_hash) {
+            return Ok(Err(Rejection::new(
+                StatusCode::BAD_REQUEST,
+                format!("{nar_hash} is not a sha256 SRI hash like the narHash in flake.lock"),
+            )));
+        }
+    }
+
+    if let Some(ref mut outputs) = publish.outputs {
+        let unknown: Vec<String> = outputs
+            .kinds()
+            .into_iter()
+            .filter(|kind| !state.output_kinds.contains(kind))
+            .collect();
+        if !unknown.is_empty() && state.strict_output_kinds {
+            return Ok(Err(Rejection::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Unknown output kinds {}, expected one of {}",
+                    unknown.join(", "),
+                    state.output_kinds.join(", ")
+                ),
+            )));
+        }
+        // Kept out of the index, where they'd show up as filters nobody looks for
+        if !unknown.is_empty() {
+            tracing::info!(
+                owner = publish.owner,
+                repo = publish.repo,
+                dropped = unknown.join(", "),
+                "Dropping unknown output kinds"
+            );
+            outputs.retain_kinds(|kind| !unknown.iter().any(|unknown| unknown == kind));
+        }
+    }
+
+    if let Some(ref github) = state.github {
+        let rejection = github
+            .verify(&publish.owner, &publish.repo, &publish.commit)
+            .await?;
+        if let Some(reason) = rejection {
+            return Ok(Err(Rejection::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                reason,
+            )));
+        }
+
+        // Republishing a version is left to the conflict check, since it's no regression
+        let latest = if state.verify_ancestry {
+            with_db_timeout(
+                state.db_timeout,
+                "get_latest_commit",
+                get_latest_commit(&publish.owner, &publish.repo, &version, &state.pool),
+            )
+            .await?
+        } else {
+            None
+        };
+        if let Some(latest) = latest {
+            let rejection = github
+                .verify_descendant(&publish.owner, &publish.repo, &latest, &publish.commit)
+                .await?;
+            if let Some(reason) = rejection {
+                return Ok(Err(Rejection::new(StatusCode::CONFLICT, reason)));
+            }
+        }
+    }
+
+    Ok(Ok(version))
+}
+
+// The commit of the release of the repo published last, `None` for a new repo or when `version`
+// is published already
+async fn get_latest_commit(
+    owner: &str,
+    repo: &str,
+    version: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<String>, AppError> {
+    let commit = sqlx::query_scalar(
+        "SELECT release.commit FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 \
+                AND NOT EXISTS ( \
+                    SELECT 1 FROM release AS published \
+                        WHERE published.repo_id = release.repo_id AND published.version = $3 \
+                ) \
+            ORDER BY release.created_at DESC, release.id DESC \
+            LIMIT 1",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(version)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch latest commit from database")?;
+
+    Ok(commit)
+}
+
+// Indexes a newly created release and notifies the webhooks of its repo
+async fn announce_release(state: &AppState, release_id: i32, publish: &Publish, version: &str) {
+    // Close enough to the stored creation time
+    let created_at = chrono::Utc::now().naive_utc();
+    index_release(state, release_id, publish, created_at).await;
+    let payload = json!({
+        "event": "release",
+        "owner": publish.owner,
+        "repo": publish.repo,
+        "version": version,
+        "commit": publish.commit,
+        "description": publish.description,
+        "url": format!(
+            "{}/flake/github/{}/{}/{version}",
+            state.base_url, publish.owner, publish.repo
+        ),
+    });
+    queue_deliveries(state, release_id, &publish.owner, &publish.repo, payload).await;
+}
+
+/// How a readme has to be rendered.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadmeType {
+    Markdown,
+    Rst,
+    Plain,
+}
+
+impl ReadmeType {
+    /// Accepts the names case insensitively, along with common aliases like `md` or `txt`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "markdown" | "md" => Some(ReadmeType::Markdown),
+            "rst" | "restructuredtext" => Some(ReadmeType::Rst),
+            "plain" | "text" | "txt" => Some(ReadmeType::Plain),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReadmeType::Markdown => "markdown",
+            ReadmeType::Rst => "rst",
+            ReadmeType::Plain => "plain",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ReadmeType::Markdown => "text/markdown; charset=utf-8",
+            ReadmeType::Rst => "text/x-rst; charset=utf-8",
+            ReadmeType::Plain => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Owners and repos are stored trimmed and lowercased. GitHub treats their names case
+/// insensitively, so `NixOS/Nixpkgs` and `nixos/nixpkgs` are the same repo and must not end
+/// up as two rows. Lookups by owner and repo normalize the same way.
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Whether `hash` is a sha256 hash in the SRI format Nix uses for `narHash`, i.e. `sha256-`
+/// followed by the padded base64 of the 32 byte digest.
+fn is_valid_nar_hash(hash: &str) -> bool {
+    hash.strip_prefix("sha256-").is_some_and(|digest| {
+        digest.len() == 44
+            && digest.ends_with('=')
+            && digest[..43]
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+    })
+}
+
+/// Whether `owner` is on the publishing allowlist, where an empty list allows everyone. Both are
+/// expected to be normalized already.
+fn is_allowed_owner(allowed: &[String], owner: &str) -> bool {
+    allowed.is_empty() || allowed.iter().any(|allowed| allowed == owner)
+}
+
+// Descriptions made up from readmes are cut at a word boundary beyond this many characters
+const DESCRIPTION_MAX_CHARS: usize = 200;
+
+/// The first paragraph of prose in a readme, skipping headings, badges, images, HTML and reST
+/// titles and directives, for releases published without a description. Whitespace is
+/// collapsed.
+pub(crate) fn description_from_readme(readme: &str) -> Option<String> {
+    let is_underline = |line: &str| {
+        line.len() >= 3
+            && line.starts_with(['=', '-', '~', '^', '*', '#'])
+            && line.chars().all(|c| line.starts_with(c))
+    };
+    let is_prose = |line: &str| {
+        !line.starts_with(['#', '<', '!', '|'])
+            && !line.starts_with("[!")
+            && !line.starts_with("..")
+            && !line.starts_with("```")
+    };
+
+    let paragraph = readme
+        .split("\n\n")
+        .map(|paragraph| paragraph.lines().map(str::trim).collect::<Vec<_>>())
+        .find(|lines| {
+            // A reST title is a line followed by its underline
+            !lines.iter().any(|line| is_underline(line))
+                && lines
+                    .first()
+                    .is_some_and(|line| !line.is_empty() && is_prose(line))
+        })?;
+    let words: Vec<&str> = paragraph
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .collect();
+
+    let mut description = String::new();
+    for word in words {
+        if description.chars().count() + word.chars().count() >= DESCRIPTION_MAX_CHARS {
+            description.push('…');
+            break;
+        }
+        if !description.is_empty() {
+            description.push(' ');
+        }
+        description.push_str(word);
+    }
+    (!description.is_empty()).then_some(description)
+}
+
+const TRUNCATED_MARKER: &str = "\n\n[readme truncated]";
+
+/// Cuts `readme` down to at most `max_bytes` at a character boundary, marking that it was cut.
+pub(crate) fn truncate_readme(readme: &str, max_bytes: usize) -> Cow<'_, str> {
+    if readme.len() <= max_bytes {
+        return Cow::Borrowed(readme);
+    }
+    let mut end = max_bytes;
+    while !readme.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}{TRUNCATED_MARKER}", &readme[..end]))
+}
+
+/// The sha256 of a readme as lowercase hex, which identifies its stored copy and lets clients
+/// tell whether the readme changed between versions.
+pub(crate) fn readme_hash(readme: &str) -> String {
+    format!("{:x}", Sha256::digest(readme.as_bytes()))
+}
+
+fn compress_readme(readme: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a `Vec` can't fail
+    encoder
+        .write_all(readme.as_bytes())
+        .and_then(|_| encoder.finish())
+        .expect("Failed to compress readme")
+}
+
+pub(crate) fn decompress_readme(content: &[u8]) -> std::io::Result<String> {
+    let mut readme = String::new();
+    GzDecoder::new(content).read_to_string(&mut readme)?;
+    Ok(readme)
+}
+
+/// A full 40 character commit SHA or one abbreviated to at least 7 characters, as lowercase hex.
+/// Anything else would break the GitHub links built from it.
+pub(crate) fn is_valid_commit(commit: &str) -> bool {
+    (7..=40).contains(&commit.len())
+        && commit
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Nix systems are a CPU and an OS like `aarch64-darwin`, in lowercase letters, digits and `_`.
+pub(crate) fn is_valid_system(system: &str) -> bool {
+    let valid_part = |part: &str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+    };
+    system
+        .split_once('-')
+        .is_some_and(|(cpu, os)| valid_part(cpu) && valid_part(os))
+}
+
+// Tags per release and characters per tag
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 32;
+
+/// Tags are lowercase words like `cli` or `home-manager`, of letters, digits and `-`.
+pub(crate) fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LENGTH
+        && !tag.starts_with('-')
+        && !tag.ends_with('-')
+        && tag
+            .chars()
+            .all(|c| c.is_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// SPDX license ids only consist of letters, digits, `.`, `-` and a trailing `+`.
+pub(crate) fn is_valid_license(license: &str) -> bool {
+    let id = license.strip_suffix('+').unwrap_or(license);
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+}
+
+/// Which releases of a repo have their readme in the search index. A repo publishing the same
+/// readme with every version otherwise has it indexed as often, and matches on it count that
+/// many times more than those of a repo with a single release.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadmeIndexing {
+    All,
+    /// Only the release published last, the readme is removed from the others as it's indexed.
+    Latest,
+    /// Releases whose readme differs from those of the repo's earlier releases.
+    Distinct,
+}
+
+impl ReadmeIndexing {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "all" => Some(ReadmeIndexing::All),
+            "latest" => Some(ReadmeIndexing::Latest),
+            "distinct" => Some(ReadmeIndexing::Distinct),
+            _ => None,
+        }
+    }
+}
+
+// The release is already stored at this point, the document is indexed in the background
+// and a failure to index it is only logged rather than failing the publish.
+// The full readme is stored in the database, the index only gets a truncated one
+async fn index_release(
+    state: &AppState,
+    release_id: i32,
+    publish: &Publish,
+    created_at: NaiveDateTime,
+) {
+    // Indexed once too many rather than not at all when the lookup fails
+    let index_readme = match (state.readme_indexing, &publish.readme) {
+        (ReadmeIndexing::Distinct, Some(readme)) => {
+            let hash = readme_hash(readme);
+            let earlier = has_earlier_readme(release_id, &hash, &state.pool);
+            match tokio::time::timeout(state.db_timeout, earlier).await {
+                Ok(Ok(earlier)) => !earlier,
+                Ok(Err(err)) => {
+                    tracing::error!(release_id, "Failed to look up earlier readmes: {err}");
+                    true
+                }
+                Err(_) => {
+                    tracing::error!(release_id, "Timed out looking up earlier readmes");
+                    true
+                }
+            }
+        }
+        _ => true,
+    };
+    let outputs = publish.outputs.as_ref().map(|outputs| json!(outputs));
+    let provides: Vec<&String> = outputs
+        .as_ref()
+        .and_then(Value::as_object)
+        .map(|outputs| outputs.keys().collect())
+        .unwrap_or_default();
+    let document = json!({
+        "description": publish.description,
+        "readme": publish
+            .readme
+            .as_deref()
+            .filter(|_| index_readme)
+            .map(|readme| truncate_readme(readme, state.readme_max_bytes)),
+        "outputs": outputs.as_ref().map(Value::to_string),
+        "provides": provides,
+        "repo": publish.repo,
+        "owner": publish.owner,
+        // Identifies the repo as a single keyword, to group search results by repo
+        "full_name": format!("{}/{}", publish.owner, publish.repo),
+        "license": publish.license,
+        "systems": publish.systems,
+        "tags": publish.tags,
+        // For searches favouring fresh releases
+        "created_at": created_at,
+    });
+
+    state.index_queue.enqueue(release_id, document).await;
+
+    // Documents of earlier releases still waiting in the queue keep their readme
+    if state.readme_indexing == ReadmeIndexing::Latest {
+        let earlier = json!({
+            "bool": {
+                "filter": [{ "term": { "full_name": format!("{}/{}", publish.owner, publish.repo) } }],
+                "must_not": [{ "ids": { "values": [release_id.to_string()] } }],
+            }
+        });
+        let remove_readme = json!({ "source": "ctx._source.remove('readme')" });
+        // Failures are logged by `update_documents`
+        let _ = update_documents(state, earlier, remove_readme).await;
+    }
+}
+
+// Whether another release of the repo of `release_id` was published with the same readme
+async fn has_earlier_readme(
+    release_id: i32,
+    hash: &str,
+    pool: &Pool<Postgres>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS ( \
+            SELECT 1 FROM release AS earlier \
+                INNER JOIN release ON release.repo_id = earlier.repo_id \
+                WHERE release.id = $1 AND earlier.id <> $1 AND earlier.readme_hash = $2 \
+        )",
+    )
+    .bind(release_id)
+    .bind(hash)
+    .fetch_one(pool)
+    .await
+}
+
+// Same pattern as the Python backend, only used for the error message
+const VERSION_REGEX: &str = r"^v?([0-9]+\.[0-9]+\.?[0-9]*$)";
+
+// Strip the optional `v` prefix from a `MAJOR.MINOR[.PATCH]` version
+fn parse_publish_version(version: &str) -> Option<&str> {
+    let stripped = version.strip_prefix('v').unwrap_or(version);
+    let parts: Vec<&str> = stripped.split('.').collect();
+    let valid = (2..=3).contains(&parts.len())
+        && parts[..2]
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        && parts
+            .get(2)
+            .is_none_or(|patch| patch.bytes().all(|b| b.is_ascii_digit()));
+    valid.then_some(stripped)
+}
+
+enum Created {
+    Release(i32),
+    // The version was already published with the same content
+    Unchanged,
+    // The version was already published with different content
+    Conflict,
+    // The version was published with different content before, which `Republish::Update`
+    // replaced. The release keeps its creation time.
+    Updated(i32, NaiveDateTime),
+}
+
+/// What publishing a version again with different content, e.g. after its tag was moved to
+/// another commit, does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Republish {
+    /// Rejects it with 409, the release keeps what it was first published with.
+    Reject,
+    /// Updates the release in place and its search document, recording the previous commit.
+    Update,
+}
+
+impl Republish {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "reject" => Some(Republish::Reject),
+            "update" => Some(Republish::Update),
+            _ => None,
+        }
+    }
+}
+
+async fn create_release(
+    publish: &Publish,
+    version: &str,
+    state: &AppState,
+) -> Result<Created, AppError> {
+    let mut tx = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to start publish transaction")?;
+
+    let created = insert_release(publish, version, state.republish, &mut tx).await?;
+
+    tx.commit()
+        .await
+        .context("Failed to commit publish transaction")?;
+
+    Ok(created)
+}
+
+// Inserts the releases which passed the checks in a single transaction, `None` for the rest
+async fn create_releases(
+    checked: &[(Publish, Result<String, Rejection>)],
+    state: &AppState,
+) -> Result<Vec<Option<Created>>, AppError> {
+    let mut tx = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to start publish transaction")?;
+
+    let mut created = Vec::with_capacity(checked.len());
+    for (publish, version) in checked {
+        created.push(match version {
+            Ok(version) => Some(insert_release(publish, version, state.republish, &mut tx).await?),
+            Err(_) => None,
+        });
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit publish transaction")?;
+
+    Ok(created)
+}
+
+async fn insert_release(
+    publish: &Publish,
+    version: &str,
+    republish: Republish,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Created, AppError> {
+    let owner_id = upsert_owner(&publish.owner, tx).await?;
+    let repo_id = upsert_repo(&publish.repo, owner_id, tx).await?;
+
+    let readme_hash = match publish.readme {
+        Some(ref readme) => Some(upsert_readme(readme, tx).await?),
+        None => None,
+    };
+
+    let release_id: Option<i32> = sqlx::query_scalar(
+        "INSERT INTO release \
+            (repo_id, version, commit, description, readme_hash, readme_type, outputs, systems, \
+                tags, closure_size, nar_hash, created_at) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, now() AT TIME ZONE 'utc') \
+            ON CONFLICT (repo_id, version) DO NOTHING \
+            RETURNING id",
+    )
+    .bind(repo_id)
+    .bind(version)
+    .bind(&publish.commit)
+    .bind(&publish.description)
+    .bind(&readme_hash)
+    .bind(&publish.readme_type)
+    .bind(publish.outputs.as_ref().map(sqlx::types::Json))
+    .bind(&publish.systems)
+    .bind(&publish.tags)
+    .bind(publish.closure_size)
+    .bind(&publish.nar_hash)
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to insert release into database")?;
+
+    Ok(match release_id {
+        Some(release_id) => Created::Release(release_id),
+        None if is_published(publish, repo_id, version, tx).await? => Created::Unchanged,
+        None if republish == Republish::Update => {
+            update_release(publish, repo_id, version, readme_hash, tx).await?
+        }
+        None => Created::Conflict,
+    })
+}
+
+// Replaces the content of a published version with that of `publish`, recording the commit it
+// had. The updated_at trigger bumps the release.
+async fn update_release(
+    publish: &Publish,
+    repo_id: i32,
+    version: &str,
+    readme_hash: Option<String>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Created, AppError> {
+    let (release_id, previous_commit, created_at): (i32, String, NaiveDateTime) =
+        sqlx::query_as(
+            "UPDATE release \
+                SET commit = $3, description = $4, readme_hash = $5, readme = NULL, \
+                    readme_type = $6, outputs = $7, systems = $8, tags = $9, closure_size = $10, \
+                    nar_hash = $11 \
+                FROM (SELECT id, commit FROM release WHERE repo_id = $1 AND version = $2 FOR UPDATE) \
+                    AS previous \
+                WHERE release.id = previous.id \
+                RETURNING release.id, previous.commit, release.created_at",
+        )
+        .bind(repo_id)
+        .bind(version)
+        .bind(&publish.commit)
+        .bind(&publish.description)
+        .bind(readme_hash)
+        .bind(&publish.readme_type)
+        .bind(publish.outputs.as_ref().map(sqlx::types::Json))
+        .bind(&publish.systems)
+        .bind(&publish.tags)
+        .bind(publish.closure_size)
+        .bind(&publish.nar_hash)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to update release in database")?;
+
+    sqlx::query(
+        "INSERT INTO release_change (release_id, previous_commit, commit, created_at) \
+            VALUES ($1, $2, $3, now() AT TIME ZONE 'utc')",
+    )
+    .bind(release_id)
+    .bind(&previous_commit)
+    .bind(&publish.commit)
+    .execute(&mut **tx)
+    .await
+    .context("Failed to record release change in database")?;
+    tracing::info!(
+        release_id,
+        owner = publish.owner,
+        repo = publish.repo,
+        version,
+        previous_commit,
+        commit = publish.commit,
+        "Updated release in place"
+    );
+
+    Ok(Created::Updated(release_id, created_at))
+}
+
+// `None` when the version isn't published yet, otherwise whether it was published with the
+// same content. Nothing is written, the transaction is rolled back when dropped.
+async fn existing_release(
+    publish: &Publish,
+    version: &str,
+    state: &AppState,
+) -> Result<Option<bool>, AppError> {
+    let mut tx = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to start publish transaction")?;
+
+    let repo_id: Option<i32> = sqlx::query_scalar(
+        "SELECT githubrepo.id FROM githubrepo \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            INNER JOIN release ON release.repo_id = githubrepo.id \
+            WHERE githubowner.name = $1 AND githubrepo.name = $2 AND release.version = $3",
+    )
+    .bind(&publish.owner)
+    .bind(&publish.repo)
+    .bind(version)
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to fetch published release from database")?;
+
+    match repo_id {
+        Some(repo_id) => Ok(Some(
+            is_published(publish, repo_id, version, &mut tx).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// Whether the stored release has the same content as `publish`. The license isn't stored,
+// so it can't be compared.
+async fn is_published(
+    publish: &Publish,
+    repo_id: i32,
+    version: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<bool, AppError> {
+    type Content = (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        String,
+        Option<Value>,
+        Vec<String>,
+        Vec<String>,
+        Option<i64>,
+        Option<String>,
+    );
+    let (
+        commit,
+        description,
+        stored_hash,
+        legacy_readme,
+        readme_type,
+        outputs,
+        systems,
+        tags,
+        closure_size,
+        nar_hash,
+    ): Content = sqlx::query_as(
+        "SELECT commit, description, readme_hash, readme, readme_type, outputs, systems, tags, \
+                closure_size, nar_hash \
+            FROM release WHERE repo_id = $1 AND version = $2",
+    )
+    .bind(repo_id)
+    .bind(version)
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to fetch published release from database")?;
+    // Readmes are compared by hash, releases published before they were hashed have the text
+    let stored_hash = stored_hash.or_else(|| legacy_readme.as_deref().map(readme_hash));
+
+    Ok((
+        commit,
+        description,
+        stored_hash,
+        readme_type,
+        outputs,
+        systems,
+        tags,
+        closure_size,
+        nar_hash,
+    ) == (
+        publish.commit.clone(),
+        publish.description.clone(),
+        publish.readme.as_deref().map(readme_hash),
+        publish.readme_type.clone().unwrap_or_default(),
+        publish.outputs.as_ref().map(|outputs| json!(outputs)),
+        publish.systems.clone(),
+        publish.tags.clone(),
+        publish.closure_size,
+        publish.nar_hash.clone(),
+    ))
+}
+
+// Identical readmes, e.g. of consecutive versions, are only stored once
+async fn upsert_readme(
+    readme: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<String, AppError> {
+    let hash = readme_hash(readme);
+    sqlx::query(
+        "INSERT INTO readme (hash, content, created_at) \
+            VALUES ($1, $2, now() AT TIME ZONE 'utc') \
+            ON CONFLICT (hash) DO NOTHING",
+    )
+    .bind(&hash)
+    .bind(compress_readme(readme))
+    .execute(&mut **tx)
+    .await
+    .context("Failed to insert readme into database")?;
+
+    Ok(hash)
+}
+
+// The no-op update makes `RETURNING` yield the id of a row created by a concurrent publish,
+// where `DO NOTHING` would return no row at all.
+pub(crate) async fn upsert_owner(
+    name: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<i32, AppError> {
+    let owner_id = sqlx::query_scalar(
+        "INSERT INTO githubowner (name, created_at) \
+            VALUES ($1, now() AT TIME ZONE 'utc') \
+            ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name \
+            RETURNING id",
+    )
+    .bind(name)
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to upsert owner into database")?;
+
+    Ok(owner_id)
+}
+
+async fn upsert_repo(
+    name: &str,
+    owner_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<i32, AppError> {
+    let repo_id = sqlx::query_scalar(
+        "INSERT INTO githubrepo (name, owner_id, created_at) \
+            VALUES ($1, $2, now() AT TIME ZONE 'utc') \
+            ON CONFLICT (owner_id, name) DO UPDATE SET name = EXCLUDED.name \
+            RETURNING id",
+    )
+    .bind(name)
+    .bind(owner_id)
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to upsert repo into database")?;
+
+    Ok(repo_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("nixos"), "nixos");
+        assert_eq!(normalize_name(" NixOS\t"), "nixos");
+        assert_eq!(normalize_name("Home-Manager "), "home-manager");
+        assert_eq!(normalize_name("  "), "");
+    }
+
+    #[test]
+    fn test_readme_type_parse() {
+        assert_eq!(ReadmeType::parse("markdown"), Some(ReadmeType::Markdown));
+        assert_eq!(ReadmeType::parse(" MD "), Some(ReadmeType::Markdown));
+        assert_eq!(ReadmeType::parse("reStructuredText"), Some(ReadmeType::Rst));
+        assert_eq!(ReadmeType::parse("txt"), Some(ReadmeType::Plain));
+        assert_eq!(ReadmeType::parse("asciidoc"), None);
+        assert_eq!(ReadmeType::parse(""), None);
+    }
+
+    #[test]
+    fn test_compress_readme() {
+        let readme = "# Flake\n\nSame text, same hash. ".repeat(100);
+        let compressed = compress_readme(&readme);
+        assert!(compressed.len() < readme.len());
+        assert_eq!(decompress_readme(&compressed).unwrap(), readme);
+        assert!(decompress_readme(readme.as_bytes()).is_err());
+
+        assert_eq!(
+            readme_hash(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(readme_hash(&readme), readme_hash(&readme.clone()));
+    }
+
+    #[test]
+    fn test_description_from_readme() {
+        assert_eq!(
+            description_from_readme(
+                "# my-flake\n\n[![CI](https://ci/badge.svg)](https://ci)\n\nPackages for\n  hacking on Nix.\n\n## Usage"
+            ),
+            Some("Packages for hacking on Nix.".to_string())
+        );
+        assert_eq!(
+            description_from_readme("my-flake\n========\n\n.. image:: badge.svg\n\nA reST readme."),
+            Some("A reST readme.".to_string())
+        );
+        assert_eq!(description_from_readme("# Only a title\n"), None);
+        assert_eq!(description_from_readme(""), None);
+
+        let long = description_from_readme(&"word ".repeat(100)).unwrap();
+        assert!(long.chars().count() <= DESCRIPTION_MAX_CHARS);
+        assert!(long.ends_with("word…"));
+    }
+
+    #[test]
+    fn test_is_valid_system() {
+        assert!(is_valid_system("x86_64-linux"));
+        assert!(is_valid_system("aarch64-darwin"));
+        assert!(is_valid_system("armv7l-linux"));
+
+        assert!(!is_valid_system("x86_64"));
+        assert!(!is_valid_system("-linux"));
+        assert!(!is_valid_system("x86_64-"));
+        assert!(!is_valid_system("X86_64-Linux"));
+        assert!(!is_valid_system("x86_64-unknown-linux-gnu"));
+    }
+
+    #[test]
+    fn test_is_valid_tag() {
+        assert!(is_valid_tag("cli"));
+        assert!(is_valid_tag("home-manager"));
+        assert!(is_valid_tag("nix2"));
+        assert!(is_valid_tag("données"));
+
+        assert!(!is_valid_tag(""));
+        assert!(!is_valid_tag("CLI"));
+        assert!(!is_valid_tag("two words"));
+        assert!(!is_valid_tag("-cli"));
+        assert!(!is_valid_tag(&"a".repeat(MAX_TAG_LENGTH + 1)));
+    }
+
+    #[test]
+    fn test_is_valid_nar_hash() {
+        assert!(is_valid_nar_hash(
+            "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        ));
+        assert!(!is_valid_nar_hash(
+            "sha512-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        ));
+        assert!(!is_valid_nar_hash("sha256-47DEQpj8HBSa"));
+        assert!(!is_valid_nar_hash(
+            "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuF-="
+        ));
+    }
+
+    #[test]
+    fn test_is_allowed_owner() {
+        assert!(is_allowed_owner(&[], "nixos"));
+
+        let allowed = ["nixos".to_string(), "nix-community".to_string()];
+        assert!(is_allowed_owner(&allowed, "nixos"));
+        assert!(is_allowed_owner(&allowed, "nix-community"));
+        assert!(!is_allowed_owner(&allowed, "nixo"));
+        assert!(!is_allowed_owner(&allowed, "numtide"));
+    }
+
+    #[test]
+    fn test_truncate_readme() {
+        assert_eq!(truncate_readme("# Flake", 7), "# Flake");
+        assert_eq!(truncate_readme("# Flake", 3), "# F\n\n[readme truncated]");
+        // `é` takes two bytes and mustn't be split
+        assert_eq!(truncate_readme("café", 4), "caf\n\n[readme truncated]");
+    }
+
+    #[test]
+    fn test_is_valid_commit() {
+        assert!(is_valid_commit("f8f9f95b9f4cf91f6ad552132f196741daf3b1ad"));
+        assert!(is_valid_commit("f8f9f95"));
+
+        assert!(!is_valid_commit(""));
+        assert!(!is_valid_commit("f8f9f9"));
+        assert!(!is_valid_commit("F8F9F95B9F4CF91F6AD552132F196741DAF3B1AD"));
+        assert!(!is_valid_commit(
+            "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad0"
+        ));
+        assert!(!is_valid_commit("g8f9f95b9f4cf91f6ad552132f196741daf3b1ad"));
+        assert!(!is_valid_commit("refs/heads/main"));
+    }
 }
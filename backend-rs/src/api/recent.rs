@@ -0,0 +1,95 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, Pool, Postgres};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::api::flake::int_param;
+use crate::api::MAX_LIST_LIMIT;
+use crate::common::{with_db_timeout, AppError, AppState, Paginated, QueryParams};
+
+#[derive(FromRow, serde::Serialize, ToSchema)]
+pub struct RecentRepo {
+    owner: String,
+    repo: String,
+    description: Option<String>,
+    latest_version: String,
+    last_release_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    total: i64,
+}
+
+// Repos by the time of their latest release, so that each one appears once however many
+// releases it had lately. Archived repos are left out like in the release listing.
+#[utoipa::path(
+    get,
+    path = "/api/recent-repos",
+    params(
+        ("limit" = Option<i64>, Query, description = "Number of repos"),
+        ("offset" = Option<i64>, Query, description = "Number of repos to skip"),
+    ),
+    responses(
+        (status = 200, body = PaginatedRecentRepo),
+        (status = 400, description = "Invalid query parameters"),
+    )
+)]
+pub async fn get_recent_repos(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<Json<Paginated<RecentRepo>>, AppError> {
+    let limit = int_param(&params, "limit", 1)?
+        .map_or(state.default_list_limit, |limit| limit.min(MAX_LIST_LIMIT));
+    let offset = int_param(&params, "offset", 0)?.unwrap_or(0);
+
+    let repos = with_db_timeout(
+        state.db_timeout,
+        "get_recently_released_repos",
+        get_recently_released_repos(limit, offset, &state.pool),
+    )
+    .await?;
+    let total = repos.first().map_or(0, |repo| repo.total);
+
+    Ok(Json(Paginated {
+        items: repos,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+// `DISTINCT ON` keeps the latest release of each repo, which are then ordered across repos.
+// Ties are broken by id, so that pages are stable.
+async fn get_recently_released_repos(
+    limit: i64,
+    offset: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<RecentRepo>, AppError> {
+    let repos: Vec<RecentRepo> = sqlx::query_as(
+        "SELECT owner, repo, description, latest_version, last_release_at, \
+            COUNT(*) OVER () AS total \
+            FROM ( \
+                SELECT DISTINCT ON (release.repo_id) \
+                    githubowner.name AS owner, \
+                    githubrepo.name AS repo, \
+                    githubrepo.description AS description, \
+                    release.version AS latest_version, \
+                    release.created_at AS last_release_at, \
+                    release.id AS release_id \
+                    FROM release \
+                    INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                    INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                    WHERE NOT githubrepo.archived \
+                    ORDER BY release.repo_id, release.created_at DESC, release.id DESC \
+            ) AS latest \
+            ORDER BY last_release_at DESC, release_id DESC \
+            LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch recently released repos from database")?;
+
+    Ok(repos)
+}
@@ -0,0 +1,93 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use opensearch::SearchParts;
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+
+use crate::api::flake::malformed_search_response;
+use crate::api::FacetCount;
+use crate::common::{AppError, AppState};
+use crate::metrics::{self, Backend};
+
+// There are only a handful of output types in practice, this is plenty for all of them
+const OUTPUT_TYPES_LIMIT: i64 = 100;
+pub const OUTPUT_STATS_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+const OUTPUT_STATS_CACHE_CONTROL: &str = "public, max-age=300";
+
+// How many flakes provide each type of output, like `packages` or `nixosModules`. A repo counts
+// once however many of its releases provide the output, and archived repos are left out like
+// searches do. The cardinality aggregation counting repos is approximate for large counts.
+#[utoipa::path(
+    get,
+    path = "/api/stats/outputs",
+    responses(
+        (status = 200, body = [FacetCount]),
+        (status = 503, description = "Search unavailable"),
+    )
+)]
+pub async fn get_output_stats(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let count = count_output_types(&state);
+    let counts = state
+        .output_stats
+        .get_or_refresh(metrics::time(
+            Backend::OpenSearch,
+            "count_output_types",
+            count,
+        ))
+        .await?;
+
+    Ok((
+        [(header::CACHE_CONTROL, OUTPUT_STATS_CACHE_CONTROL)],
+        Json(counts),
+    ))
+}
+
+async fn count_output_types(state: &AppState) -> Result<Vec<FacetCount>, AppError> {
+    let response = state
+        .opensearch
+        .search(SearchParts::Index(&["flakes"]))
+        .size(0)
+        .body(json!({
+            "query": { "bool": { "must_not": [{ "term": { "archived": true } }] } },
+            "aggs": {
+                "outputs": {
+                    "terms": { "field": "provides", "size": OUTPUT_TYPES_LIMIT },
+                    "aggs": { "repos": { "cardinality": { "field": "full_name" } } },
+                },
+            },
+        }))
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to send opensearch request: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    let status = response.status_code();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(%status, body, "OpenSearch returned an error");
+        return Err(AppError::search_unavailable());
+    }
+
+    let res = response
+        .json::<Value>()
+        .await
+        .map_err(malformed_search_response)?;
+
+    let Some(buckets) = res["aggregations"]["outputs"]["buckets"].as_array() else {
+        return Err(malformed_search_response("no outputs aggregation"));
+    };
+    let mut counts: Vec<FacetCount> = buckets
+        .iter()
+        .filter_map(|bucket| {
+            Some(FacetCount {
+                value: bucket["key"].as_str()?.to_string(),
+                count: bucket["repos"]["value"].as_i64()?,
+            })
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    Ok(counts)
+}
@@ -0,0 +1,72 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use opensearch::SearchParts;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::api::flake::{facet_counts, int_param, malformed_search_response};
+use crate::api::MAX_LIST_LIMIT;
+use crate::common::{AppError, AppState, QueryParams};
+use crate::metrics::{self, Backend};
+
+const TAGS_LIMIT: i64 = 50;
+// Popular tags change slowly, so they may be reused for longer than search results
+const TAGS_CACHE_CONTROL: &str = "public, max-age=300";
+
+// The most used tags with the number of releases tagged with them, leaving out archived repos
+// like searches do
+#[utoipa::path(
+    get,
+    path = "/api/tags",
+    params(("limit" = Option<i64>, Query, description = "Number of tags")),
+    responses(
+        (status = 200, body = [FacetCount]),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 503, description = "Search unavailable"),
+    )
+)]
+pub async fn get_tags(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams,
+) -> Result<impl IntoResponse, AppError> {
+    let limit =
+        int_param(&params, "limit", 1)?.map_or(TAGS_LIMIT, |limit| limit.min(MAX_LIST_LIMIT));
+
+    let search = state
+        .opensearch
+        .search(SearchParts::Index(&["flakes"]))
+        .size(0)
+        .body(json!({
+            "query": { "bool": { "must_not": [{ "term": { "archived": true } }] } },
+            "aggs": { "tags": { "terms": { "field": "tags", "size": limit } } },
+        }))
+        .send();
+    let response = metrics::time(Backend::OpenSearch, "get_tags", search)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to send opensearch request: {err}");
+            AppError::search_unavailable()
+        })?;
+
+    let status = response.status_code();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(%status, body, "OpenSearch returned an error");
+        return Err(AppError::search_unavailable());
+    }
+
+    let res = response
+        .json::<Value>()
+        .await
+        .map_err(malformed_search_response)?;
+
+    // Without any tags the aggregation is still there, only without buckets
+    let tags = &res["aggregations"]["tags"];
+    if tags.is_null() {
+        return Err(malformed_search_response("no tags aggregation"));
+    }
+
+    Ok((
+        [(header::CACHE_CONTROL, TAGS_CACHE_CONTROL)],
+        Json(facet_counts(tags)),
+    ))
+}
@@ -0,0 +1,82 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, Pool, Postgres};
+use std::{sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+use crate::common::{with_db_timeout, AppError, AppState, Paginated};
+
+// How far back releases count towards a repo trending
+const TRENDING_WINDOW_DAYS: i32 = 30;
+const TRENDING_LIMIT: i64 = 10;
+pub const TRENDING_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, FromRow, serde::Serialize, ToSchema)]
+pub struct TrendingRepo {
+    owner: String,
+    repo: String,
+    description: Option<String>,
+    releases: i64,
+    last_release_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    total: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/trending",
+    responses((status = 200, body = PaginatedTrendingRepo))
+)]
+pub async fn get_trending(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Paginated<TrendingRepo>>, AppError> {
+    let trending = state
+        .trending
+        .get_or_refresh(async {
+            let repos = with_db_timeout(
+                state.db_timeout,
+                "get_trending_repos",
+                get_trending_repos(&state.pool),
+            )
+            .await?;
+            let total = repos.first().map_or(0, |repo| repo.total);
+
+            Ok(Paginated {
+                items: repos,
+                total,
+                limit: TRENDING_LIMIT,
+                offset: 0,
+            })
+        })
+        .await?;
+
+    Ok(Json(trending))
+}
+
+// Repos with the most releases within the trending window, most recently active first on ties
+// and then by name, so that the same releases always rank the same
+async fn get_trending_repos(pool: &Pool<Postgres>) -> Result<Vec<TrendingRepo>, AppError> {
+    let repos: Vec<TrendingRepo> = sqlx::query_as(
+        "SELECT githubowner.name AS owner, \
+            githubrepo.name AS repo, \
+            githubrepo.description AS description, \
+            COUNT(release.id) AS releases, \
+            MAX(release.created_at) AS last_release_at, \
+            COUNT(*) OVER () AS total \
+            FROM release \
+            INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+            INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+            WHERE release.created_at > (now() AT TIME ZONE 'utc') - make_interval(days => $1) \
+            GROUP BY githubowner.name, githubrepo.name, githubrepo.description \
+            ORDER BY releases DESC, last_release_at DESC, owner, repo \
+            LIMIT $2",
+    )
+    .bind(TRENDING_WINDOW_DAYS)
+    .bind(TRENDING_LIMIT)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch trending repos from database")?;
+
+    Ok(repos)
+}
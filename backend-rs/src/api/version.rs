@@ -0,0 +1,59 @@
+use axum::{extract::State, Json};
+use opensearch::indices::IndicesGetMappingParts;
+use serde_json::Value;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::common::AppState;
+
+/// Version of the `flakes` index settings and mappings, to be bumped whenever they change. It's
+/// stored in the `_meta` of the index mappings when the index is created.
+pub const FLAKE_INDEX_SCHEMA_VERSION: u32 = 6;
+
+/// Which build is running, there must be nothing secret in here.
+#[derive(serde::Serialize, ToSchema)]
+pub struct VersionResponse {
+    version: &'static str,
+    commit: &'static str,
+    // The index schema this build creates
+    index_schema_version: u32,
+    // The schema the live index was created with, unknown when OpenSearch is unavailable or the
+    // index predates versioning
+    live_index_schema_version: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    responses((status = 200, body = VersionResponse))
+)]
+pub async fn get_version(State(state): State<Arc<AppState>>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("GIT_COMMIT"),
+        index_schema_version: FLAKE_INDEX_SCHEMA_VERSION,
+        live_index_schema_version: live_index_schema_version(&state).await,
+    })
+}
+
+async fn live_index_schema_version(state: &AppState) -> Option<u64> {
+    let response = state
+        .opensearch
+        .indices()
+        .get_mapping(IndicesGetMappingParts::Index(&["flakes"]))
+        .send()
+        .await
+        .map_err(|err| tracing::warn!("Failed to fetch index mapping: {err}"))
+        .ok()?;
+    if !response.status_code().is_success() {
+        tracing::warn!(status = %response.status_code(), "Failed to fetch index mapping");
+        return None;
+    }
+    let body = response
+        .json::<Value>()
+        .await
+        .map_err(|err| tracing::warn!("Failed to decode index mapping: {err}"))
+        .ok()?;
+
+    body["flakes"]["mappings"]["_meta"]["schema_version"].as_u64()
+}
@@ -0,0 +1,147 @@
+use anyhow::Context;
+use axum::{extract::State, http::HeaderMap, http::StatusCode, Json};
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::api::publish::normalize_name;
+use crate::common::{with_db_timeout, AppError, AppState};
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct WebhookRequest {
+    owner: String,
+    repo: String,
+    // Receives a POST with the release as JSON for every new release of the repo
+    url: String,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, ToSchema)]
+pub struct Webhook {
+    id: i32,
+    owner: String,
+    repo: String,
+    url: String,
+}
+
+// Registers a URL to be notified of new releases of a repo, which doesn't have to be published
+// yet. Failed deliveries are retried with backoff.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    request_body = WebhookRequest,
+    responses(
+        (status = 201, body = Webhook),
+        (status = 400, description = "Empty owner or repo or invalid URL"),
+        (status = 401, description = "Missing or invalid admin bearer token"),
+        (status = 409, description = "URL already registered for the repo"),
+        (status = 503, description = "Read-only mode"),
+    )
+)]
+pub async fn post_webhook(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<WebhookRequest>,
+) -> Result<(StatusCode, Json<Webhook>), AppError> {
+    state.authorize_admin(&headers)?;
+    state.ensure_writable()?;
+
+    let (owner, repo) = (
+        normalize_name(&request.owner),
+        normalize_name(&request.repo),
+    );
+    if owner.is_empty() || repo.is_empty() {
+        return Err(AppError::BadRequest(
+            "owner and repo must not be empty".to_string(),
+        ));
+    }
+    let is_http = reqwest::Url::parse(&request.url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some());
+    if !is_http {
+        return Err(AppError::BadRequest(format!(
+            "{} is not an http or https URL",
+            request.url
+        )));
+    }
+
+    let webhook = with_db_timeout(
+        state.db_timeout,
+        "insert_webhook",
+        insert_webhook(&owner, &repo, &request.url, &state.pool),
+    )
+    .await?;
+    match webhook {
+        Some(webhook) => Ok((StatusCode::CREATED, Json(webhook))),
+        None => Err(AppError::Conflict(format!(
+            "{} is already registered for {owner}/{repo}",
+            request.url
+        ))),
+    }
+}
+
+async fn insert_webhook(
+    owner: &str,
+    repo: &str,
+    url: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<Webhook>, AppError> {
+    let webhook = sqlx::query_as(
+        "INSERT INTO webhook (owner, repo, url, created_at) \
+            VALUES ($1, $2, $3, now() AT TIME ZONE 'utc') \
+            ON CONFLICT (owner, repo, url) DO NOTHING \
+            RETURNING id, owner, repo, url",
+    )
+    .bind(owner)
+    .bind(repo)
+    .bind(url)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to insert webhook into database")?;
+
+    Ok(webhook)
+}
+
+// The release is already stored at this point, so a failure to queue its deliveries is only
+// logged rather than failing the publish
+pub(crate) async fn queue_deliveries(
+    state: &AppState,
+    release_id: i32,
+    owner: &str,
+    repo: &str,
+    payload: Value,
+) {
+    let queued = with_db_timeout(
+        state.db_timeout,
+        "insert_deliveries",
+        insert_deliveries(release_id, owner, repo, payload, &state.pool),
+    )
+    .await;
+    match queued {
+        Ok(0) => {}
+        Ok(_) => state.webhooks.wake(),
+        Err(_) => tracing::error!(release_id, "Failed to queue webhook deliveries"),
+    }
+}
+
+async fn insert_deliveries(
+    release_id: i32,
+    owner: &str,
+    repo: &str,
+    payload: Value,
+    pool: &Pool<Postgres>,
+) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        "INSERT INTO webhook_delivery (webhook_id, release_id, payload, next_attempt_at) \
+            SELECT id, $1, $2, now() AT TIME ZONE 'utc' FROM webhook \
+            WHERE owner = $3 AND repo = $4",
+    )
+    .bind(release_id)
+    .bind(sqlx::types::Json(payload))
+    .bind(owner)
+    .bind(repo)
+    .execute(pool)
+    .await
+    .context("Failed to insert webhook deliveries into database")?;
+
+    Ok(result.rows_affected())
+}
@@ -1,23 +1,521 @@
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts, HeaderMap, HeaderName, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use opensearch::OpenSearch;
+use serde_json::json;
 use sqlx::postgres::PgPool;
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, Semaphore};
+use utoipa::ToSchema;
+
+use crate::api::{
+    CatalogRepo, FacetCount, FlakeRelease, FlakeReleaseCompact, FreshnessDecay, LeaderboardOwner,
+    PublishFailure, ReadmeIndexing, RecentRepo, RepoOwner, Republish, SearchResults, TrendingRepo,
+};
+use crate::github::{Avatars, GitHub};
+use crate::indexer::IndexQueue;
+use crate::metrics::{self, Backend};
+use crate::ratelimit::RateLimiter;
+use crate::webhooks::WebhookQueue;
 
 pub struct AppState {
     pub opensearch: OpenSearch,
     pub pool: PgPool,
+    pub db_timeout: Duration,
+    pub read_only: bool,
+    // Allows exposing search internals such as scores, not meant for production
+    pub search_debug: bool,
+    // Longer search queries are rejected, they can make for expensive OpenSearch queries
+    pub max_query_length: usize,
+    // Fields matched by search queries with their default boosts, e.g. to experiment with
+    // leaving readmes out
+    pub search_fields: Vec<(&'static str, f64)>,
+    // How much older releases lose when searching with `freshness=true`
+    pub freshness: FreshnessDecay,
+    // Scores exposed in explain mode are rounded to this many decimals
+    pub score_decimals: u32,
+    // Passed to OpenSearch to require a share of the query terms to match
+    pub minimum_should_match: Option<String>,
+    pub default_list_limit: i64,
+    // Most releases of a repo returned at once, more have to be paged through
+    pub max_repo_releases: i64,
+    // Public URL of the frontend, used to link to release pages
+    pub base_url: String,
+    pub trending: Cached<Paginated<TrendingRepo>>,
+    // Flakes per output type, which only change as releases are published
+    pub output_stats: Cached<Vec<FacetCount>>,
+    // Pages of the leaderboard by ranking, limit and offset
+    pub leaderboard: TtlCache<String, Paginated<LeaderboardOwner>>,
+    // Readmes are truncated to this many bytes for the search index and previews
+    pub readme_max_bytes: usize,
+    // Which releases of a repo get their readme indexed, all of them are stored
+    pub readme_indexing: ReadmeIndexing,
+    pub search_cache: TtlCache<String, SearchResults>,
+    // Ids of known repos by owner and name. Unknown ones aren't cached, another instance may
+    // be the one they get published through.
+    pub repo_ids: LruCache<(String, String), i32>,
+    // Published releases are indexed in bulk by a background worker
+    pub index_queue: IndexQueue,
+    // Wakes the worker delivering webhooks of new releases
+    pub webhooks: WebhookQueue,
+    // Bounds how many searches are sent to OpenSearch at once, the rest wait for a permit
+    pub search_permits: Semaphore,
+    // Sends searches straight to the database fallback while OpenSearch keeps failing
+    pub search_breaker: CircuitBreaker,
+    // Set when publishes have to be verified against GitHub
+    pub github: Option<GitHub>,
+    // With `github`, publishes also have to descend from the commit of the latest release
+    pub verify_ancestry: bool,
+    // What publishing a version again with a different commit or content does
+    pub republish: Republish,
+    // Set when releases are enriched with the avatars of their owners
+    pub avatars: Option<Arc<Avatars>>,
+    // Bearer token for the admin endpoints, which are disabled without one
+    pub admin_token: Option<String>,
+    // Normalized owners allowed to publish, anyone may publish when it's empty
+    pub allowed_publish_owners: Vec<String>,
+    // Output kinds releases are published with, others are dropped or with
+    // `strict_output_kinds` rejected
+    pub output_kinds: Vec<String>,
+    pub strict_output_kinds: bool,
+    // Requests taking at least this long are logged as warnings
+    pub slow_request_threshold: Duration,
+    // Set when requests are limited per client IP
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+impl AppState {
+    /// Reject writes while the API is in maintenance read-only mode.
+    pub fn ensure_writable(&self) -> Result<(), AppError> {
+        if self.read_only {
+            return Err(AppError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Reject admin requests without the configured bearer token.
+    pub fn authorize_admin(&self, headers: &HeaderMap) -> Result<(), AppError> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match (&self.admin_token, token) {
+            (Some(expected), Some(token)) if expected == token => Ok(()),
+            _ => Err(AppError::Unauthorized),
+        }
+    }
+}
+
+/// The envelope shared by all endpoints returning a list of items.
+#[derive(Clone, serde::Serialize, ToSchema)]
+#[aliases(
+    PaginatedCatalogRepo = Paginated<CatalogRepo>,
+    PaginatedFlakeRelease = Paginated<FlakeRelease>,
+    PaginatedFlakeReleaseCompact = Paginated<FlakeReleaseCompact>,
+    PaginatedLeaderboardOwner = Paginated<LeaderboardOwner>,
+    PaginatedPublishFailure = Paginated<PublishFailure>,
+    PaginatedRecentRepo = Paginated<RecentRepo>,
+    PaginatedRepoOwner = Paginated<RepoOwner>,
+    PaginatedTrendingRepo = Paginated<TrendingRepo>
+)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// A `Paginated` list of releases with its items under `releases` as well, the key releases
+/// were listed under before the envelope and which the frontend still reads.
+pub struct WithReleases<T>(pub Paginated<T>);
+
+impl<T: serde::Serialize> serde::Serialize for WithReleases<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(serde::Serialize)]
+        struct Fields<'a, T> {
+            #[serde(flatten)]
+            page: &'a Paginated<T>,
+            releases: &'a [T],
+        }
+
+        Fields {
+            page: &self.0,
+            releases: &self.0.items,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Query parameters by name, extracted like `Query<HashMap<String, String>>` except that a
+/// parameter given more than once is rejected with a 400. Only one of the values could be used
+/// and which one would be up to the parser, so clients are told instead of left guessing.
+pub struct QueryParams(pub HashMap<String, String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for QueryParams {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(pairs) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+        let mut params = HashMap::with_capacity(pairs.len());
+        for (name, value) in pairs {
+            if params.contains_key(&name) {
+                return Err(AppError::BadRequest(format!(
+                    "{name} may only be given once"
+                )));
+            }
+            params.insert(name, value);
+        }
+        Ok(QueryParams(params))
+    }
+}
+
+/// A single value kept in memory and recomputed once it is older than `ttl`.
+pub struct Cached<T> {
+    ttl: Duration,
+    value: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> Cached<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Cached {
+            ttl,
+            value: Mutex::new(None),
+        }
+    }
+
+    pub async fn get_or_refresh<F>(&self, refresh: F) -> Result<T, AppError>
+    where
+        F: Future<Output = Result<T, AppError>>,
+    {
+        // Holding the lock while refreshing stops concurrent requests from all recomputing
+        let mut value = self.value.lock().await;
+        if let Some((computed_at, ref cached)) = *value {
+            if computed_at.elapsed() < self.ttl {
+                return Ok(cached.clone());
+            }
+        }
+
+        let fresh = refresh.await?;
+        *value = Some((Instant::now(), fresh.clone()));
+        Ok(fresh)
+    }
+}
+
+/// Values kept in memory by key for `ttl`, dropping the oldest entry once `capacity` is reached.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> TtlCache<K, V> {
+    /// A `capacity` of 0 disables the cache.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        TtlCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().await;
+        match entries.get(key) {
+            Some((inserted_at, value)) if inserted_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().await;
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (inserted_at, _)| inserted_at.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted_at, _))| *inserted_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+/// Values kept in memory by key, dropping the least recently used entry once `capacity` is
+/// reached. For values that stay valid until they are removed.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: Mutex<LruEntries<K, V>>,
+}
+
+struct LruEntries<K, V> {
+    // Counts the uses, to tell which entry was used least recently
+    uses: u64,
+    values: HashMap<K, (u64, V)>,
 }
 
-pub struct AppError(anyhow::Error);
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    /// A `capacity` of 0 disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: Mutex::new(LruEntries {
+                uses: 0,
+                values: HashMap::new(),
+            }),
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().await;
+        let entries = &mut *entries;
+        let (used_at, value) = entries.values.get_mut(key)?;
+        entries.uses += 1;
+        *used_at = entries.uses;
+        Some(value.clone())
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().await;
+        let entries = &mut *entries;
+        if entries.values.len() >= self.capacity && !entries.values.contains_key(&key) {
+            let least_recent = entries
+                .values
+                .iter()
+                .min_by_key(|(_, (used_at, _))| *used_at)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                entries.values.remove(&least_recent);
+            }
+        }
+        entries.uses += 1;
+        entries.values.insert(key, (entries.uses, value));
+    }
+
+    pub async fn clear(&self) {
+        self.entries.lock().await.values.clear();
+    }
+}
+
+/// Stops calling a failing service after `threshold` consecutive failures, until `cooldown`
+/// has passed and a call is let through again to find out whether it recovered.
+pub struct CircuitBreaker {
+    name: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    state: std::sync::Mutex<BreakerState>,
+}
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // The cooldown is over and calls are tried again, the next failure reopens the breaker
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of 0 disables the breaker.
+    pub fn new(name: &'static str, threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            name,
+            threshold,
+            cooldown,
+            state: std::sync::Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether the service should be called at all.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Open { until } if Instant::now() < until => false,
+            BreakerState::Open { .. } => {
+                tracing::info!(
+                    breaker = self.name,
+                    "Circuit breaker half-open, trying again"
+                );
+                *state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Closed { .. } | BreakerState::HalfOpen => true,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, BreakerState::Closed { .. }) {
+            tracing::info!(breaker = self.name, "Circuit breaker closed");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            BreakerState::HalfOpen => self.threshold,
+            BreakerState::Open { .. } => return,
+        };
+        *state = if failures >= self.threshold {
+            tracing::warn!(
+                breaker = self.name,
+                failures,
+                cooldown_secs = self.cooldown.as_secs(),
+                "Circuit breaker opened"
+            );
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+}
+
+/// How long a request spent waiting on the database and on search, reported to clients in
+/// the `Server-Timing` header.
+#[derive(Default)]
+pub struct ServerTiming {
+    db: Duration,
+    search: Option<Duration>,
+}
+
+impl ServerTiming {
+    pub async fn db<T>(&mut self, query: impl Future<Output = T>) -> T {
+        let start = Instant::now();
+        let result = query.await;
+        self.db += start.elapsed();
+        result
+    }
+
+    pub async fn search<T>(&mut self, search: impl Future<Output = T>) -> T {
+        let start = Instant::now();
+        let result = search.await;
+        *self.search.get_or_insert_default() += start.elapsed();
+        result
+    }
+
+    pub fn header(&self) -> [(HeaderName, String); 1] {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let mut value = format!("db;dur={:.1}", millis(self.db));
+        if let Some(search) = self.search {
+            value.push_str(&format!(", search;dur={:.1}", millis(search)));
+        }
+        [(HeaderName::from_static("server-timing"), value)]
+    }
+}
+
+/// The ways a handler can fail, each mapped to its own status code.
+pub enum AppError {
+    NotFound,
+    BadRequest(String),
+    Unauthorized,
+    /// The request clashes with existing data.
+    Conflict(String),
+    /// Writes are rejected during maintenance.
+    ReadOnly,
+    /// A service the API depends on, like the database or OpenSearch, is unavailable.
+    Upstream(String),
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn search_unavailable() -> Self {
+        AppError::Upstream("Search backend unavailable, please try again later".to_string())
+    }
+}
+
+/// Run a database query, giving up with an upstream error once `timeout` has passed. Its latency
+/// is recorded under `operation`, usually the name of the database helper. Like the timeout,
+/// dropping the future, when the client disconnects, abandons the query.
+pub async fn with_db_timeout<T>(
+    timeout: Duration,
+    operation: &'static str,
+    query: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    let query = tokio::time::timeout(timeout, query);
+    metrics::time(Backend::Postgres, operation, query)
+        .await
+        .map_err(|_| {
+            AppError::Upstream("Database query timed out, please try again later".to_string())
+        })?
+}
 
 impl From<anyhow::Error> for AppError {
     fn from(value: anyhow::Error) -> Self {
-        AppError(value)
+        AppError::Internal(value)
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-       let body = self.0.to_string();
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+        match self {
+            AppError::NotFound => (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "detail": "Not Found" })),
+            )
+                .into_response(),
+            AppError::BadRequest(detail) => {
+                (StatusCode::BAD_REQUEST, Json(json!({ "detail": detail }))).into_response()
+            }
+            AppError::Conflict(detail) => {
+                (StatusCode::CONFLICT, Json(json!({ "detail": detail }))).into_response()
+            }
+            AppError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({ "detail": "Missing or invalid admin token" })),
+            )
+                .into_response(),
+            AppError::ReadOnly => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "300")],
+                Json(json!({
+                    "detail": "Flakestry is in read-only mode for maintenance, please try again later"
+                })),
+            )
+                .into_response(),
+            AppError::Upstream(detail) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "detail": detail })),
+            )
+                .into_response(),
+            AppError::Internal(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "detail": err.to_string() })),
+            )
+                .into_response(),
+        }
     }
 }
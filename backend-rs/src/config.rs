@@ -0,0 +1,364 @@
+//! Settings read from the environment once at startup. A variable that doesn't parse panics with
+//! its name before anything is served, rather than when a request first needs it.
+
+use opensearch::{
+    auth::Credentials,
+    cert::{Certificate, CertificateValidation},
+    http::{transport::DEFAULT_ADDRESS, Url},
+};
+use std::{env, fs, net::SocketAddr, str::FromStr, time::Duration};
+
+use crate::api::{
+    is_valid_minimum_should_match, normalize_name, parse_search_fields, parse_time_value,
+    FreshnessDecay, ReadmeIndexing, Republish, FLAKE_OUTPUTS, MAX_LIST_LIMIT, SEARCH_FIELDS,
+};
+use crate::indexer::IndexConfig;
+use crate::server::ServerConfig;
+use crate::webhooks::WebhookConfig;
+use crate::{TextAnalysis, TEXT_ANALYZERS};
+
+pub struct Config {
+    pub database_url: String,
+    pub run_migrations: bool,
+    pub db_timeout: Duration,
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    pub opensearch_url: Url,
+    pub opensearch_credentials: Option<Credentials>,
+    pub opensearch_cert_validation: Option<CertificateValidation>,
+    // Refuses to start when the index mapping differs from the expected one
+    pub strict_index_mapping: bool,
+    pub text_analysis: TextAnalysis,
+    pub bind_addr: SocketAddr,
+    pub base_url: String,
+    pub read_only: bool,
+    pub search_debug: bool,
+    pub max_query_length: usize,
+    pub search_fields: Vec<(&'static str, f64)>,
+    pub freshness: FreshnessDecay,
+    pub score_decimals: u32,
+    pub minimum_should_match: Option<String>,
+    pub default_list_limit: i64,
+    pub max_repo_releases: i64,
+    pub search_cache_ttl: Duration,
+    pub search_cache_capacity: usize,
+    pub repo_id_cache_capacity: usize,
+    pub search_concurrency: usize,
+    pub search_breaker_threshold: u32,
+    pub search_breaker_cooldown: Duration,
+    pub readme_max_bytes: usize,
+    pub readme_indexing: ReadmeIndexing,
+    pub github_api_url: String,
+    pub github_token: Option<String>,
+    // Verifies publishes against GitHub, which requires `github_token`
+    pub verify_github: bool,
+    // Also rejects publishes of commits older than the latest release, with `verify_github`
+    pub verify_ancestry: bool,
+    pub republish: Republish,
+    pub enrich_avatars: bool,
+    pub admin_token: Option<String>,
+    pub allowed_publish_owners: Vec<String>,
+    pub output_kinds: Vec<String>,
+    pub strict_output_kinds: bool,
+    pub slow_request_threshold: Duration,
+    pub rate_limit_per_minute: u32,
+    pub index: IndexConfig,
+    pub webhooks: WebhookConfig,
+    pub server: ServerConfig,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let db_timeout = parse_var("DB_TIMEOUT_SECS").unwrap_or(5);
+
+        // Only applies when the index is created, an existing one has to be recreated to change it
+        let text_analyzer =
+            env::var("SEARCH_TEXT_ANALYZER").unwrap_or_else(|_| "standard".to_string());
+        assert!(
+            TEXT_ANALYZERS.contains(&text_analyzer.as_str()),
+            "Failed to parse SEARCH_TEXT_ANALYZER, expected one of {}",
+            TEXT_ANALYZERS.join(", ")
+        );
+        let text_analysis = TextAnalysis {
+            // Comma separated, e.g. `nix,flake,install`
+            stopwords: list_var("SEARCH_STOPWORDS")
+                .map(|words| words.iter().map(|word| word.to_lowercase()).collect())
+                .unwrap_or_default(),
+            min_token_length: parse_var("SEARCH_MIN_TOKEN_LENGTH").unwrap_or(1),
+            ..TextAnalysis::new(&text_analyzer)
+        };
+
+        // Comma separated, e.g. `description,repo,owner`
+        let search_fields = env::var("SEARCH_FIELDS")
+            .map(|fields| {
+                parse_search_fields(&fields).expect(
+                    "Failed to parse SEARCH_FIELDS, expected a list of description, readme, \
+                        outputs, repo and owner",
+                )
+            })
+            .unwrap_or_else(|_| SEARCH_FIELDS.to_vec());
+        let default_freshness = FreshnessDecay::default();
+        let freshness = FreshnessDecay {
+            offset: env::var("SEARCH_FRESHNESS_OFFSET").unwrap_or(default_freshness.offset),
+            scale: env::var("SEARCH_FRESHNESS_SCALE").unwrap_or(default_freshness.scale),
+            decay: parse_var("SEARCH_FRESHNESS_DECAY").unwrap_or(default_freshness.decay),
+        };
+        assert!(
+            parse_time_value(&freshness.offset).is_some(),
+            "Failed to parse SEARCH_FRESHNESS_OFFSET, expected a time like 30d"
+        );
+        assert!(
+            parse_time_value(&freshness.scale).is_some_and(|scale| scale > 0),
+            "Failed to parse SEARCH_FRESHNESS_SCALE, expected a time like 180d"
+        );
+        assert!(
+            freshness.decay > 0.0 && freshness.decay < 1.0,
+            "Failed to parse SEARCH_FRESHNESS_DECAY, expected a number between 0 and 1"
+        );
+        let minimum_should_match = env::var("SEARCH_MINIMUM_SHOULD_MATCH").ok();
+        if let Some(ref value) = minimum_should_match {
+            assert!(
+                is_valid_minimum_should_match(value),
+                "Failed to parse SEARCH_MINIMUM_SHOULD_MATCH, expected a number or a percentage"
+            );
+        }
+
+        let github_token = env::var("GITHUB_TOKEN").ok();
+        let verify_github = env_flag("VERIFY_GITHUB");
+        assert!(
+            !verify_github || github_token.is_some(),
+            "VERIFY_GITHUB requires GITHUB_TOKEN"
+        );
+        let verify_ancestry = env_flag("VERIFY_COMMIT_ANCESTRY");
+        assert!(
+            !verify_ancestry || verify_github,
+            "VERIFY_COMMIT_ANCESTRY requires VERIFY_GITHUB"
+        );
+
+        Config {
+            database_url: env::var("DATABASE_URL").expect("Failed to parse database url"),
+            run_migrations: env_flag("RUN_MIGRATIONS"),
+            db_timeout: Duration::from_secs(db_timeout),
+            // The defaults of sqlx
+            db_max_connections: parse_var("DB_MAX_CONNECTIONS").unwrap_or(10).max(1),
+            db_min_connections: parse_var("DB_MIN_CONNECTIONS").unwrap_or(0),
+            opensearch_url: parse_var("OPENSEARCH_URL")
+                .unwrap_or_else(|| Url::parse(DEFAULT_ADDRESS).unwrap()),
+            opensearch_credentials: opensearch_credentials(|name| {
+                env::var(name).ok().filter(|v| !v.is_empty())
+            })
+            .unwrap_or_else(|err| panic!("{err}")),
+            opensearch_cert_validation: opensearch_cert_validation(
+                env::var("OPENSEARCH_CA_CERT").ok().as_deref(),
+                env_flag("OPENSEARCH_INSECURE"),
+            )
+            .unwrap_or_else(|err| panic!("{err}")),
+            strict_index_mapping: env_flag("STRICT_INDEX_MAPPING"),
+            text_analysis,
+            bind_addr: parse_var("BIND_ADDR").unwrap_or(SocketAddr::from(([0, 0, 0, 0], 3000))),
+            base_url: env::var("FLAKESTRY_URL")
+                .unwrap_or_else(|_| "https://flakestry.dev".to_string()),
+            read_only: env_flag("READ_ONLY"),
+            search_debug: env_flag("SEARCH_DEBUG"),
+            max_query_length: parse_var("MAX_QUERY_LENGTH").unwrap_or(256),
+            search_fields,
+            freshness,
+            score_decimals: parse_var("SCORE_DECIMALS").unwrap_or(3),
+            minimum_should_match,
+            default_list_limit: parse_var("DEFAULT_LIST_LIMIT")
+                .unwrap_or(100)
+                .clamp(1, MAX_LIST_LIMIT),
+            max_repo_releases: parse_var("MAX_REPO_RELEASES").unwrap_or(500).max(1),
+            search_cache_ttl: Duration::from_secs(parse_var("SEARCH_CACHE_TTL_SECS").unwrap_or(30)),
+            // A capacity of 0 turns the cache off
+            search_cache_capacity: parse_var("SEARCH_CACHE_CAPACITY").unwrap_or(1000),
+            // Ids are small, so this holds the hot repos at little cost
+            repo_id_cache_capacity: parse_var("REPO_ID_CACHE_CAPACITY").unwrap_or(10_000),
+            // At least one search has to be let through
+            search_concurrency: parse_var("SEARCH_CONCURRENCY").unwrap_or(32).max(1),
+            // A threshold of 0 keeps trying OpenSearch however often it fails
+            search_breaker_threshold: parse_var("SEARCH_BREAKER_THRESHOLD").unwrap_or(5),
+            search_breaker_cooldown: Duration::from_secs(
+                parse_var("SEARCH_BREAKER_COOLDOWN_SECS").unwrap_or(30),
+            ),
+            readme_max_bytes: parse_var("README_MAX_BYTES").unwrap_or(64 * 1024),
+            readme_indexing: env::var("README_INDEXING")
+                .map(|strategy| {
+                    ReadmeIndexing::parse(&strategy)
+                        .expect("Failed to parse README_INDEXING, expected all, latest or distinct")
+                })
+                .unwrap_or(ReadmeIndexing::All),
+            github_api_url: env::var("GITHUB_API_URL")
+                .unwrap_or_else(|_| "https://api.github.com".to_string()),
+            github_token,
+            verify_github,
+            verify_ancestry,
+            republish: env::var("REPUBLISH_POLICY")
+                .map(|policy| {
+                    Republish::parse(&policy)
+                        .expect("Failed to parse REPUBLISH_POLICY, expected reject or update")
+                })
+                .unwrap_or(Republish::Reject),
+            enrich_avatars: env_flag("ENRICH_AVATARS"),
+            admin_token: env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            // Comma separated, e.g. `nixos, nix-community`
+            allowed_publish_owners: list_var("ALLOWED_PUBLISH_OWNERS")
+                .map(|owners| owners.iter().map(|owner| normalize_name(owner)).collect())
+                .unwrap_or_default(),
+            // Comma separated like the owners, replacing the standard kinds
+            output_kinds: list_var("PUBLISH_OUTPUT_KINDS")
+                .unwrap_or_else(|| FLAKE_OUTPUTS.iter().map(|kind| kind.to_string()).collect()),
+            strict_output_kinds: env_flag("STRICT_OUTPUT_KINDS"),
+            slow_request_threshold: Duration::from_millis(
+                parse_var("SLOW_REQUEST_MS").unwrap_or(1000),
+            ),
+            // Requests per client IP, 0 turns the limit off
+            rate_limit_per_minute: parse_var("RATE_LIMIT_PER_MINUTE").unwrap_or(600),
+            index: IndexConfig::from_env(),
+            webhooks: WebhookConfig::from_env(),
+            server: ServerConfig::from_env(),
+        }
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| matches!(value.as_str(), "1" | "true"))
+}
+
+// `None` when the variable isn't set
+fn parse_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Failed to parse {name}")),
+    )
+}
+
+// The trimmed items of a comma separated variable, leaving out empty ones
+fn list_var(name: &str) -> Option<Vec<String>> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect(),
+    )
+}
+
+// Either OPENSEARCH_USERNAME and OPENSEARCH_PASSWORD or OPENSEARCH_API_KEY_ID and
+// OPENSEARCH_API_KEY, half a pair is rather a mistake than a reason to go unauthenticated.
+// OPENSEARCH_USER is read as well, OPENSEARCH_USERNAME wins when both are set.
+fn opensearch_credentials(
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Credentials>, &'static str> {
+    let username = var("OPENSEARCH_USERNAME").or_else(|| var("OPENSEARCH_USER"));
+    let basic = match (username, var("OPENSEARCH_PASSWORD")) {
+        (Some(username), Some(password)) => Some(Credentials::Basic(username, password)),
+        (None, None) => None,
+        _ => return Err("OPENSEARCH_USERNAME and OPENSEARCH_PASSWORD have to be set together"),
+    };
+    let api_key = match (var("OPENSEARCH_API_KEY_ID"), var("OPENSEARCH_API_KEY")) {
+        (Some(id), Some(key)) => Some(Credentials::ApiKey(id, key)),
+        (None, None) => None,
+        _ => return Err("OPENSEARCH_API_KEY_ID and OPENSEARCH_API_KEY have to be set together"),
+    };
+    match (basic, api_key) {
+        (Some(_), Some(_)) => {
+            Err("Either OPENSEARCH_USERNAME and OPENSEARCH_PASSWORD or an API key can be set")
+        }
+        (basic, api_key) => Ok(basic.or(api_key)),
+    }
+}
+
+// Certificates are validated against the system's CAs unless a CA file is given, e.g. for a
+// cluster with certificates signed by its own CA. Skipping validation is only meant for development.
+fn opensearch_cert_validation(
+    ca_cert: Option<&str>,
+    insecure: bool,
+) -> Result<Option<CertificateValidation>, String> {
+    match (ca_cert, insecure) {
+        (Some(_), true) => {
+            Err("OPENSEARCH_CA_CERT and OPENSEARCH_INSECURE can't be set together".to_string())
+        }
+        (None, true) => {
+            tracing::warn!("Not validating OpenSearch certificates, OPENSEARCH_INSECURE is set");
+            Ok(Some(CertificateValidation::None))
+        }
+        (Some(path), false) => {
+            let pem = fs::read(path)
+                .map_err(|err| format!("Failed to read OPENSEARCH_CA_CERT {path}: {err}"))?;
+            let cert = Certificate::from_pem(&pem)
+                .map_err(|err| format!("Failed to parse OPENSEARCH_CA_CERT {path}: {err}"))?;
+            Ok(Some(CertificateValidation::Full(cert)))
+        }
+        (None, false) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opensearch_credentials() {
+        let credentials = |vars: &[(&str, &str)]| {
+            let vars: Vec<(String, String)> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            opensearch_credentials(move |name| {
+                vars.iter()
+                    .find(|(var, _)| var == name)
+                    .map(|(_, value)| value.clone())
+            })
+        };
+
+        assert!(matches!(credentials(&[]), Ok(None)));
+        assert!(matches!(
+            credentials(&[("OPENSEARCH_USERNAME", "admin"), ("OPENSEARCH_PASSWORD", "secret")]),
+            Ok(Some(Credentials::Basic(username, password))) if username == "admin" && password == "secret"
+        ));
+        assert!(matches!(
+            credentials(&[("OPENSEARCH_API_KEY_ID", "id"), ("OPENSEARCH_API_KEY", "key")]),
+            Ok(Some(Credentials::ApiKey(id, key))) if id == "id" && key == "key"
+        ));
+        assert!(matches!(
+            credentials(&[("OPENSEARCH_USER", "admin"), ("OPENSEARCH_PASSWORD", "secret")]),
+            Ok(Some(Credentials::Basic(username, _))) if username == "admin"
+        ));
+        assert!(matches!(
+            credentials(&[
+                ("OPENSEARCH_USER", "other"),
+                ("OPENSEARCH_USERNAME", "admin"),
+                ("OPENSEARCH_PASSWORD", "secret"),
+            ]),
+            Ok(Some(Credentials::Basic(username, _))) if username == "admin"
+        ));
+        assert!(credentials(&[("OPENSEARCH_USERNAME", "admin")]).is_err());
+        assert!(credentials(&[("OPENSEARCH_USER", "admin")]).is_err());
+        assert!(credentials(&[("OPENSEARCH_API_KEY", "key")]).is_err());
+        assert!(credentials(&[
+            ("OPENSEARCH_USERNAME", "admin"),
+            ("OPENSEARCH_PASSWORD", "secret"),
+            ("OPENSEARCH_API_KEY_ID", "id"),
+            ("OPENSEARCH_API_KEY", "key"),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_opensearch_cert_validation() {
+        assert!(matches!(opensearch_cert_validation(None, false), Ok(None)));
+        assert!(matches!(
+            opensearch_cert_validation(None, true),
+            Ok(Some(CertificateValidation::None))
+        ));
+        assert!(opensearch_cert_validation(Some("/nonexistent/ca.pem"), false).is_err());
+        assert!(opensearch_cert_validation(Some("Cargo.toml"), false).is_err());
+        assert!(opensearch_cert_validation(Some("/nonexistent/ca.pem"), true).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::Context;
+use reqwest::{header, StatusCode};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::task::JoinSet;
+
+use crate::common::{AppError, TtlCache};
+
+// Verified repos and commits don't go away often, so they're only checked every few minutes
+const VERIFIED_TTL: Duration = Duration::from_secs(10 * 60);
+const VERIFIED_CAPACITY: usize = 10_000;
+// Avatars rarely change, owners without one or failed lookups are only retried after this too
+const AVATAR_TTL: Duration = Duration::from_secs(60 * 60);
+const AVATAR_CAPACITY: usize = 10_000;
+// Listings wait for the avatars, so slow lookups are given up on
+const AVATAR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks published repos and commits against the GitHub API.
+pub struct GitHub {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    verified: TtlCache<String, ()>,
+}
+
+impl GitHub {
+    pub fn new(api_url: String, token: String) -> Self {
+        GitHub {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            verified: TtlCache::new(VERIFIED_TTL, VERIFIED_CAPACITY),
+        }
+    }
+
+    /// Returns why the publish should be rejected, if it should.
+    pub async fn verify(
+        &self,
+        owner: &str,
+        repo: &str,
+        commit: &str,
+    ) -> Result<Option<String>, AppError> {
+        let key = format!("{owner}/{repo}@{commit}");
+        if self.verified.get(&key).await.is_some() {
+            return Ok(None);
+        }
+
+        if !self.exists(&format!("repos/{owner}/{repo}")).await? {
+            return Ok(Some(format!("{owner}/{repo} doesn't exist on GitHub")));
+        }
+        if !self
+            .exists(&format!("repos/{owner}/{repo}/commits/{commit}"))
+            .await?
+        {
+            return Ok(Some(format!("{commit} is not a commit of {owner}/{repo}")));
+        }
+
+        self.verified.insert(key, ()).await;
+        Ok(None)
+    }
+
+    /// Returns why a publish of `commit` should be rejected when it doesn't descend from `base`,
+    /// the commit of the repo's latest release. It's up to GitHub's compare API,
+    /// `GET /repos/{owner}/{repo}/compare/{base}...{commit}`, whose `status` is `ahead` or
+    /// `identical` for a descendant and `behind` or `diverged` otherwise.
+    pub async fn verify_descendant(
+        &self,
+        owner: &str,
+        repo: &str,
+        base: &str,
+        commit: &str,
+    ) -> Result<Option<String>, AppError> {
+        let key = format!("{owner}/{repo}@{base}...{commit}");
+        if self.verified.get(&key).await.is_some() {
+            return Ok(None);
+        }
+
+        let path = format!("repos/{owner}/{repo}/compare/{base}...{commit}");
+        let response = self.get(&path).await?;
+        // The base was verified when it was published, but it can have been force-pushed away
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::UNPROCESSABLE_ENTITY
+        ) {
+            return Ok(Some(format!(
+                "{commit} can't be compared with {base}, the commit of the latest release"
+            )));
+        }
+        let status = response.status();
+        if !status.is_success() {
+            tracing::error!(%status, path, "GitHub returned an error");
+            return Err(AppError::Upstream(
+                "GitHub unavailable, please try again later".to_string(),
+            ));
+        }
+        let comparison: Value = response
+            .json()
+            .await
+            .context("Failed to decode GitHub comparison")?;
+
+        match comparison["status"].as_str() {
+            Some("ahead" | "identical") => {
+                self.verified.insert(key, ()).await;
+                Ok(None)
+            }
+            _ => Ok(Some(format!(
+                "{commit} doesn't descend from {base}, the commit of the latest release"
+            ))),
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<reqwest::Response, AppError> {
+        let response = self
+            .client
+            .get(format!("{}/{path}", self.api_url))
+            .bearer_auth(&self.token)
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .header(header::USER_AGENT, "flakestry")
+            .send()
+            .await
+            .context("Failed to send GitHub request")?;
+        Ok(response)
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, AppError> {
+        let response = self.get(path).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            // GitHub answers 422 for SHAs that don't resolve to a commit
+            StatusCode::NOT_FOUND | StatusCode::UNPROCESSABLE_ENTITY => Ok(false),
+            status => {
+                tracing::error!(%status, path, "GitHub returned an error");
+                Err(AppError::Upstream(
+                    "GitHub unavailable, please try again later".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// Looks up the avatars of GitHub owners to enrich releases with. The token is optional, but
+/// GitHub only allows few requests without one.
+pub struct Avatars {
+    client: reqwest::Client,
+    api_url: String,
+    token: Option<String>,
+    cached: TtlCache<String, Option<String>>,
+}
+
+impl Avatars {
+    pub fn new(api_url: String, token: Option<String>) -> Self {
+        Avatars {
+            client: reqwest::Client::builder()
+                .timeout(AVATAR_TIMEOUT)
+                .build()
+                .expect("Failed to build avatar client"),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            cached: TtlCache::new(AVATAR_TTL, AVATAR_CAPACITY),
+        }
+    }
+
+    /// The avatar URLs of the `owners` that have one, looked up concurrently.
+    pub async fn lookup(self: &Arc<Self>, owners: Vec<String>) -> HashMap<String, String> {
+        let mut lookups = JoinSet::new();
+        for owner in owners {
+            let avatars = self.clone();
+            lookups.spawn(async move {
+                let avatar = avatars.get(&owner).await;
+                (owner, avatar)
+            });
+        }
+
+        let mut avatars = HashMap::new();
+        while let Some(lookup) = lookups.join_next().await {
+            if let Ok((owner, Some(avatar))) = lookup {
+                avatars.insert(owner, avatar);
+            }
+        }
+        avatars
+    }
+
+    async fn get(&self, owner: &str) -> Option<String> {
+        if let Some(avatar) = self.cached.get(&owner.to_string()).await {
+            return avatar;
+        }
+        let avatar = self
+            .fetch(owner)
+            .await
+            .map_err(|err| tracing::warn!(owner, "Failed to fetch GitHub avatar: {err:#}"))
+            .ok()
+            .flatten();
+        self.cached.insert(owner.to_string(), avatar.clone()).await;
+        avatar
+    }
+
+    async fn fetch(&self, owner: &str) -> anyhow::Result<Option<String>> {
+        let mut request = self
+            .client
+            .get(format!("{}/users/{owner}", self.api_url))
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .header(header::USER_AGENT, "flakestry");
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .context("Failed to send GitHub request")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let user: Value = response
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to decode GitHub user")?;
+
+        Ok(user["avatar_url"].as_str().map(str::to_string))
+    }
+}
@@ -0,0 +1,227 @@
+use opensearch::{http::request::JsonBody, params::Refresh, BulkParts, OpenSearch};
+use serde_json::{json, Value};
+use std::{env, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+
+use crate::metrics::{self, Backend};
+
+// Publishes wait for room in the queue once this many documents are waiting to be indexed
+const QUEUE_CAPACITY: usize = 1000;
+
+/// When the queued documents are sent to OpenSearch, whichever comes first.
+pub struct IndexConfig {
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    // Documents only become searchable once OpenSearch refreshes the index, every second by
+    // default. Waiting for that makes publishes searchable as soon as they're acknowledged, at
+    // the cost of each publish taking up to a refresh interval longer and being indexed on its
+    // own rather than batched.
+    pub wait_for_refresh: bool,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig {
+            batch_size: 100,
+            flush_interval: Duration::from_secs(1),
+            wait_for_refresh: false,
+        }
+    }
+}
+
+impl IndexConfig {
+    pub fn from_env() -> Self {
+        let default = IndexConfig::default();
+        IndexConfig {
+            // A batch has to hold at least one document
+            batch_size: env::var("INDEX_BATCH_SIZE")
+                .map(|size| size.parse().expect("Failed to parse INDEX_BATCH_SIZE"))
+                .map_or(default.batch_size, |size: usize| size.max(1)),
+            flush_interval: env::var("INDEX_FLUSH_INTERVAL_MS")
+                .map(|millis| {
+                    Duration::from_millis(
+                        millis
+                            .parse()
+                            .expect("Failed to parse INDEX_FLUSH_INTERVAL_MS"),
+                    )
+                })
+                .unwrap_or(default.flush_interval),
+            wait_for_refresh: match env::var("INDEX_REFRESH").as_deref() {
+                Err(_) | Ok("false") => default.wait_for_refresh,
+                Ok("wait_for") => true,
+                Ok(_) => panic!("Failed to parse INDEX_REFRESH, expected false or wait_for"),
+            },
+        }
+    }
+}
+
+struct IndexOperation {
+    release_id: i32,
+    document: Value,
+    // Told once the document is searchable, or indexing it failed
+    indexed: Option<oneshot::Sender<()>>,
+}
+
+/// Hands search documents to the background worker, which indexes them in bulk.
+pub struct IndexQueue {
+    sender: mpsc::Sender<IndexOperation>,
+    wait_for_refresh: bool,
+}
+
+impl IndexQueue {
+    /// Queues the document of a release, waiting for it to be searchable with
+    /// `IndexConfig::wait_for_refresh`. It's only logged when the worker has shut down already,
+    /// the release is stored and can be reindexed.
+    pub async fn enqueue(&self, release_id: i32, document: Value) {
+        let (indexed, searchable) = if self.wait_for_refresh {
+            let (indexed, searchable) = oneshot::channel();
+            (Some(indexed), Some(searchable))
+        } else {
+            (None, None)
+        };
+        let operation = IndexOperation {
+            release_id,
+            document,
+            indexed,
+        };
+        if self.sender.send(operation).await.is_err() {
+            tracing::error!(
+                release_id,
+                "Failed to queue release for indexing, shutting down"
+            );
+            return;
+        }
+        if let Some(searchable) = searchable {
+            let _ = searchable.await;
+        }
+    }
+}
+
+/// The worker task indexing queued documents, which has to be shut down to index what's left.
+pub struct IndexWorker {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl IndexWorker {
+    /// Stops taking documents and waits for the queued ones to be indexed.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if let Err(err) = self.handle.await {
+            tracing::error!("Index worker failed: {err}");
+        }
+    }
+}
+
+/// Starts the worker, which also drains the queue when its `IndexWorker` is dropped.
+pub fn spawn(opensearch: OpenSearch, config: IndexConfig) -> (IndexQueue, IndexWorker) {
+    let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+    let (shutdown, shutdown_receiver) = oneshot::channel();
+    let queue = IndexQueue {
+        sender,
+        wait_for_refresh: config.wait_for_refresh,
+    };
+    let handle = tokio::spawn(run(opensearch, config, receiver, shutdown_receiver));
+    (queue, IndexWorker { shutdown, handle })
+}
+
+async fn run(
+    opensearch: OpenSearch,
+    config: IndexConfig,
+    mut receiver: mpsc::Receiver<IndexOperation>,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    // Unlike `interval`, the first tick is a whole interval away rather than immediate
+    let mut interval = time::interval_at(
+        Instant::now() + config.flush_interval,
+        config.flush_interval,
+    );
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            operation = receiver.recv() => match operation {
+                // Publishes waiting for their document don't wait for the batch to fill up too
+                Some(operation) => {
+                    batch.push(operation);
+                    if batch.len() >= config.batch_size || config.wait_for_refresh {
+                        flush(&opensearch, &mut batch).await;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => flush(&opensearch, &mut batch).await,
+            _ = &mut shutdown => {
+                // Publishes queueing from now on fail, what's already queued is still received
+                receiver.close();
+                while let Some(operation) = receiver.recv().await {
+                    batch.push(operation);
+                    if batch.len() >= config.batch_size {
+                        flush(&opensearch, &mut batch).await;
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    flush(&opensearch, &mut batch).await;
+}
+
+// Failures are only logged like they were when publishes indexed inline, with the ids of the
+// releases that have to be reindexed
+async fn flush(opensearch: &OpenSearch, batch: &mut Vec<IndexOperation>) {
+    if batch.is_empty() {
+        return;
+    }
+    let release_ids: Vec<i32> = batch.iter().map(|operation| operation.release_id).collect();
+    let mut body: Vec<JsonBody<Value>> = Vec::with_capacity(batch.len() * 2);
+    // Dropped once the bulk request is done, whether it succeeded or not
+    let mut waiting = Vec::new();
+    for operation in batch.drain(..) {
+        body.push(json!({ "index": { "_id": operation.release_id.to_string() } }).into());
+        body.push(operation.document.into());
+        waiting.extend(operation.indexed);
+    }
+
+    let mut bulk = opensearch.bulk(BulkParts::Index("flakes")).body(body);
+    if !waiting.is_empty() {
+        bulk = bulk.refresh(Refresh::WaitFor);
+    }
+    let response = match metrics::time(Backend::OpenSearch, "index_bulk", bulk.send()).await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::error!(?release_ids, "Failed to index releases: {err}");
+            return;
+        }
+    };
+    let status = response.status_code();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        tracing::error!(?release_ids, %status, body, "Failed to index releases");
+        return;
+    }
+
+    // A successful bulk request can still have failed for some of the documents
+    match response.json::<Value>().await {
+        Ok(body) if body["errors"] == true => {
+            let items = body["items"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for item in items {
+                let item = &item["index"];
+                if !item["error"].is_null() {
+                    tracing::error!(id = %item["_id"], error = %item["error"], "Failed to index release");
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(?release_ids, "Failed to decode bulk response: {err}"),
+    }
+}
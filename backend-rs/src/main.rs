@@ -1,58 +1,217 @@
 mod api;
 mod common;
+mod config;
+mod github;
+mod indexer;
+mod metrics;
+mod ratelimit;
+mod server;
+mod webhooks;
 
 use axum::{
     extract::{ConnectInfo, Request},
     middleware::{self, Next},
-    response::IntoResponse,
-    routing::{get, post},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
     Router,
 };
 use opensearch::{
-    http::StatusCode,
-    indices::{IndicesCreateParts, IndicesGetParts},
+    auth::Credentials,
+    cert::CertificateValidation,
+    http::{
+        transport::{SingleNodeConnectionPool, TransportBuilder},
+        StatusCode, Url,
+    },
+    indices::{IndicesCreateParts, IndicesGetMappingParts, IndicesGetParts},
     OpenSearch,
 };
-use sqlx::postgres::PgPoolOptions;
-use std::{env, net::SocketAddr, sync::Arc};
-use tower_http::trace::TraceLayer;
+use serde_json::{json, Value};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, PgConnection,
+};
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use tokio::{signal, sync::Semaphore};
+use tower_http::{
+    normalize_path::NormalizePath,
+    trace::{DefaultOnResponse, OnResponse, TraceLayer},
+};
 use tracing::{field, info_span, Span};
 use tracing_subscriber::{fmt, EnvFilter};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::api::{get_flake, post_publish};
-use crate::common::AppState;
+use crate::api::{
+    delete_index_document, delete_release, get_cadence, get_catalog, get_commit_releases,
+    get_export, get_flake, get_health, get_index_document, get_leaderboard, get_live, get_openapi,
+    get_output_stats, get_outputs_diff, get_owner_search, get_publish_failures, get_random_release,
+    get_readme, get_ready, get_recent_repos, get_recommended, get_release, get_releases_after,
+    get_releases_feed, get_repo_owners, get_repo_search, get_resolve, get_shields, get_tags,
+    get_timeline, get_trending, get_version, get_version_status, post_backfill_descriptions,
+    post_flakes_batch, post_merge_owners, post_publish, post_publish_batch, post_webhook,
+    put_repo_archived, put_repo_recommended, read_repo, FLAKE_INDEX_SCHEMA_VERSION,
+    LEADERBOARD_CACHE_CAPACITY, LEADERBOARD_CACHE_TTL, OUTPUT_STATS_CACHE_TTL, TRENDING_CACHE_TTL,
+};
+use crate::common::{AppState, Cached, CircuitBreaker, LruCache, TtlCache};
+use crate::config::Config;
+use crate::github::{Avatars, GitHub};
+use crate::metrics::get_metrics;
+use crate::ratelimit::{limit_rate, RateLimiter};
 
 #[tokio::main]
 async fn main() {
-    // TODO: read PG and OS host names from env variables
-    // build our application with a single route
     dotenv::dotenv().ok();
     tracing_subscriber::registry()
         .with(fmt::layer().with_target(false))
         .with(EnvFilter::from_default_env())
         .init();
-    let database_url = env::var("DATABASE_URL").expect("Failed to parse database url");
+    let config = Config::from_env();
+    // Migrations can take longer than a query is allowed to, they get their own connection
+    if config.run_migrations {
+        let mut connection = PgConnection::connect(&config.database_url)
+            .await
+            .expect("Failed to connect to the database for migrations");
+        sqlx::migrate!()
+            .run(&mut connection)
+            .await
+            .expect("Failed to run database migrations");
+    }
+    // Dropping a query, because it timed out or its client went away, only stops waiting for it.
+    // The statement timeout has Postgres give up on it too rather than run it to completion.
+    let connect_options = PgConnectOptions::from_str(&config.database_url)
+        .expect("Failed to parse database url")
+        .options([(
+            "statement_timeout",
+            format!("{}s", config.db_timeout.as_secs()),
+        )]);
     let pool = PgPoolOptions::new()
-        .connect(&database_url)
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .connect_with(connect_options)
         .await
         .expect("failed to start database pool");
+    let github = config.verify_github.then(|| {
+        GitHub::new(
+            config.github_api_url.clone(),
+            config.github_token.clone().unwrap(),
+        )
+    });
+    let avatars = config.enrich_avatars.then(|| {
+        Arc::new(Avatars::new(
+            config.github_api_url.clone(),
+            config.github_token.clone(),
+        ))
+    });
+    let opensearch = opensearch_client(
+        config.opensearch_url,
+        config.opensearch_credentials,
+        config.opensearch_cert_validation,
+    );
+    let (index_queue, index_worker) = indexer::spawn(opensearch.clone(), config.index);
+    let (webhooks, webhook_worker) = webhooks::spawn(pool.clone(), config.webhooks);
     let state = Arc::new(AppState {
-        opensearch: OpenSearch::default(),
+        opensearch,
         pool,
+        db_timeout: config.db_timeout,
+        read_only: config.read_only,
+        search_debug: config.search_debug,
+        max_query_length: config.max_query_length,
+        search_fields: config.search_fields,
+        freshness: config.freshness,
+        score_decimals: config.score_decimals,
+        minimum_should_match: config.minimum_should_match,
+        default_list_limit: config.default_list_limit,
+        max_repo_releases: config.max_repo_releases,
+        base_url: config.base_url,
+        trending: Cached::new(TRENDING_CACHE_TTL),
+        output_stats: Cached::new(OUTPUT_STATS_CACHE_TTL),
+        leaderboard: TtlCache::new(LEADERBOARD_CACHE_TTL, LEADERBOARD_CACHE_CAPACITY),
+        readme_max_bytes: config.readme_max_bytes,
+        readme_indexing: config.readme_indexing,
+        search_cache: TtlCache::new(config.search_cache_ttl, config.search_cache_capacity),
+        repo_ids: LruCache::new(config.repo_id_cache_capacity),
+        search_permits: Semaphore::new(config.search_concurrency),
+        search_breaker: CircuitBreaker::new(
+            "opensearch",
+            config.search_breaker_threshold,
+            config.search_breaker_cooldown,
+        ),
+        index_queue,
+        webhooks,
+        github,
+        verify_ancestry: config.verify_ancestry,
+        republish: config.republish,
+        avatars,
+        admin_token: config.admin_token,
+        allowed_publish_owners: config.allowed_publish_owners,
+        output_kinds: config.output_kinds,
+        strict_output_kinds: config.strict_output_kinds,
+        slow_request_threshold: config.slow_request_threshold,
+        rate_limiter: (config.rate_limit_per_minute > 0)
+            .then(|| RateLimiter::new(config.rate_limit_per_minute, Duration::from_secs(60))),
     });
-    let _ = create_flake_index(&state.opensearch).await;
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let text_analysis = config.text_analysis;
+    let _ = create_flake_index(&state.opensearch, &text_analysis).await;
+    // An index created by an older build keeps its mapping, which can make searches behave
+    // differently than this build expects
+    match flake_index_drift(&state.opensearch, &text_analysis).await {
+        Ok(drift) if drift.is_empty() => {}
+        Ok(drift) => {
+            for difference in &drift {
+                tracing::warn!(
+                    "The flakes index mapping differs from the expected one: {difference}"
+                );
+            }
+            assert!(
+                !config.strict_index_mapping,
+                "The flakes index has to be recreated and reindexed, its mapping differs from \
+                    the expected one"
+            );
+        }
+        Err(err) => tracing::warn!("Failed to check the flakes index mapping: {err}"),
+    }
+    // run our app with hyper, listening globally on port 3000 unless BIND_ADDR says otherwise
+    let listener = tokio::net::TcpListener::bind(config.bind_addr)
         .await
         .expect("Failed to bind TCP listener");
-    tracing::info!("Listening on 0.0.0.0:3000");
-    axum::serve(
-        listener,
-        app(state).into_make_service_with_connect_info::<SocketAddr>(),
+    tracing::info!("Listening on {}", config.bind_addr);
+    let pool = state.pool.clone();
+    server::serve(listener, app(state), config.server, shutdown_signal())
+        .await
+        .expect("Failed to start axum");
+    // Releases published right before shutting down still have to be indexed
+    index_worker.shutdown().await;
+    webhook_worker.shutdown().await;
+    // Tells Postgres the connections are going away rather than dropping them
+    pool.close().await;
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+// `OpenSearch::default()` but for the cluster at OPENSEARCH_URL, with credentials when it's secured
+fn opensearch_client(
+    url: Url,
+    credentials: Option<Credentials>,
+    cert_validation: Option<CertificateValidation>,
+) -> OpenSearch {
+    let mut transport = TransportBuilder::new(SingleNodeConnectionPool::new(url));
+    if let Some(credentials) = credentials {
+        transport = transport.auth(credentials);
+    }
+    if let Some(cert_validation) = cert_validation {
+        transport = transport.cert_validation(cert_validation);
+    }
+    OpenSearch::new(
+        transport
+            .build()
+            .expect("Failed to build OpenSearch transport"),
     )
-    .await
-    .expect("Failed to start axum");
 }
 
 async fn add_ip_trace(
@@ -66,11 +225,76 @@ async fn add_ip_trace(
 }
 
 fn app(state: Arc<AppState>) -> Router {
+    let slow_request_threshold = state.slow_request_threshold;
     let api = Router::new()
+        .route(
+            "/admin/index/:id",
+            get(get_index_document).delete(delete_index_document),
+        )
+        .route(
+            "/admin/backfill-descriptions",
+            post(post_backfill_descriptions),
+        )
+        .route("/admin/owner/merge", post(post_merge_owners))
+        .route("/admin/publish-failures", get(get_publish_failures))
+        .route("/admin/repo/:owner/:repo/archived", put(put_repo_archived))
+        .route(
+            "/admin/repo/:owner/:repo/recommended",
+            put(put_repo_recommended),
+        )
+        .route("/catalog", get(get_catalog))
+        .route("/commit/:sha", get(get_commit_releases))
+        .route("/export.ndjson", get(get_export))
         .route("/flake", get(get_flake))
-        .route("/publish", post(post_publish));
-    Router::new()
+        .route("/flake/random", get(get_random_release))
+        .route("/flake/github/:owner/:repo", get(read_repo))
+        .route("/flake/github/:owner/:repo/cadence", get(get_cadence))
+        .route("/flake/github/:owner/:repo/diff", get(get_outputs_diff))
+        .route(
+            "/flake/github/:owner/:repo/recommended",
+            get(get_recommended),
+        )
+        .route("/flake/github/:owner/:repo/search", get(get_repo_search))
+        .route("/flake/github/:owner/:repo/shields.json", get(get_shields))
+        .route("/flake/github/:owner/:repo/timeline", get(get_timeline))
+        .route(
+            "/flake/github/:owner/:repo/:version",
+            get(get_release).delete(delete_release),
+        )
+        .route(
+            "/flake/github/:owner/:repo/:version/readme",
+            get(get_readme),
+        )
+        .route(
+            "/flake/github/:owner/:repo/:version/status",
+            get(get_version_status),
+        )
+        .route("/flakes/batch", post(post_flakes_batch))
+        .route("/health", get(get_health))
+        .route("/leaderboard", get(get_leaderboard))
+        .route("/openapi.json", get(get_openapi))
+        .route("/owner/:owner/search", get(get_owner_search))
+        .route("/publish", post(post_publish))
+        .route("/publish/batch", post(post_publish_batch))
+        .route("/recent-repos", get(get_recent_repos))
+        .route("/repo/:repo", get(get_repo_owners))
+        .route("/resolve", get(get_resolve))
+        .route("/releases", get(get_releases_after))
+        .route("/releases.atom", get(get_releases_feed))
+        .route("/stats/outputs", get(get_output_stats))
+        .route("/tags", get(get_tags))
+        .route("/trending", get(get_trending))
+        .route("/version", get(get_version))
+        .route("/webhooks", post(post_webhook));
+    // Probes for the orchestrator rather than API routes
+    let health = Router::new()
+        .route("/live", get(get_live))
+        .route("/ready", get(get_ready));
+    let app = Router::new()
         .nest("/api", api)
+        .nest("/health", health)
+        .route("/metrics", get(get_metrics))
+        .layer(middleware::from_fn_with_state(state.clone(), limit_rate))
         .layer(middleware::from_fn(add_ip_trace))
         .layer(
             TraceLayer::new_for_http()
@@ -79,11 +303,25 @@ fn app(state: Arc<AppState>) -> Router {
                         info_span!("request", ip = field::Empty, method = %request.method(), uri = %request.uri(), version = ?request.version())
                     }
                 )
+                .on_response(move |response: &Response, latency: Duration, span: &Span| {
+                    // The span carries the method and uri of the request
+                    if latency >= slow_request_threshold {
+                        tracing::warn!(status = %response.status(), latency = ?latency, "Slow request");
+                    } else {
+                        DefaultOnResponse::default().on_response(response, latency, span);
+                    }
+                })
         )
-        .with_state(state)
+        .with_state(state);
+    // `/api/flake/` is served like `/api/flake`, which only works when the path is changed
+    // before routing
+    Router::new().fallback_service(NormalizePath::trim_trailing_slash(app))
 }
 
-async fn create_flake_index(opensearch: &OpenSearch) -> Result<(), opensearch::Error> {
+async fn create_flake_index(
+    opensearch: &OpenSearch,
+    text_analysis: &TextAnalysis,
+) -> Result<(), opensearch::Error> {
     let status = opensearch
         .indices()
         .get(IndicesGetParts::Index(&["flakes"]))
@@ -95,6 +333,7 @@ async fn create_flake_index(opensearch: &OpenSearch) -> Result<(), opensearch::E
         let _ = opensearch
             .indices()
             .create(IndicesCreateParts::Index("flakes"))
+            .body(flake_index_body(text_analysis))
             .send()
             .await?;
     }
@@ -102,40 +341,376 @@ async fn create_flake_index(opensearch: &OpenSearch) -> Result<(), opensearch::E
     Ok(())
 }
 
+// How the mapping of the live `flakes` index differs from the one it would be created with
+async fn flake_index_drift(
+    opensearch: &OpenSearch,
+    text_analysis: &TextAnalysis,
+) -> Result<Vec<String>, opensearch::Error> {
+    let live: Value = opensearch
+        .indices()
+        .get_mapping(IndicesGetMappingParts::Index(&["flakes"]))
+        .send()
+        .await?
+        .error_for_status_code()?
+        .json()
+        .await?;
+
+    let expected = &flake_index_body(text_analysis)["mappings"];
+    let live = &live["flakes"]["mappings"];
+    let mut drift = Vec::new();
+    // Indexes created before versioning have none
+    match live["_meta"]["schema_version"].as_u64() {
+        Some(version) if version == u64::from(FLAKE_INDEX_SCHEMA_VERSION) => {}
+        Some(version) => drift.push(format!(
+            "created with schema version {version} instead of {FLAKE_INDEX_SCHEMA_VERSION}"
+        )),
+        None => drift.push("created without a schema version".to_string()),
+    }
+    mapping_drift("", &expected["properties"], &live["properties"], &mut drift);
+    Ok(drift)
+}
+
+// Compares the type and analyzer of every expected field and its subfields with the live ones.
+// Fields only the live mapping has, like dynamically mapped ones, are left alone.
+fn mapping_drift(prefix: &str, expected: &Value, live: &Value, drift: &mut Vec<String>) {
+    let Some(fields) = expected.as_object() else {
+        return;
+    };
+    for (name, field) in fields {
+        let path = format!("{prefix}{name}");
+        let live_field = &live[name];
+        if live_field.is_null() {
+            drift.push(format!("{path} is not mapped"));
+            continue;
+        }
+        let (expected_type, live_type) = (
+            field["type"].as_str().unwrap_or("object"),
+            live_field["type"].as_str().unwrap_or("object"),
+        );
+        if expected_type != live_type {
+            drift.push(format!(
+                "{path} is a {live_type} instead of a {expected_type}"
+            ));
+            continue;
+        }
+        // Text fields without an analyzer use the standard one
+        if let Some(analyzer) = field["analyzer"].as_str() {
+            let live_analyzer = live_field["analyzer"].as_str().unwrap_or("standard");
+            if analyzer != live_analyzer {
+                drift.push(format!(
+                    "{path} is analyzed with {live_analyzer} instead of {analyzer}"
+                ));
+            }
+        }
+        mapping_drift(
+            &format!("{path}."),
+            &field["fields"],
+            &live_field["fields"],
+            drift,
+        );
+    }
+}
+
+// Analyzers for descriptions and readmes: the default one, OpenSearch's language analyzers, which
+// stem the words of their language, and the ICU one, which needs the `analysis-icu` plugin and
+// handles scripts without spaces between words
+const TEXT_ANALYZERS: &[&str] = &[
+    "standard",
+    "arabic",
+    "armenian",
+    "basque",
+    "bengali",
+    "brazilian",
+    "bulgarian",
+    "catalan",
+    "cjk",
+    "czech",
+    "danish",
+    "dutch",
+    "english",
+    "estonian",
+    "finnish",
+    "french",
+    "galician",
+    "german",
+    "greek",
+    "hindi",
+    "hungarian",
+    "indonesian",
+    "irish",
+    "italian",
+    "latvian",
+    "lithuanian",
+    "norwegian",
+    "persian",
+    "portuguese",
+    "romanian",
+    "russian",
+    "sorani",
+    "spanish",
+    "swedish",
+    "thai",
+    "turkish",
+    "icu_analyzer",
+];
+
+// Languages of `TEXT_ANALYZERS` OpenSearch has a stemmer for, the others only split words
+const STEMMED_LANGUAGES: &[&str] = &[
+    "arabic",
+    "armenian",
+    "basque",
+    "bengali",
+    "brazilian",
+    "bulgarian",
+    "catalan",
+    "czech",
+    "danish",
+    "dutch",
+    "english",
+    "estonian",
+    "finnish",
+    "french",
+    "galician",
+    "german",
+    "greek",
+    "hindi",
+    "hungarian",
+    "indonesian",
+    "irish",
+    "italian",
+    "latvian",
+    "lithuanian",
+    "norwegian",
+    "persian",
+    "portuguese",
+    "romanian",
+    "russian",
+    "sorani",
+    "spanish",
+    "swedish",
+    "turkish",
+];
+
+/// How descriptions and readmes are analyzed, which only applies when the index is created.
+struct TextAnalysis {
+    // One of `TEXT_ANALYZERS`
+    analyzer: String,
+    // Words like `nix` that are in most readmes, left out on top of the analyzer's own
+    stopwords: Vec<String>,
+    // Shorter words are left out
+    min_token_length: usize,
+}
+
+impl TextAnalysis {
+    fn new(analyzer: &str) -> Self {
+        TextAnalysis {
+            analyzer: analyzer.to_string(),
+            stopwords: Vec::new(),
+            min_token_length: 1,
+        }
+    }
+
+    // Built-in analyzers can't be extended, so stopwords or a minimum length take a custom one
+    fn is_custom(&self) -> bool {
+        !self.stopwords.is_empty() || self.min_token_length > 1
+    }
+
+    fn field_analyzer(&self) -> &str {
+        if self.is_custom() {
+            "flake_text"
+        } else {
+            &self.analyzer
+        }
+    }
+
+    // Rebuilds the chosen analyzer from its parts, the language's stopwords and stemmer, without
+    // the extras of some language analyzers like handling elisions
+    fn custom_analyzer(&self) -> (Value, Value) {
+        let language = self.analyzer.as_str();
+        let tokenizer = if language == "icu_analyzer" {
+            "icu_tokenizer"
+        } else {
+            "standard"
+        };
+        let mut filters = json!({
+            "flake_stopwords": { "type": "stop", "stopwords": self.stopwords },
+            "flake_min_length": { "type": "length", "min": self.min_token_length },
+        });
+        let mut chain = vec!["lowercase"];
+        if !matches!(language, "standard" | "icu_analyzer") {
+            filters["flake_language_stopwords"] =
+                json!({ "type": "stop", "stopwords": format!("_{language}_") });
+            chain.push("flake_language_stopwords");
+        }
+        chain.extend(["flake_stopwords", "flake_min_length"]);
+        if STEMMED_LANGUAGES.contains(&language) {
+            filters["flake_stemmer"] = json!({ "type": "stemmer", "language": language });
+            chain.push("flake_stemmer");
+        }
+        let analyzer = json!({ "type": "custom", "tokenizer": tokenizer, "filter": chain });
+        (analyzer, filters)
+    }
+}
+
+// Settings and mappings of the `flakes` index. An existing index keeps the ones it was created
+// with, it has to be recreated and reindexed to pick up changes. Changes have to bump
+// `FLAKE_INDEX_SCHEMA_VERSION`.
+// Descriptions and readmes are analyzed as configured by `text_analysis`.
+fn flake_index_body(text_analysis: &TextAnalysis) -> Value {
+    let text_analyzer = text_analysis.field_analyzer();
+    let mut body = json!({
+        "settings": {
+            "analysis": {
+                // Splits Nix identifiers like `python3Packages.requests` or `home-manager` into
+                // their words, so that `requests` or `packages` match them
+                "tokenizer": {
+                    "flake_identifier": {
+                        "type": "pattern",
+                        "pattern": "([^\\p{L}\\d]+)|(?<=\\D)(?=\\d)|(?<=\\d)(?=\\D)|(?<=[\\p{L}&&[^\\p{Lu}]])(?=\\p{Lu})|(?<=\\p{Lu})(?=\\p{Lu}[\\p{L}&&[^\\p{Lu}]])",
+                    }
+                },
+                "analyzer": {
+                    "flake_identifier": {
+                        "type": "custom",
+                        "tokenizer": "flake_identifier",
+                        "filter": ["lowercase"],
+                    }
+                },
+            }
+        },
+        "mappings": {
+            "_meta": { "schema_version": FLAKE_INDEX_SCHEMA_VERSION },
+            "properties": {
+                // The standard output categories (packages, overlays, ...) a release provides
+                "provides": { "type": "keyword" },
+                // SPDX license id
+                "license": { "type": "keyword" },
+                // Nix systems like `x86_64-linux`
+                "systems": { "type": "keyword" },
+                // Keywords like `cli` picked by the author
+                "tags": { "type": "keyword" },
+                // `owner/repo`
+                "full_name": { "type": "keyword" },
+                // Only set once a repo was archived
+                "archived": { "type": "boolean" },
+                "created_at": { "type": "date" },
+                "repo": {
+                    "type": "text",
+                    "analyzer": "flake_identifier",
+                    "fields": { "keyword": { "type": "keyword", "ignore_above": 256 } },
+                },
+                // `nix flake show --json` of the release as a string
+                "outputs": { "type": "text", "analyzer": "flake_identifier" },
+                "description": { "type": "text", "analyzer": text_analyzer },
+                "readme": { "type": "text", "analyzer": text_analyzer },
+            }
+        }
+    });
+    if text_analysis.is_custom() {
+        let (analyzer, filters) = text_analysis.custom_analyzer();
+        body["settings"]["analysis"]["analyzer"]["flake_text"] = analyzer;
+        body["settings"]["analysis"]["filter"] = filters;
+    }
+    body
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use axum::http::StatusCode;
+    use crate::api::{
+        FreshnessDecay, ReadmeIndexing, Republish, FLAKE_OUTPUTS, MAX_LIST_LIMIT, SEARCH_FIELDS,
+    };
+    use crate::indexer::{IndexConfig, IndexWorker};
+    use crate::server::ServerConfig;
+    use crate::webhooks::{WebhookConfig, WebhookWorker};
+    use axum::{extract::Path, http::StatusCode};
+    use opensearch::{indices::IndicesDeleteParts, params::Refresh, IndexParts, SearchParts};
+    use serde_json::Value;
+    use sqlx::PgPool;
     use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
     use tokio::net::TcpListener;
     use tokio::task::JoinHandle;
-    use url::Url;
 
     pub struct TestApp {
         pub base_url: Url,
         pub client: reqwest::Client,
+        pub pool: PgPool,
         server: JoinHandle<()>,
+        _index_worker: IndexWorker,
+        _webhook_worker: WebhookWorker,
     }
 
     impl TestApp {
         pub async fn new() -> TestApp {
+            TestApp::with_state(|_| {}).await
+        }
+
+        pub async fn with_state(configure: impl FnOnce(&mut AppState)) -> TestApp {
             let database_url = env::var("DATABASE_URL").unwrap();
             let pool = PgPoolOptions::new().connect(&database_url).await.unwrap();
-            let state = Arc::new(AppState {
+            let (index_queue, index_worker) =
+                indexer::spawn(OpenSearch::default(), IndexConfig::default());
+            // Retried quickly, for tests to see failed deliveries succeed
+            let (webhooks, webhook_worker) = webhooks::spawn(
+                pool.clone(),
+                WebhookConfig {
+                    max_attempts: 3,
+                    retry_backoff: Duration::from_millis(50),
+                    timeout: Duration::from_secs(1),
+                    poll_interval: Duration::from_millis(50),
+                },
+            );
+            let mut state = AppState {
                 opensearch: OpenSearch::default(),
-                pool,
-            });
-            let app = app(state);
+                pool: pool.clone(),
+                db_timeout: Duration::from_secs(5),
+                read_only: false,
+                search_debug: false,
+                max_query_length: 256,
+                search_fields: SEARCH_FIELDS.to_vec(),
+                freshness: FreshnessDecay::default(),
+                score_decimals: 3,
+                minimum_should_match: None,
+                default_list_limit: 100,
+                max_repo_releases: 500,
+                base_url: "https://flakestry.dev".to_string(),
+                trending: Cached::new(TRENDING_CACHE_TTL),
+                output_stats: Cached::new(OUTPUT_STATS_CACHE_TTL),
+                leaderboard: TtlCache::new(Duration::ZERO, 0),
+                readme_max_bytes: 64 * 1024,
+                readme_indexing: ReadmeIndexing::All,
+                search_cache: TtlCache::new(Duration::ZERO, 0),
+                repo_ids: LruCache::new(0),
+                search_permits: Semaphore::new(Semaphore::MAX_PERMITS),
+                search_breaker: CircuitBreaker::new("opensearch", 0, Duration::ZERO),
+                index_queue,
+                webhooks,
+                github: None,
+                verify_ancestry: false,
+                republish: Republish::Reject,
+                avatars: None,
+                admin_token: None,
+                allowed_publish_owners: Vec::new(),
+                output_kinds: FLAKE_OUTPUTS.iter().map(|kind| kind.to_string()).collect(),
+                strict_output_kinds: false,
+                slow_request_threshold: Duration::from_secs(1),
+                rate_limiter: None,
+            };
+            configure(&mut state);
+            let app = app(Arc::new(state));
 
             let listener = TcpListener::bind("127.0.0.1:0")
                 .await
                 .expect("Could not bind ephemeral socket");
             let addr = listener.local_addr().unwrap();
             let server = tokio::spawn(async move {
-                axum::serve(
+                server::serve(
                     listener,
-                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                    app,
+                    ServerConfig::default(),
+                    std::future::pending(),
                 )
                 .await
                 .unwrap();
@@ -143,8 +718,15 @@ mod tests {
 
             TestApp {
                 base_url: Url::parse(&format!("http://{addr}")).unwrap(),
-                client: reqwest::Client::new(),
+                // Redirects are asserted on rather than followed
+                client: reqwest::Client::builder()
+                    .redirect(reqwest::redirect::Policy::none())
+                    .build()
+                    .unwrap(),
+                pool,
                 server,
+                _index_worker: index_worker,
+                _webhook_worker: webhook_worker,
             }
         }
 
@@ -154,6 +736,27 @@ mod tests {
             let url = base.parse(path).unwrap();
             self.client.get(url)
         }
+
+        pub fn post(&self, path: &str) -> reqwest::RequestBuilder {
+            let base_url = Some(&self.base_url);
+            let base = Url::options().base_url(base_url);
+            let url = base.parse(path).unwrap();
+            self.client.post(url)
+        }
+
+        pub fn delete(&self, path: &str) -> reqwest::RequestBuilder {
+            let base_url = Some(&self.base_url);
+            let base = Url::options().base_url(base_url);
+            let url = base.parse(path).unwrap();
+            self.client.delete(url)
+        }
+
+        pub fn put(&self, path: &str) -> reqwest::RequestBuilder {
+            let base_url = Some(&self.base_url);
+            let base = Url::options().base_url(base_url);
+            let url = base.parse(path).unwrap();
+            self.client.put(url)
+        }
     }
 
     impl Drop for TestApp {
@@ -162,10 +765,190 @@ mod tests {
         }
     }
 
+    // Insert an owner and repo with a release per version, leaving readme and description NULL
+    async fn seed_repo(pool: &PgPool, owner: &str, repo: &str, versions: &[&str]) -> i32 {
+        let repo_id: i32 = sqlx::query_scalar(
+            "WITH owner AS (INSERT INTO githubowner (name, created_at) VALUES ($1, now()) RETURNING id) \
+                INSERT INTO githubrepo (name, owner_id, created_at) SELECT $2, id, now() FROM owner RETURNING id",
+        )
+        .bind(owner)
+        .bind(repo)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+
+        for version in versions {
+            sqlx::query(
+                "INSERT INTO release (repo_id, version, commit, created_at) VALUES ($1, $2, '123', '2000-01-01')",
+            )
+            .bind(repo_id)
+            .bind(version)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        repo_id
+    }
+
+    async fn remove_owner(pool: &PgPool, owner: &str) {
+        sqlx::query(
+            "DELETE FROM release USING githubrepo, githubowner \
+                WHERE release.repo_id = githubrepo.id AND githubrepo.owner_id = githubowner.id AND githubowner.name = $1",
+        )
+        .bind(owner)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "DELETE FROM githubrepo USING githubowner \
+                WHERE githubrepo.owner_id = githubowner.id AND githubowner.name = $1",
+        )
+        .bind(owner)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query("DELETE FROM githubowner WHERE name = $1")
+            .bind(owner)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // An OpenSearch client talking to a stub cluster that answers every request with an error
+    async fn failing_opensearch() -> OpenSearch {
+        stub_opensearch(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": { "type": "index_not_found_exception" }, "status": 500 }),
+        )
+        .await
+    }
+
+    // An OpenSearch client talking to a stub cluster that answers every request with `body`
+    async fn stub_opensearch(status: StatusCode, body: Value) -> OpenSearch {
+        counting_opensearch(status, body).await.0
+    }
+
+    // Like `stub_opensearch`, also counting the requests the stub cluster got
+    async fn counting_opensearch(
+        status: StatusCode,
+        body: Value,
+    ) -> (OpenSearch, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let stub = Router::new().fallback(move || async move {
+            counter.fetch_add(1, AtomicOrdering::SeqCst);
+            (status, axum::Json(body))
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        (OpenSearch::new(transport), requests)
+    }
+
+    async fn release_id(pool: &PgPool, owner: &str, repo: &str, version: &str) -> i32 {
+        sqlx::query_scalar(
+            "SELECT release.id FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                WHERE githubowner.name = $1 AND githubrepo.name = $2 AND release.version = $3",
+        )
+        .bind(owner)
+        .bind(repo)
+        .bind(version)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    fn versions(body: &Value) -> Vec<&str> {
+        body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| release["version"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        for (grace, answered) in [(Duration::from_secs(5), true), (Duration::ZERO, false)] {
+            let (started, mut in_flight) = tokio::sync::mpsc::channel(1);
+            let app = Router::new().route(
+                "/",
+                get(move || async move {
+                    started.send(()).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    "ok"
+                }),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let config = ServerConfig {
+                shutdown_grace: grace,
+                ..ServerConfig::default()
+            };
+            let (shut_down, shutdown) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(server::serve(listener, app, config, async {
+                let _ = shutdown.await;
+            }));
+
+            let request = tokio::spawn(reqwest::get(format!("http://{addr}/")));
+            in_flight.recv().await.unwrap();
+            shut_down.send(()).unwrap();
+            tokio::time::timeout(Duration::from_secs(5), server)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+
+            // The request in flight is answered within the grace period, new ones are refused
+            let response = request.await.unwrap();
+            assert_eq!(response.is_ok(), answered, "{grace:?}");
+            assert!(reqwest::get(format!("http://{addr}/")).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_http2() {
+        for http2 in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let config = ServerConfig {
+                http2,
+                http2_max_concurrent_streams: Some(10),
+                tcp_keepalive: Some(Duration::from_secs(60)),
+                shutdown_grace: Duration::ZERO,
+            };
+            let app = Router::new().route("/", get(|| async { "ok" }));
+            let server = tokio::spawn(server::serve(listener, app, config, std::future::pending()));
+
+            let client = reqwest::Client::builder()
+                .http2_prior_knowledge()
+                .build()
+                .unwrap();
+            let response = client.get(format!("http://{addr}/")).send().await;
+            server.abort();
+
+            match response {
+                Ok(response) => {
+                    assert!(http2);
+                    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+                }
+                Err(_) => assert!(!http2),
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_get_flake_with_params() {
         let app = TestApp::new().await;
-        let expected_response = "{\"releases\":[{\"owner\":\"nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\"}],\"count\":1,\"query\":\"search\"}";
+        let expected_response = "{\"items\":[{\"owner\":\"nix-community\",\"owner_url\":\"https://github.com/nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\",\"updated_at\":\"2024-07-12T23:08:41.029566\"}],\"total\":1,\"limit\":10,\"offset\":0,\"releases\":[{\"owner\":\"nix-community\",\"owner_url\":\"https://github.com/nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\",\"updated_at\":\"2024-07-12T23:08:41.029566\"}],\"count\":1,\"query\":\"search\",\"source\":\"search\",\"total_relation\":\"eq\",\"facets\":{\"owners\":[{\"value\":\"nix-community\",\"count\":1}],\"outputs\":[]},\"warnings\":[]}";
 
         let response = app.get("/api/flake?q=search").send().await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
@@ -174,10 +957,335 @@ mod tests {
         assert_eq!(body, expected_response);
     }
 
+    #[tokio::test]
+    async fn test_flake_index_drift() {
+        let expected = flake_index_body(&TextAnalysis::new("english"))["mappings"].clone();
+        let opensearch = stub_opensearch(
+            StatusCode::OK,
+            json!({ "flakes": { "mappings": expected } }),
+        )
+        .await;
+        assert_eq!(
+            flake_index_drift(&opensearch, &TextAnalysis::new("english"))
+                .await
+                .unwrap(),
+            Vec::<String>::new()
+        );
+
+        // An index from before the explicit mapping, with everything mapped dynamically
+        let dynamic = json!({ "type": "text", "fields": { "keyword": { "type": "keyword" } } });
+        let live = json!({ "flakes": { "mappings": { "properties": {
+            "owner": dynamic,
+            "repo": dynamic,
+            "description": { "type": "text" },
+            "readme": { "type": "text", "analyzer": "english" },
+            "created_at": { "type": "date" },
+        } } } });
+        let opensearch = stub_opensearch(StatusCode::OK, live).await;
+        let mut drift = flake_index_drift(&opensearch, &TextAnalysis::new("english"))
+            .await
+            .unwrap();
+        drift.sort();
+        assert_eq!(
+            drift,
+            [
+                "archived is not mapped",
+                "created without a schema version",
+                "description is analyzed with standard instead of english",
+                "full_name is not mapped",
+                "license is not mapped",
+                "outputs is not mapped",
+                "provides is not mapped",
+                "repo is analyzed with standard instead of flake_identifier",
+                "systems is not mapped",
+                "tags is not mapped",
+            ]
+        );
+
+        assert!(
+            flake_index_drift(&failing_opensearch().await, &TextAnalysis::new("english"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flake_index_analyzer() {
+        let opensearch = OpenSearch::default();
+        let index = "flakes-analyzer-test";
+        let _ = opensearch
+            .indices()
+            .delete(IndicesDeleteParts::Index(&[index]))
+            .send()
+            .await;
+        let response = opensearch
+            .indices()
+            .create(IndicesCreateParts::Index(index))
+            .body(flake_index_body(&TextAnalysis::new("standard")))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status_code().is_success());
+
+        let outputs =
+            json!({ "legacyPackages": { "x86_64-linux": { "python3Packages.requests": {} } } });
+        opensearch
+            .index(IndexParts::IndexId(index, "1"))
+            .body(json!({ "repo": "nixpkgs", "outputs": outputs.to_string() }))
+            .refresh(Refresh::True)
+            .send()
+            .await
+            .unwrap();
+
+        let mut totals = Vec::new();
+        for query in [
+            "requests",
+            "python3Packages",
+            "packages",
+            "python3packages.requests",
+        ] {
+            let response = opensearch
+                .search(SearchParts::Index(&[index]))
+                .body(json!({ "query": { "match": { "outputs": query } } }))
+                .send()
+                .await
+                .unwrap();
+            let body: Value = response.json().await.unwrap();
+            totals.push(body["hits"]["total"]["value"].as_i64().unwrap());
+        }
+        let _ = opensearch
+            .indices()
+            .delete(IndicesDeleteParts::Index(&[index]))
+            .send()
+            .await;
+
+        assert_eq!(totals, [1, 1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_then_search() {
+        let config = IndexConfig {
+            wait_for_refresh: true,
+            ..IndexConfig::default()
+        };
+        let (queue, worker) = indexer::spawn(OpenSearch::default(), config);
+        let app = TestApp::with_state(|state| state.index_queue = queue).await;
+
+        // Publishing with `INDEX_REFRESH=wait_for` answers once the release is searchable
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-publish-search",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "description": "Bindings for the quetzalcoatl toolkit",
+            }))
+            .send()
+            .await
+            .unwrap();
+        let published = response.status();
+        let response = app.get("/api/flake?q=quetzalcoatl").send().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+
+        worker.shutdown().await;
+        let release_id = release_id(&app.pool, "test-publish-search", "flake", "1.0.0").await;
+        let _ = OpenSearch::default()
+            .delete(opensearch::DeleteParts::IndexId(
+                "flakes",
+                &release_id.to_string(),
+            ))
+            .refresh(Refresh::True)
+            .send()
+            .await;
+        remove_owner(&app.pool, "test-publish-search").await;
+
+        assert_eq!(published, StatusCode::CREATED);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["source"], "search");
+        assert_eq!(body["items"][0]["owner"], "test-publish-search");
+        assert_eq!(body["items"][0]["version"], "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_exact() {
+        let app = TestApp::new().await;
+
+        // One transposition away from `search`, which fuzzy matching tolerates
+        let response = app.get("/api/flake?q=saerch").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["items"][0]["repo"], "home-manager");
+
+        let response = app
+            .get("/api/flake?q=saerch&exact=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 0);
+        let response = app
+            .get("/api/flake?q=search&exact=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["items"][0]["repo"], "home-manager");
+
+        // Together with the owner filter
+        let path = "/api/owner/nix-community/search?q=saerch";
+        let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+        assert_eq!(body["total"], 1);
+        let path = "/api/owner/nix-community/search?q=saerch&exact=true";
+        let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+        assert_eq!(body["total"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_flake_index_text_analyzer() {
+        let opensearch = OpenSearch::default();
+        let mut totals = Vec::new();
+        for text_analyzer in ["standard", "french"] {
+            let index = format!("flakes-{text_analyzer}-test");
+            let _ = opensearch
+                .indices()
+                .delete(IndicesDeleteParts::Index(&[&index]))
+                .send()
+                .await;
+            let response = opensearch
+                .indices()
+                .create(IndicesCreateParts::Index(&index))
+                .body(flake_index_body(&TextAnalysis::new(text_analyzer)))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status_code().is_success());
+
+            opensearch
+                .index(IndexParts::IndexId(&index, "1"))
+                .body(json!({ "description": "Gestionnaire de paquets déclaratif" }))
+                .refresh(Refresh::True)
+                .send()
+                .await
+                .unwrap();
+            for query in ["déclaratif", "déclaratifs"] {
+                let response = opensearch
+                    .search(SearchParts::Index(&[&index]))
+                    .body(json!({ "query": { "match": { "description": query } } }))
+                    .send()
+                    .await
+                    .unwrap();
+                let body: Value = response.json().await.unwrap();
+                totals.push(body["hits"]["total"]["value"].as_i64().unwrap());
+            }
+            let _ = opensearch
+                .indices()
+                .delete(IndicesDeleteParts::Index(&[&index]))
+                .send()
+                .await;
+        }
+
+        // Only the French analyzer knows `déclaratifs` is the plural of `déclaratif`
+        assert_eq!(totals, [1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_flake_index_custom_analyzer() {
+        let body = flake_index_body(&TextAnalysis::new("english"));
+        assert_eq!(
+            body["mappings"]["properties"]["readme"]["analyzer"],
+            "english"
+        );
+        assert!(body["settings"]["analysis"]["filter"].is_null());
+
+        let body = flake_index_body(&TextAnalysis {
+            stopwords: vec!["nix".to_string()],
+            min_token_length: 3,
+            ..TextAnalysis::new("english")
+        });
+        let analysis = &body["settings"]["analysis"];
+        assert_eq!(
+            body["mappings"]["properties"]["readme"]["analyzer"],
+            "flake_text"
+        );
+        assert_eq!(
+            analysis["analyzer"]["flake_text"]["filter"],
+            json!([
+                "lowercase",
+                "flake_language_stopwords",
+                "flake_stopwords",
+                "flake_min_length",
+                "flake_stemmer"
+            ])
+        );
+        assert_eq!(
+            analysis["filter"]["flake_stopwords"]["stopwords"],
+            json!(["nix"])
+        );
+        assert_eq!(analysis["filter"]["flake_min_length"]["min"], 3);
+        assert_eq!(analysis["filter"]["flake_stemmer"]["language"], "english");
+    }
+
+    #[tokio::test]
+    async fn test_flake_index_stopwords() {
+        let opensearch = OpenSearch::default();
+        let mut top_hits = Vec::new();
+        for stopwords in [vec![], vec!["nix".to_string()]] {
+            let index = format!("flakes-stopwords-{}-test", stopwords.len());
+            let _ = opensearch
+                .indices()
+                .delete(IndicesDeleteParts::Index(&[&index]))
+                .send()
+                .await;
+            let text_analysis = TextAnalysis {
+                stopwords,
+                ..TextAnalysis::new("standard")
+            };
+            let response = opensearch
+                .indices()
+                .create(IndicesCreateParts::Index(&index))
+                .body(flake_index_body(&text_analysis))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status_code().is_success());
+
+            for (id, readme) in [
+                ("1", "Nix nix nix, built with nix for nix users"),
+                ("2", "A home manager module, installed with nix"),
+            ] {
+                opensearch
+                    .index(IndexParts::IndexId(&index, id))
+                    .body(json!({ "readme": readme }))
+                    .refresh(Refresh::True)
+                    .send()
+                    .await
+                    .unwrap();
+            }
+            let response = opensearch
+                .search(SearchParts::Index(&[&index]))
+                .body(json!({ "query": { "match": { "readme": "nix home" } } }))
+                .send()
+                .await
+                .unwrap();
+            let body: Value = response.json().await.unwrap();
+            top_hits.push(body["hits"]["hits"][0]["_id"].as_str().unwrap().to_string());
+            let _ = opensearch
+                .indices()
+                .delete(IndicesDeleteParts::Index(&[&index]))
+                .send()
+                .await;
+        }
+
+        // Repeating `nix` wins until it's a stopword, then the readme about home managers does
+        assert_eq!(top_hits, ["1", "2"]);
+    }
+
     #[tokio::test]
     async fn test_get_flake_with_params_no_result() {
         let app = TestApp::new().await;
-        let expected_response = "{\"releases\":[],\"count\":0,\"query\":\"nothing\"}";
+        let expected_response = "{\"items\":[],\"total\":0,\"limit\":10,\"offset\":0,\"releases\":[],\"count\":0,\"query\":\"nothing\",\"source\":\"search\",\"total_relation\":\"eq\",\"facets\":{\"owners\":[],\"outputs\":[]},\"warnings\":[]}";
 
         let response = app.get("/api/flake?q=nothing").send().await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
@@ -189,7 +1297,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_flake_without_params() {
         let app = TestApp::new().await;
-        let expected_response = "{\"releases\":[{\"owner\":\"nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\"},{\"owner\":\"nixos\",\"repo\":\"nixpkgs\",\"version\":\"22.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\"},{\"owner\":\"nixos\",\"repo\":\"nixpkgs\",\"version\":\"23.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\"}],\"count\":3,\"query\":null}";
+        let expected_response = "{\"items\":[{\"owner\":\"nix-community\",\"owner_url\":\"https://github.com/nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\",\"updated_at\":\"2024-07-12T23:08:41.029566\"},{\"owner\":\"nixos\",\"owner_url\":\"https://github.com/nixos\",\"repo\":\"nixpkgs\",\"version\":\"23.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\",\"updated_at\":\"2024-07-12T23:08:41.005518\"},{\"owner\":\"nixos\",\"owner_url\":\"https://github.com/nixos\",\"repo\":\"nixpkgs\",\"version\":\"22.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\",\"updated_at\":\"2024-07-12T23:08:41.005518\"}],\"total\":3,\"limit\":100,\"offset\":0,\"releases\":[{\"owner\":\"nix-community\",\"owner_url\":\"https://github.com/nix-community\",\"repo\":\"home-manager\",\"version\":\"23.05\",\"description\":\"\",\"created_at\":\"2024-07-12T23:08:41.029566\",\"updated_at\":\"2024-07-12T23:08:41.029566\"},{\"owner\":\"nixos\",\"owner_url\":\"https://github.com/nixos\",\"repo\":\"nixpkgs\",\"version\":\"23.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\",\"updated_at\":\"2024-07-12T23:08:41.005518\"},{\"owner\":\"nixos\",\"owner_url\":\"https://github.com/nixos\",\"repo\":\"nixpkgs\",\"version\":\"22.05\",\"description\":\"nixpkgs is official package collection\",\"created_at\":\"2024-07-12T23:08:41.005518\",\"updated_at\":\"2024-07-12T23:08:41.005518\"}],\"count\":3,\"query\":null,\"source\":\"database\",\"total_relation\":\"eq\",\"warnings\":[]}";
 
         let response = app.get("/api/flake").send().await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
@@ -197,4 +1305,4954 @@ mod tests {
         let body = response.text().await.unwrap();
         assert_eq!(body, expected_response);
     }
+
+    #[tokio::test]
+    async fn test_read_repo() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), vec!["23.05", "22.05"]);
+        // Clients from before the pagination envelope read the releases under `releases`
+        assert_eq!(body["releases"], body["items"]);
+        assert_eq!(body["meta"]["owner_repos"], 2);
+        assert_eq!(body["meta"]["releases"], 2);
+        assert_eq!(body["items"][0]["flake_ref"], "github:nixos/nixpkgs/123");
+        assert_eq!(
+            body["items"][0]["nix_run"],
+            "nix run github:nixos/nixpkgs/123"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_repo_owners() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "fork-origin",
+            "forked-flake",
+            &["1.9.0", "1.10.0"],
+        )
+        .await;
+        seed_repo(&app.pool, "fork-copy", "forked-flake", &["1.0.0"]).await;
+
+        let response = app.get("/api/repo/forked-flake").send().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "fork-origin").await;
+        remove_owner(&app.pool, "fork-copy").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["items"],
+            json!([
+                { "owner": "fork-origin", "latest_version": "1.10.0", "releases": 2 },
+                { "owner": "fork-copy", "latest_version": "1.0.0", "releases": 1 },
+            ])
+        );
+        assert_eq!(body["total"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_resolve() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "test-resolve", "flake", &["1.9.0", "1.10.0"]).await;
+
+        let mut resolved = Vec::new();
+        for flake_ref in [
+            "github:test-resolve/flake",
+            "github:Test-Resolve/flake/1.9.0",
+            "github:test-resolve/flake?rev=123",
+            "github:test-resolve/flake/2.0.0",
+            "github:test-resolve/unknown",
+            "github:test-resolve",
+            "gitlab:test-resolve/flake",
+        ] {
+            let response = app
+                .get("/api/resolve")
+                .query(&[("ref", flake_ref)])
+                .send()
+                .await
+                .unwrap();
+            let status = response.status();
+            let version = match status {
+                StatusCode::OK => response.json::<Value>().await.unwrap()["version"].clone(),
+                _ => Value::Null,
+            };
+            resolved.push((status, version));
+        }
+        let missing = app.get("/api/resolve").send().await.unwrap().status();
+        remove_owner(&app.pool, "test-resolve").await;
+
+        assert_eq!(
+            resolved,
+            [
+                (StatusCode::OK, json!("1.10.0")),
+                (StatusCode::OK, json!("1.9.0")),
+                (StatusCode::OK, json!("1.10.0")),
+                (StatusCode::NOT_FOUND, Value::Null),
+                (StatusCode::NOT_FOUND, Value::Null),
+                (StatusCode::BAD_REQUEST, Value::Null),
+                (StatusCode::BAD_REQUEST, Value::Null),
+            ]
+        );
+        assert_eq!(missing, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_version_status() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "version-status",
+            "flake",
+            &["1.9.0", "1.10.0", "v1.2"],
+        )
+        .await;
+
+        let mut statuses = Vec::new();
+        for version in ["1.9.0", "1.10.0", "v1.2", "2.0.0"] {
+            let response = app
+                .get(&format!(
+                    "/api/flake/github/version-status/flake/{version}/status"
+                ))
+                .send()
+                .await
+                .unwrap();
+            let status = response.status();
+            let body: Value = response.json().await.unwrap();
+            statuses.push((status, body));
+        }
+        remove_owner(&app.pool, "version-status").await;
+
+        assert_eq!(
+            statuses[0],
+            (
+                StatusCode::OK,
+                json!({ "is_latest": false, "latest": "1.10.0" })
+            )
+        );
+        assert_eq!(
+            statuses[1],
+            (
+                StatusCode::OK,
+                json!({ "is_latest": true, "latest": "1.10.0" })
+            )
+        );
+        assert_eq!(statuses[2].1["is_latest"], false);
+        assert_eq!(statuses[3].0, StatusCode::NOT_FOUND);
+
+        // Versions parsing the same are told apart by when they were published, like on the
+        // repo page, and legacy versions that don't parse are never the latest
+        let repo_id = seed_repo(
+            &app.pool,
+            "version-status-tie",
+            "flake",
+            &["1.2", "1.2.0", "nightly"],
+        )
+        .await;
+        sqlx::query(
+            "UPDATE release SET created_at = created_at + interval '1 day' \
+                WHERE repo_id = $1 AND version = '1.2.0'",
+        )
+        .bind(repo_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let mut tied = Vec::new();
+        for version in ["1.2", "1.2.0", "nightly"] {
+            let body: Value = app
+                .get(&format!(
+                    "/api/flake/github/version-status-tie/flake/{version}/status"
+                ))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            tied.push(body);
+        }
+        remove_owner(&app.pool, "version-status-tie").await;
+        assert_eq!(
+            tied,
+            [
+                json!({ "is_latest": false, "latest": "1.2.0" }),
+                json!({ "is_latest": true, "latest": "1.2.0" }),
+                json!({ "is_latest": false, "latest": "1.2.0" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_timing() {
+        let app = TestApp::new().await;
+
+        let response = app.get("/api/flake").send().await.unwrap();
+        let timing = response.headers()["server-timing"].to_str().unwrap();
+        assert!(timing.starts_with("db;dur="), "{timing}");
+        assert!(!timing.contains("search"), "{timing}");
+
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        let timing = response.headers()["server-timing"].to_str().unwrap();
+        assert!(timing.contains(", search;dur="), "{timing}");
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.headers().contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_readme_preview() {
+        let app = TestApp::with_state(|state| state.readme_max_bytes = 8).await;
+        seed_repo(&app.pool, "readme-preview", "flake", &["1.0.0"]).await;
+        sqlx::query("UPDATE release SET readme = '# A long readme' WHERE id = $1")
+            .bind(release_id(&app.pool, "readme-preview", "flake", "1.0.0").await)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let mut readmes = Vec::new();
+        for query in ["", "?readme=preview"] {
+            let response = app
+                .get(&format!("/api/flake/github/readme-preview/flake{query}"))
+                .send()
+                .await
+                .unwrap();
+            let body: Value = response.json().await.unwrap();
+            readmes.push(body["items"][0]["readme"].clone());
+        }
+        let response = app
+            .get("/api/flake/github/readme-preview/flake?readme=short")
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "readme-preview").await;
+
+        assert_eq!(
+            readmes,
+            ["# A long readme", "# A long\n\n[readme truncated]"]
+        );
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_not_found() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake/github/nixos/does-not-exist")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "detail": "Not Found" }));
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_release_without_readme() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "test-null-readme", "no-readme", &["1.0.0"]).await;
+
+        let response = app
+            .get("/api/flake/github/test-null-readme/no-readme")
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+
+        remove_owner(&app.pool, "test-null-readme").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"][0]["version"], "1.0.0");
+        assert_eq!(body["items"][0]["readme"], "");
+    }
+
+    #[tokio::test]
+    async fn test_publish_read_only() {
+        let app = TestApp::with_state(|state| state.read_only = true).await;
+
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-read-only",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "300");
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_error_bodies() {
+        let app = TestApp::with_state(|state| {
+            state.admin_token = Some("secret".to_string());
+            state.read_only = true;
+        })
+        .await;
+
+        let unauthorized = app
+            .get("/api/admin/publish-failures")
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        let unauthorized_status = unauthorized.status();
+        let unauthorized: Value = unauthorized.json().await.unwrap();
+        let read_only = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-error-bodies",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        let read_only_status = read_only.status();
+        let read_only: Value = read_only.json().await.unwrap();
+        // Rejected publishes answer the same shape, dry runs are allowed in read-only mode
+        let rejected = app
+            .post("/api/publish?dry_run=true")
+            .json(&json!({
+                "owner": "test-error-bodies",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "not-a-commit",
+            }))
+            .send()
+            .await
+            .unwrap();
+        let rejected_status = rejected.status();
+        let rejected: Value = rejected.json().await.unwrap();
+
+        assert_eq!(unauthorized_status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            unauthorized,
+            json!({ "detail": "Missing or invalid admin token" })
+        );
+        assert_eq!(read_only_status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            read_only,
+            json!({
+                "detail": "Flakestry is in read-only mode for maintenance, please try again later"
+            })
+        );
+        assert_eq!(rejected_status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            rejected,
+            json!({ "detail": "not-a-commit is not a valid commit SHA" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_trending() {
+        let app = TestApp::new().await;
+        // More releases than other tests publish, for the seeded repos to make the top 10. Only
+        // the ones within the window count, `test-trending-old` has none in it. The repos are
+        // archived to keep them out of the listings other tests assert on, trending counts them
+        // all the same.
+        let versions: Vec<String> = (0..30).map(|minor| format!("1.{minor}")).collect();
+        let versions: Vec<&str> = versions.iter().map(String::as_str).collect();
+        for (owner, recent, old) in [
+            ("test-trending-old", 0, 30),
+            ("test-trending-warm", 12, 10),
+            ("test-trending-hot", 15, 0),
+        ] {
+            let repo_id = seed_repo(&app.pool, owner, "flake", &versions[..recent + old]).await;
+            sqlx::query(
+                "UPDATE release SET created_at = (now() AT TIME ZONE 'utc') - interval '1 day' \
+                    WHERE id IN (SELECT id FROM release WHERE repo_id = $1 LIMIT $2)",
+            )
+            .bind(repo_id)
+            .bind(recent as i64)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+            sqlx::query("UPDATE githubrepo SET archived = true WHERE id = $1")
+                .bind(repo_id)
+                .execute(&app.pool)
+                .await
+                .unwrap();
+        }
+
+        let response = app.get("/api/trending").send().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        for owner in [
+            "test-trending-old",
+            "test-trending-warm",
+            "test-trending-hot",
+        ] {
+            remove_owner(&app.pool, owner).await;
+        }
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["limit"], 10);
+        let seeded: Vec<(&str, i64)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|repo| {
+                repo["owner"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("test-trending-")
+            })
+            .map(|repo| {
+                let owner = repo["owner"].as_str().unwrap();
+                (owner, repo["releases"].as_i64().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            seeded,
+            [("test-trending-hot", 15), ("test-trending-warm", 12)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_timeout() {
+        // No query answers before the first poll, so each of them times out
+        let app = TestApp::with_state(|state| state.db_timeout = Duration::ZERO).await;
+
+        let response = app.get("/api/flake").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body["detail"],
+            "Database query timed out, please try again later"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_version() {
+        let mapping = json!({
+            "flakes": { "mappings": { "_meta": { "schema_version": 1 }, "properties": {} } }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, mapping).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/version").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["commit"], env!("GIT_COMMIT"));
+        assert_eq!(body["index_schema_version"], FLAKE_INDEX_SCHEMA_VERSION);
+        assert_eq!(body["live_index_schema_version"], 1);
+
+        // The build info doesn't depend on OpenSearch being up
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/version").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["live_index_schema_version"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_get_health() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({})).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/health").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "database": "ok", "opensearch": "ok" }));
+
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/health").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "database": "ok", "opensearch": "error" }));
+
+        // A cluster that never answers is given up on
+        let stub = Router::new().fallback(|| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            StatusCode::OK
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let opensearch = OpenSearch::new(transport);
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = tokio::time::timeout(Duration::from_secs(10), app.get("/api/health").send())
+            .await
+            .expect("health check hung")
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "database": "ok", "opensearch": "timeout" }));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let app = TestApp::with_state(|state| {
+            state.rate_limiter = Some(RateLimiter::new(2, Duration::from_secs(60)));
+        })
+        .await;
+
+        for _ in 0..2 {
+            let response = app.get("/api/version").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.get("/api/version").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+
+        // Probes are answered however often they come
+        for path in ["/health/live", "/api/health", "/api/health/"] {
+            let response = app.get(path).send().await.unwrap();
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/health/live").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // OpenSearch being down doesn't make the instance unready
+        let response = app.get("/health/ready").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "database": true, "search": false }));
+
+        let app = TestApp::with_state(|state| {
+            state.pool = PgPoolOptions::new()
+                .acquire_timeout(Duration::from_millis(100))
+                .connect_lazy("postgres://postgres@127.0.0.1:1/flakestry")
+                .unwrap();
+        })
+        .await;
+        let response = app.get("/health/live").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.get("/health/ready").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["database"], false);
+    }
+
+    #[tokio::test]
+    async fn test_get_metrics() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        app.get("/api/flake/github/nixos/nixpkgs")
+            .send()
+            .await
+            .unwrap();
+        app.get("/api/flake?q=nixpkgs").send().await.unwrap();
+
+        let response = app.get("/metrics").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        for labels in [
+            "backend=\"postgres\",operation=\"get_repo_id\"",
+            "backend=\"postgres\",operation=\"search_flakes_pg\"",
+            "backend=\"opensearch\",operation=\"search_flakes\"",
+        ] {
+            let count = format!("flakestry_backend_duration_seconds_count{{{labels}}} ");
+            assert!(body.contains(&count), "{labels}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_openapi() {
+        let app = TestApp::new().await;
+
+        let response = app.get("/api/openapi.json").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = response.json().await.unwrap();
+        assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(body["paths"]["/api/flake"]["get"].is_object());
+        assert!(body["paths"]["/api/flake/github/{owner}/{repo}"]["get"].is_object());
+
+        // Every schema referenced has to be part of the document
+        let document = body.to_string();
+        for reference in document.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(
+                body["components"]["schemas"][name].is_object(),
+                "{name} is missing"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_version_schemes() {
+        let app = TestApp::new().await;
+        let repo_id = seed_repo(
+            &app.pool,
+            "test-version-schemes",
+            "flake",
+            &["1.0.0", "2024-01-15", "nightly", "1.1.0"],
+        )
+        .await;
+        sqlx::query(
+            "UPDATE release SET created_at = '2024-02-01' WHERE repo_id = $1 AND version = 'nightly'",
+        )
+        .bind(repo_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        let response = app
+            .get("/api/flake/github/test-version-schemes/flake")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-version-schemes").await;
+
+        // Versions which aren't semver can only be ordered by when they were published
+        assert_eq!(versions(&body), ["1.1.0", "1.0.0", "nightly", "2024-01-15"]);
+        let schemes: Vec<&str> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| release["version_scheme"].as_str().unwrap())
+            .collect();
+        assert_eq!(schemes, ["semver", "semver", "tag", "date"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_semver_order() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "test-semver-order",
+            "flake",
+            &["0.9.0", "0.10.0", "0.2.0"],
+        )
+        .await;
+
+        let response = app
+            .get("/api/flake/github/test-semver-order/flake")
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-semver-order").await;
+
+        // Ordered by their numbers, not as strings
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(versions(&body), ["0.10.0", "0.9.0", "0.2.0"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_release() {
+        let app = TestApp::new().await;
+        let response = app
+            .get("/api/flake/github/NixOS/nixpkgs/22.05")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["version"], "22.05");
+        assert_eq!(body["commit"], "566");
+        assert_eq!(body["flake_ref"], "github:nixos/nixpkgs/566");
+        assert!(body["readme"].is_string());
+        assert!(body.get("published_by").is_none());
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs/0.1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_max_releases() {
+        let app = TestApp::with_state(|state| state.max_repo_releases = 2).await;
+        seed_repo(
+            &app.pool,
+            "test-max-releases",
+            "flake",
+            &["1.0.0", "1.10.0", "1.2.0", "2.0.0", "1.3.0"],
+        )
+        .await;
+
+        let mut pages = Vec::new();
+        for query in ["", "?offset=2", "?offset=4&limit=5", "?limit=1"] {
+            let response = app
+                .get(&format!("/api/flake/github/test-max-releases/flake{query}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{query}");
+            pages.push(response.json::<Value>().await.unwrap());
+        }
+        let invalid = app
+            .get("/api/flake/github/test-max-releases/flake?limit=0")
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-max-releases").await;
+
+        assert_eq!(versions(&pages[0]), ["2.0.0", "1.10.0"]);
+        assert_eq!(pages[0]["truncated"], true);
+        assert_eq!(pages[0]["warnings"].as_array().unwrap().len(), 1);
+        assert_eq!(pages[0]["total"], 5);
+        assert_eq!(pages[0]["limit"], 2);
+        assert_eq!(versions(&pages[1]), ["1.3.0", "1.2.0"]);
+        assert_eq!(pages[1]["truncated"], true);
+        assert_eq!(pages[1]["offset"], 2);
+        assert_eq!(versions(&pages[2]), ["1.0.0"]);
+        assert_eq!(pages[2]["truncated"], false);
+        assert_eq!(pages[2]["warnings"], json!([]));
+        assert_eq!(pages[2]["limit"], 2);
+        assert_eq!(versions(&pages[3]), ["2.0.0"]);
+        assert_eq!(pages[3]["meta"]["releases"], 5);
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_include_prerelease() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "test-prerelease",
+            "flake",
+            &["1.0.0", "1.1.0-rc.1", "1.1.0", "1.2.0-beta"],
+        )
+        .await;
+
+        let all = app
+            .get("/api/flake/github/test-prerelease/flake")
+            .send()
+            .await
+            .unwrap();
+        let all_status = all.status();
+        let all: Value = all.json().await.unwrap();
+
+        let stable = app
+            .get("/api/flake/github/test-prerelease/flake?include_prerelease=false")
+            .send()
+            .await
+            .unwrap();
+        let stable_status = stable.status();
+        let stable: Value = stable.json().await.unwrap();
+
+        let invalid = app
+            .get("/api/flake/github/test-prerelease/flake?include_prerelease=maybe")
+            .send()
+            .await
+            .unwrap();
+        let invalid_status = invalid.status();
+
+        remove_owner(&app.pool, "test-prerelease").await;
+
+        assert_eq!(all_status, StatusCode::OK);
+        assert_eq!(
+            versions(&all),
+            vec!["1.2.0-beta", "1.1.0", "1.1.0-rc.1", "1.0.0"]
+        );
+        assert_eq!(stable_status, StatusCode::OK);
+        assert_eq!(versions(&stable), vec!["1.1.0", "1.0.0"]);
+        assert_eq!(stable["meta"]["releases"], 4);
+        assert_eq!(invalid_status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_backend_error() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        // Only the search index knows about licenses
+        let response = app.get("/api/flake?license=MIT").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body["detail"],
+            "Search backend unavailable, please try again later"
+        );
+    }
+
+    // Clients retry on a 503, so a response OpenSearch shouldn't have sent fails with one too
+    // rather than with a 500 or an empty result
+    #[tokio::test]
+    async fn test_get_flake_malformed_search_response() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "took": 1 })).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let mut statuses = Vec::new();
+        for path in [
+            "/api/flake?license=MIT",
+            "/api/owner/nixos/search?q=nix",
+            "/api/tags",
+        ] {
+            statuses.push(app.get(path).send().await.unwrap().status());
+        }
+        assert_eq!(statuses, [StatusCode::SERVICE_UNAVAILABLE; 3]);
+
+        // Searches the database can answer fall back to it
+        let response = app.get("/api/flake?q=home").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["source"], "database");
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_query_operators() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        for q in [
+            "\"home manager",
+            "owner:nixos AND (repo:[a TO *])",
+            "nix~ || -rust +go^2 \\/",
+            "()",
+        ] {
+            let response = app
+                .get("/api/flake")
+                .query(&[("q", q)])
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{q}");
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["query"], q);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_group_by_repo() {
+        let search_response = json!({
+            "hits": { "total": { "value": 3, "relation": "eq" }, "hits": [] },
+            "aggregations": { "repos": { "value": 2 } }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 3);
+
+        let response = app
+            .get("/api/flake?q=nix&group_by_repo=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 2);
+
+        // Without OpenSearch the database search groups too
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app
+            .get("/api/flake?q=nixpkgs&group_by_repo=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 1);
+        let response = app.get("/api/flake?q=nixpkgs").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_facets() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] },
+            "aggregations": {
+                "owners": { "buckets": [{ "key": "nixos", "doc_count": 2 }] },
+                "outputs": { "buckets": [
+                    { "key": "packages", "doc_count": 2 },
+                    { "key": "overlays", "doc_count": 1 },
+                ] },
+            }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["source"], "search");
+        assert_eq!(
+            body["facets"],
+            json!({
+                "owners": [{ "value": "nixos", "count": 2 }],
+                "outputs": [
+                    { "value": "packages", "count": 2 },
+                    { "value": "overlays", "count": 1 },
+                ],
+            })
+        );
+
+        let response = app.get("/api/flake").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert!(body.get("facets").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_partial_results() {
+        let search_response = json!({
+            "_shards": {
+                "total": 2,
+                "successful": 1,
+                "failed": 1,
+                "failures": [{ "shard": 1, "reason": { "type": "node_disconnected_exception" } }],
+            },
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["partial"], true);
+        assert_eq!(
+            body["warnings"],
+            json!(["part of the search index couldn't be searched, some matches may be missing"])
+        );
+
+        let response = app.get("/api/flake").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert!(body.get("partial").is_none());
+        assert_eq!(body["warnings"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_cache() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+            state.search_cache = TtlCache::new(Duration::from_secs(60), 10);
+        })
+        .await;
+
+        for path in ["/api/flake?q=python", "/api/flake?q=%20Python%20"] {
+            let response = app.get(path).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 1);
+
+        app.get("/api/flake?q=python&explain=true")
+            .send()
+            .await
+            .unwrap();
+        app.get("/api/flake?q=python&page=2").send().await.unwrap();
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_lru_cache() {
+        let cache = LruCache::new(2);
+        cache.insert("a", 1).await;
+        cache.insert("b", 2).await;
+        // Using `a` leaves `b` as the least recently used
+        assert_eq!(cache.get(&"a").await, Some(1));
+        cache.insert("c", 3).await;
+        assert_eq!(cache.get(&"b").await, None);
+        assert_eq!(cache.get(&"a").await, Some(1));
+        assert_eq!(cache.get(&"c").await, Some(3));
+
+        cache.clear().await;
+        assert_eq!(cache.get(&"a").await, None);
+
+        let disabled = LruCache::new(0);
+        disabled.insert("a", 1).await;
+        assert_eq!(disabled.get(&"a").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_repo_id_cache() {
+        let app = TestApp::with_state(|state| state.repo_ids = LruCache::new(10)).await;
+        let path = "/api/flake/github/test-repo-id-cache/flake";
+        let response = app.get(path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Unknown repos aren't cached, the repo may be published through another instance
+        seed_repo(&app.pool, "test-repo-id-cache", "flake", &["1.0"]).await;
+        let response = app.get(path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Later lookups don't go to the database, which has the repo by another name now
+        sqlx::query(
+            "UPDATE githubrepo SET name = 'renamed' FROM githubowner \
+                WHERE githubowner.id = githubrepo.owner_id AND githubowner.name = $1",
+        )
+        .bind("test-repo-id-cache")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let response = app.get(path).send().await.unwrap();
+        remove_owner(&app.pool, "test-repo-id-cache").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_owner_filter() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let response = app
+            .get("/api/flake?owner=NixOS&repo=nixpkgs&debug_query=true")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body["filters"],
+            json!({ "owner": "nixos", "repo": "nixpkgs" })
+        );
+        let query = &body["debug_query"]["query"]["bool"];
+        assert_eq!(query["must"], json!({ "match_all": {} }));
+        assert_eq!(
+            query["filter"],
+            json!([
+                { "term": { "owner.keyword": "nixos" } },
+                { "term": { "full_name": "nixos/nixpkgs" } },
+            ])
+        );
+
+        let response = app.get("/api/flake?repo=nixpkgs").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // The database fallback matches owners exactly as well
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/flake?owner=nixos").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        let owners: Vec<&Value> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| &release["owner"])
+            .collect();
+        assert!(!owners.is_empty());
+        assert!(owners.iter().all(|owner| *owner == "nixos"));
+
+        let response = app.get("/api/flake?owner=nix").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["count"], 0);
+        assert_eq!(body["filters"], json!({ "owner": "nix" }));
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_debug_query() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response.clone()).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let response = app
+            .get("/api/flake?q=python&tag=cli&size=5&debug_query=true")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        let body: Value = response.json().await.unwrap();
+        let debug_query = &body["debug_query"];
+        assert_eq!(debug_query["size"], 5);
+        assert_eq!(debug_query["from"], 0);
+        assert_eq!(
+            debug_query["query"]["bool"]["must"]["multi_match"]["query"],
+            "python"
+        );
+        assert_eq!(
+            debug_query["query"]["bool"]["filter"],
+            json!([{ "term": { "tags": "cli" } }])
+        );
+        assert!(!debug_query.to_string().contains("127.0.0.1"));
+
+        // Off unless the deployment allows debugging
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        for path in [
+            "/api/flake?q=python&debug_query=true",
+            "/api/flake?q=python",
+        ] {
+            let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+            assert!(body.get("debug_query").is_none(), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_provides() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let response = app
+            .get("/api/flake?provides=overlay&debug_query=true")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body["debug_query"]["query"]["bool"]["filter"],
+            json!([{ "terms": { "provides": ["overlays"] } }])
+        );
+        let response = app.get("/api/flake?provides=bogus").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // The filter matches the output kinds releases are indexed with
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let stub = Router::new().fallback(move |uri: axum::http::Uri, body: String| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((uri.path().to_string(), body));
+            async { axum::Json(json!({ "errors": false, "items": [] })) }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let opensearch = OpenSearch::new(transport);
+        let (queue, worker) = indexer::spawn(opensearch.clone(), IndexConfig::default());
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.index_queue = queue;
+        })
+        .await;
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-provides",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "outputs": {
+                    "overlays": { "default": { "type": "nixpkgs-overlay" } },
+                    "packages": { "x86_64-linux": { "default": { "type": "derivation" } } },
+                },
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        worker.shutdown().await;
+        remove_owner(&app.pool, "test-provides").await;
+
+        let requests = requests.lock().unwrap();
+        let documents: Vec<Value> = requests
+            .iter()
+            .filter(|(path, _)| path.ends_with("/_bulk"))
+            .flat_map(|(_, body)| body.lines().skip(1).step_by(2))
+            .map(|document| serde_json::from_str(document).unwrap())
+            .collect();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0]["provides"], json!(["overlays", "packages"]));
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_phrase_query() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let must = |query: &'static str| {
+            let app = &app;
+            async move {
+                let path = format!("/api/flake?q={query}&debug_query=true");
+                let body: Value = app.get(&path).send().await.unwrap().json().await.unwrap();
+                body["debug_query"]["query"]["bool"]["must"].clone()
+            }
+        };
+
+        // Unquoted terms stay fuzzy
+        let unquoted = must("nix%20flake").await;
+        assert_eq!(unquoted["multi_match"]["query"], "nix flake");
+        assert_eq!(unquoted["multi_match"]["fuzziness"], "AUTO");
+
+        let quoted = must("%22nix%20flake%22").await;
+        assert_eq!(quoted[0]["multi_match"]["query"], "nix flake");
+        assert_eq!(quoted[0]["multi_match"]["type"], "phrase");
+        assert!(quoted[0]["multi_match"].get("fuzziness").is_none());
+        assert_eq!(quoted.as_array().unwrap().len(), 1);
+
+        let mixed = must("%22nix%20flake%22%20rust").await;
+        assert_eq!(mixed[0]["multi_match"]["type"], "phrase");
+        assert_eq!(mixed[1]["multi_match"]["query"], "rust");
+        assert_eq!(mixed[1]["multi_match"]["fuzziness"], "AUTO");
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_cancels_search() {
+        struct Cancelled(Option<tokio::sync::oneshot::Sender<()>>);
+        impl Drop for Cancelled {
+            fn drop(&mut self) {
+                if let Some(cancelled) = self.0.take() {
+                    let _ = cancelled.send(());
+                }
+            }
+        }
+
+        // The search never answers, until dropping its request shows it was abandoned
+        let (started_sender, started) = tokio::sync::oneshot::channel();
+        let (cancelled_sender, cancelled) = tokio::sync::oneshot::channel();
+        let senders = Arc::new(std::sync::Mutex::new(Some((
+            started_sender,
+            cancelled_sender,
+        ))));
+        let stub = Router::new().fallback(move || {
+            let senders = senders.lock().unwrap().take();
+            async move {
+                let Some((started, cancelled)) = senders else {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                };
+                let _cancelled = Cancelled(Some(cancelled));
+                let _ = started.send(());
+                std::future::pending::<StatusCode>().await
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let app = TestApp::with_state(|state| state.opensearch = OpenSearch::new(transport)).await;
+
+        let mut client =
+            tokio::net::TcpStream::connect(app.base_url.socket_addrs(|| None).unwrap()[0])
+                .await
+                .unwrap();
+        tokio::io::AsyncWriteExt::write_all(
+            &mut client,
+            b"GET /api/flake?q=nix HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        started.await.unwrap();
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(5), cancelled)
+            .await
+            .expect("the search outlived the request")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_concurrency_limit() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            // Every permit is taken, as if the cluster was already busy
+            state.search_permits = Semaphore::new(0);
+        })
+        .await;
+
+        let response = app
+            .get("/api/flake?q=nix&license=MIT")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Searches which can be answered by the database fall back to it
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_text_format() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?format=text").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "nix-community/home-manager 23.05\nnixos/nixpkgs 23.05\nnixos/nixpkgs 22.05\n"
+        );
+
+        // Searches are filtered the same, here by the database fallback
+        let response = app
+            .get("/api/flake?q=home&format=text")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.text().await.unwrap(),
+            "nix-community/home-manager 23.05\n"
+        );
+
+        let response = app.get("/api/flake?format=csv").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_repeated_param() {
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, json!({})).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=a&q=b").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["detail"], "q may only be given once");
+
+        let response = app.get("/api/flake?limit=1&limit=1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_breaker() {
+        let (opensearch, requests) = counting_opensearch(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": { "type": "index_not_found_exception" }, "status": 500 }),
+        )
+        .await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_breaker = CircuitBreaker::new("opensearch", 2, Duration::from_millis(200));
+        })
+        .await;
+
+        let search = || async {
+            let response = app.get("/api/flake?q=home").send().await.unwrap();
+            let body: Value = response.json().await.unwrap();
+            body["source"].clone()
+        };
+        let mut sources = Vec::new();
+        for _ in 0..4 {
+            sources.push(search().await);
+        }
+        let while_open = requests.load(AtomicOrdering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        // After the cooldown a single search tries OpenSearch again and reopens the breaker
+        sources.push(search().await);
+        sources.push(search().await);
+        let after_cooldown = requests.load(AtomicOrdering::SeqCst);
+
+        assert!(sources.iter().all(|source| source == "database"));
+        assert_eq!(while_open, 2);
+        assert_eq!(after_cooldown, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_fallback() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=home").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), ["23.05"]);
+        assert_eq!(body["items"][0]["repo"], "home-manager");
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["source"], "database");
+        assert_eq!(
+            body["warnings"],
+            json!(["search is unavailable, these are the matches of a simpler database search"])
+        );
+
+        // Listing the newest releases doesn't need the search index
+        let response = app.get("/api/flake").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["warnings"], json!([]));
+
+        let response = app.get("/api/flake?q=100%25").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 0);
+    }
+
+    // The frontend tells "no results for the query" apart from "no query" by these fields
+    #[tokio::test]
+    async fn test_get_flake_empty_results() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/flake?q=nothing").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["query"], "nothing");
+        assert_eq!(body["count"], 0);
+        assert_eq!(body["items"], json!([]));
+
+        // The database fallback keeps the same contract
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/flake?q=nothing").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["query"], "nothing");
+        assert_eq!(body["count"], 0);
+        assert_eq!(body["items"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_max_query_length() {
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, json!({})).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.max_query_length = 8;
+        })
+        .await;
+
+        let response = app.get("/api/flake?q=nixpkgs-1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["detail"], "q must be at most 8 characters");
+        // Characters are counted rather than bytes
+        let response = app.get("/api/flake?q=caf%C3%A9").send().await.unwrap();
+        assert_ne!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app
+            .get("/api/owner/nixos/search?q=nixpkgs-1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_cache_control() {
+        let search_response = json!({
+            "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let mut cache_control = Vec::new();
+        for path in [
+            "/api/flake",
+            "/api/flake?q=nix",
+            "/api/flake?q=nix&explain=true",
+        ] {
+            let response = app.get(path).send().await.unwrap();
+            cache_control.push(response.headers()["cache-control"].clone());
+        }
+        assert_eq!(
+            cache_control,
+            ["public, max-age=60", "public, max-age=30", "no-store"]
+        );
+
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        assert_eq!(response.headers()["cache-control"], "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_list_limit() {
+        let app = TestApp::with_state(|state| state.default_list_limit = 1).await;
+
+        let response = app.get("/api/flake").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["limit"], 1);
+
+        let response = app.get("/api/flake?limit=2").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["count"], 2);
+
+        let response = app.get("/api/flake?limit=100000").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["limit"], MAX_LIST_LIMIT);
+
+        let response = app.get("/api/flake?limit=0").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_list_paging() {
+        let app = TestApp::new().await;
+        // Newer than anything else, to be the first page. Archived so that only listings with
+        // `include_archived` see them, other tests assert on the default listing.
+        let repo_id = seed_repo(
+            &app.pool,
+            "test-list-paging",
+            "flake",
+            &["1.0", "1.1", "1.2"],
+        )
+        .await;
+        sqlx::query(
+            "UPDATE release SET created_at = '2999-01-01'::timestamp + version::numeric * interval '1 day' \
+                WHERE repo_id = $1",
+        )
+        .bind(repo_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE githubrepo SET archived = true WHERE id = $1")
+            .bind(repo_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let mut versions = Vec::new();
+        for query in [
+            "limit=2",
+            "limit=2&offset=1",
+            "limit=1&page=3",
+            "per_page=2",
+            "per_page=2&page=2",
+        ] {
+            let response = app
+                .get(&format!("/api/flake?include_archived=true&{query}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{query}");
+            let body: Value = response.json().await.unwrap();
+            let page: Vec<&str> = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|release| release["owner"] == "test-list-paging")
+                .map(|release| release["version"].as_str().unwrap())
+                .collect::<Vec<_>>();
+            versions.push(page.join(","));
+            assert!(body["total"].as_i64().unwrap() >= 3, "{query}");
+        }
+        let response = app
+            .get(&format!("/api/flake?limit=1&page={}", i64::MAX))
+            .send()
+            .await
+            .unwrap();
+        let far_page = response.status();
+        let response = app.get("/api/flake?per_page=1000").send().await.unwrap();
+        let capped: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-list-paging").await;
+
+        assert_eq!(versions, ["1.2,1.1", "1.1,1.0", "1.0", "1.2,1.1", "1.0"]);
+        assert_eq!(far_page, StatusCode::OK);
+        assert_eq!(capped["limit"], 250);
+        for query in [
+            "offset=-1",
+            "offset=abc",
+            "page=0",
+            "per_page=0",
+            "per_page=-1",
+            "per_page=abc",
+        ] {
+            let response = app
+                .get(&format!("/api/flake?{query}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_fields() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake?limit=2&fields=owner, version")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["count"], 2);
+        assert_eq!(body["releases"], body["items"]);
+        for release in body["items"].as_array().unwrap() {
+            let mut fields: Vec<&String> = release.as_object().unwrap().keys().collect();
+            fields.sort();
+            assert_eq!(fields, ["owner", "version"]);
+        }
+
+        let response = app
+            .get("/api/flake?fields=owner,stars")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "test-trailing-slash", "flake", &["1.0"]).await;
+
+        let mut statuses = Vec::new();
+        for path in [
+            "/api/flake",
+            "/api/flake/",
+            "/api/flake/github/test-trailing-slash/flake",
+            "/api/flake/github/test-trailing-slash/flake/",
+            "/health/live/",
+        ] {
+            statuses.push(app.get(path).send().await.unwrap().status());
+        }
+        remove_owner(&app.pool, "test-trailing-slash").await;
+
+        assert_eq!(statuses, [StatusCode::OK; 5]);
+    }
+
+    #[tokio::test]
+    async fn test_release_view() {
+        let app = TestApp::new().await;
+
+        let mut views = Vec::new();
+        for path in [
+            "/api/flake?limit=1",
+            "/api/flake?limit=1&view=full",
+            "/api/flake/github/nixos/nixpkgs",
+            "/api/flake/github/nixos/nixpkgs?view=compact",
+        ] {
+            let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+            assert_eq!(body["releases"], body["items"], "{path}");
+            let release = &body["items"][0];
+            views.push((
+                release.get("version").is_some(),
+                release.get("commit").is_some(),
+            ));
+        }
+        assert_eq!(
+            views,
+            [(true, false), (true, true), (true, true), (true, false)]
+        );
+
+        let response = app.get("/api/flake?view=wide").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_release_empty_fields() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        seed_repo(&app.pool, "test-empty-fields", "flake", &["1.0"]).await;
+
+        let mut releases = Vec::new();
+        for path in [
+            "/api/flake/github/test-empty-fields/flake",
+            "/api/flake?q=test-empty-fields&view=full",
+        ] {
+            let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+            releases.push(body["items"][0].clone());
+        }
+        remove_owner(&app.pool, "test-empty-fields").await;
+
+        for release in releases {
+            assert_eq!(release["outputs"], json!({}));
+            assert_eq!(release["systems"], json!([]));
+            assert_eq!(release["tags"], json!([]));
+            assert_eq!(release["description"], "");
+            assert_eq!(release["readme_hash"], Value::Null);
+            for omitted in ["score", "explanation", "owner_avatar_url", "published_by"] {
+                assert!(release.get(omitted).is_none(), "{omitted}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_release_updated_at() {
+        let app = TestApp::new().await;
+        let repo_id = seed_repo(&app.pool, "test-updated-at", "flake", &["1.0"]).await;
+
+        let timestamps = || async {
+            let body: Value = app
+                .get("/api/flake/github/test-updated-at/flake")
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let release = &body["items"][0];
+            (release["created_at"].clone(), release["updated_at"].clone())
+        };
+        let seeded = timestamps().await;
+        sqlx::query("UPDATE release SET description = 'Changed' WHERE repo_id = $1")
+            .bind(repo_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let changed = timestamps().await;
+        remove_owner(&app.pool, "test-updated-at").await;
+
+        assert_eq!(seeded.0, "2000-01-01T00:00:00");
+        assert_eq!(seeded.1, seeded.0);
+        assert_eq!(changed.0, seeded.0);
+        assert!(changed.1.as_str().unwrap() > "2000-01-01T00:00:00");
+    }
+
+    #[tokio::test]
+    async fn test_get_shields() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs/shields.json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert_eq!(
+            body,
+            "{\"schemaVersion\":1,\"label\":\"flakestry\",\"message\":\"v23.05\",\"color\":\"blue\"}"
+        );
+
+        let response = app
+            .get("/api/flake/github/nixos/does-not-exist/shields.json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["message"], "no release");
+    }
+
+    #[tokio::test]
+    async fn test_get_leaderboard() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "leaderboard-releases",
+            "flake",
+            &["1", "2", "3", "4"],
+        )
+        .await;
+        let repo_id = seed_repo(&app.pool, "leaderboard-repos", "flake-1", &["1"]).await;
+        for repo in ["flake-2", "flake-3"] {
+            sqlx::query(
+                "WITH repo AS ( \
+                    INSERT INTO githubrepo (name, owner_id, created_at) \
+                        SELECT $2, owner_id, now() FROM githubrepo WHERE id = $1 RETURNING id \
+                ) \
+                INSERT INTO release (repo_id, version, commit, created_at) \
+                    SELECT id, '1', '123', '2000-01-01' FROM repo",
+            )
+            .bind(repo_id)
+            .bind(repo)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        }
+
+        let mut rankings = Vec::new();
+        for by in ["releases", "repos"] {
+            let path = format!("/api/leaderboard?by={by}&limit={MAX_LIST_LIMIT}");
+            let body: Value = app.get(&path).send().await.unwrap().json().await.unwrap();
+            let owners: Vec<Value> = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|item| item["owner"].as_str().unwrap().starts_with("leaderboard-"))
+                .cloned()
+                .collect();
+            rankings.push(owners);
+        }
+        let response = app.get("/api/leaderboard?by=stars").send().await.unwrap();
+        remove_owner(&app.pool, "leaderboard-releases").await;
+        remove_owner(&app.pool, "leaderboard-repos").await;
+
+        let releases = json!({ "owner": "leaderboard-releases", "releases": 4, "repos": 1 });
+        let repos = json!({ "owner": "leaderboard-repos", "releases": 3, "repos": 3 });
+        assert_eq!(rankings[0], [releases.clone(), repos.clone()]);
+        assert_eq!(rankings[1], [repos, releases]);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_timeline() {
+        let app = TestApp::new().await;
+        let repo_id = seed_repo(&app.pool, "timeline", "flake", &["1.0", "1.1", "2.0"]).await;
+        for (version, created_at) in [
+            ("1.0", "2024-01-05"),
+            ("1.1", "2024-01-20"),
+            ("2.0", "2024-03-31"),
+        ] {
+            sqlx::query(
+                "UPDATE release SET created_at = $3::timestamp WHERE repo_id = $1 AND version = $2",
+            )
+            .bind(repo_id)
+            .bind(version)
+            .bind(created_at)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        }
+
+        let response = app
+            .get("/api/flake/github/timeline/flake/timeline")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert_eq!(
+            body,
+            "[{\"month\":\"2024-01\",\"releases\":2},{\"month\":\"2024-02\",\"releases\":0},{\"month\":\"2024-03\",\"releases\":1}]"
+        );
+
+        let response = app
+            .get("/api/flake/github/timeline/does-not-exist/timeline")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        remove_owner(&app.pool, "timeline").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_cadence() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "cadence", "flake", &["1.0", "1.1"]).await;
+
+        let response = app
+            .get("/api/flake/github/cadence/flake/cadence")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        // Both seeded releases are from 2000
+        assert_eq!(body["releases"], 2);
+        assert_eq!(body["releases_last_year"], 0);
+        assert_eq!(body["average_gap_days"], 0.0);
+        assert!(body["days_since_last_release"].as_i64().unwrap() > 365);
+
+        let response = app
+            .get("/api/flake/github/cadence/does-not-exist/cadence")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        remove_owner(&app.pool, "cadence").await;
+    }
+
+    #[tokio::test]
+    async fn test_publish_concurrent_new_repo() {
+        let app = TestApp::new().await;
+        let publish = |version: &str| {
+            app.post("/api/publish")
+                .json(&json!({
+                    "owner": "test-concurrent",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                }))
+                .send()
+        };
+
+        let (first, second) = tokio::join!(publish("1.0.0"), publish("1.1.0"));
+        let repo_ids: Vec<i32> = sqlx::query_scalar(
+            "SELECT release.repo_id FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                WHERE githubowner.name = 'test-concurrent'",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        remove_owner(&app.pool, "test-concurrent").await;
+
+        assert_eq!(first.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(second.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(repo_ids.len(), 2);
+        assert_eq!(repo_ids[0], repo_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_existing_version() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "test-existing-version", "flake", &["1.0.0"]).await;
+
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-existing-version",
+                "repo": "flake",
+                "version": "v1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        remove_owner(&app.pool, "test-existing-version").await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_publish_outputs() {
+        let app = TestApp::new().await;
+        let mut publish = json!({
+            "owner": "test-publish-outputs",
+            "repo": "flake",
+            "version": "1.0.0",
+            "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            "outputs": { "packages": { "x86_64-linux": { "default": { "name": "hello" } } } },
+        });
+        let response = app
+            .post("/api/publish")
+            .json(&publish)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let outputs = json!({
+            "devShells": { "x86_64-linux": { "default": { "type": "derivation", "name": "shell" } } },
+            "nixosModules": { "default": { "type": "nixos-module" } },
+            "lib": { "type": "unknown" },
+        });
+        publish["outputs"] = outputs.clone();
+        let response = app
+            .post("/api/publish")
+            .json(&publish)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = app
+            .get("/api/flake/github/test-publish-outputs/flake")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-publish-outputs").await;
+
+        assert_eq!(body["items"][0]["outputs"], outputs);
+    }
+
+    #[tokio::test]
+    async fn test_index_queue_batches() {
+        let bulk_response = json!({ "errors": false, "items": [] });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, bulk_response).await;
+        let config = IndexConfig {
+            batch_size: 2,
+            flush_interval: Duration::from_secs(3600),
+            ..IndexConfig::default()
+        };
+        let (queue, worker) = indexer::spawn(opensearch, config);
+
+        for release_id in 1..=3 {
+            queue.enqueue(release_id, json!({ "repo": "flake" })).await;
+        }
+        // The first two fill a batch, the third waits for the interval or the shutdown
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while requests.load(AtomicOrdering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 1);
+
+        worker.shutdown().await;
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_index_queue_flush_interval() {
+        let bulk_response = json!({ "errors": false, "items": [] });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, bulk_response).await;
+        let config = IndexConfig {
+            batch_size: 100,
+            flush_interval: Duration::from_millis(10),
+            ..IndexConfig::default()
+        };
+        let (queue, _worker) = indexer::spawn(opensearch, config);
+
+        queue.enqueue(1, json!({ "repo": "flake" })).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while requests.load(AtomicOrdering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_index_queue_wait_for_refresh() {
+        let queries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = queries.clone();
+        let stub = Router::new().fallback(move |uri: axum::http::Uri| {
+            recorded
+                .lock()
+                .unwrap()
+                .push(uri.query().unwrap_or_default().to_string());
+            async { axum::Json(json!({ "errors": false, "items": [] })) }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let config = IndexConfig {
+            flush_interval: Duration::from_secs(3600),
+            wait_for_refresh: true,
+            ..IndexConfig::default()
+        };
+        let (queue, _worker) = indexer::spawn(OpenSearch::new(transport), config);
+
+        // Returns once the document is searchable, without waiting for the interval
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            queue.enqueue(1, json!({ "repo": "flake" })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*queries.lock().unwrap(), ["refresh=wait_for"]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_gzip() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let app = TestApp::new().await;
+        let gzip = |json: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(json).unwrap();
+            encoder.finish().unwrap()
+        };
+        let publish = |body: Vec<u8>, encoding: &str| {
+            app.post("/api/publish")
+                .header("content-type", "application/json")
+                .header("content-encoding", encoding)
+                .body(body)
+                .send()
+        };
+        let release = json!({
+            "owner": "test-publish-gzip",
+            "repo": "flake",
+            "version": "1.0.0",
+            "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            "readme": "# Flake\n\nCompressed on the way in.",
+        });
+        let compressed = gzip(release.to_string().as_bytes());
+        let created = publish(compressed.clone(), "gzip").await.unwrap().status();
+        // A few kilobytes expanding into megabytes of whitespace
+        let mut bomb = release.to_string().into_bytes();
+        bomb.extend(std::iter::repeat_n(b' ', 3 * 1024 * 1024));
+        let too_large = publish(gzip(&bomb), "gzip").await.unwrap().status();
+        let invalid = publish(b"not gzip".to_vec(), "gzip")
+            .await
+            .unwrap()
+            .status();
+        let unsupported = publish(compressed, "br").await.unwrap().status();
+        let description: Option<String> = sqlx::query_scalar(
+            "SELECT release.description FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                INNER JOIN githubowner ON githubowner.id = githubrepo.owner_id \
+                WHERE githubowner.name = 'test-publish-gzip'",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        remove_owner(&app.pool, "test-publish-gzip").await;
+
+        assert_eq!(created, StatusCode::CREATED);
+        // Made up from the readme, so it was decompressed whole
+        assert_eq!(description.as_deref(), Some("Compressed on the way in."));
+        assert_eq!(too_large, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(invalid, StatusCode::BAD_REQUEST);
+        assert_eq!(unsupported, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_get_publish_failures() {
+        let app = TestApp::with_state(|state| state.admin_token = Some("secret".to_string())).await;
+        let publish = |version: &str, commit: &str| {
+            app.post("/api/publish")
+                .json(&json!({
+                    "owner": "Test-Publish-Failures",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": commit,
+                }))
+                .send()
+        };
+        let commit = "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad";
+        let statuses = [
+            publish("latest", commit).await.unwrap().status(),
+            publish("1.0.0", "not-a-commit").await.unwrap().status(),
+            publish("1.0.0", commit).await.unwrap().status(),
+        ];
+        // Rejected items of a batch are recorded too
+        let batch: Value = app
+            .post("/api/publish/batch")
+            .bearer_auth("secret")
+            .json(&json!([
+                { "owner": "test-publish-failures", "repo": "flake", "version": "1.1.0", "commit": commit },
+                { "owner": "test-publish-failures", "repo": "flake", "version": "1.2.0", "commit": "also-not-a-commit" },
+            ]))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        // Only rejections are recorded, dry runs aren't either
+        app.post("/api/publish?dry_run=true")
+            .json(&json!({ "owner": "test-publish-failures", "repo": "flake", "version": "x", "commit": commit }))
+            .send()
+            .await
+            .unwrap();
+
+        let failures = |query: &str, token: &str| {
+            app.get(&format!("/api/admin/publish-failures{query}"))
+                .bearer_auth(token)
+                .send()
+        };
+        let unauthorized = failures("", "wrong").await.unwrap().status();
+        let response = failures("?owner=test-publish-failures", "secret")
+            .await
+            .unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        let page: Value = failures("?owner=test-publish-failures&limit=1&offset=1", "secret")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM publish_failure WHERE owner = 'test-publish-failures'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-publish-failures").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::BAD_REQUEST,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::CREATED
+            ]
+        );
+        assert_eq!(unauthorized, StatusCode::UNAUTHORIZED);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(batch[0]["status"], 201);
+        assert_eq!(batch[1]["status"], 422);
+        assert_eq!(body["total"], 3);
+        let failures: Vec<(&str, &str, i64)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|failure| {
+                (
+                    failure["version"].as_str().unwrap(),
+                    failure["commit"].as_str().unwrap(),
+                    failure["status"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                ("1.2.0", "also-not-a-commit", 422),
+                ("1.0.0", "not-a-commit", 422),
+                ("latest", commit, 400)
+            ]
+        );
+        assert_eq!(
+            body["items"][1]["reason"],
+            "not-a-commit is not a valid commit SHA"
+        );
+        assert_eq!(page["items"][0]["version"], "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_publish_queues_index() {
+        let bulk_response = json!({ "errors": false, "items": [] });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, bulk_response).await;
+        let (queue, worker) = indexer::spawn(opensearch, IndexConfig::default());
+        let app = TestApp::with_state(|state| state.index_queue = queue).await;
+
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-publish-queues-index",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        worker.shutdown().await;
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 1);
+
+        remove_owner(&app.pool, "test-publish-queues-index").await;
+    }
+
+    #[tokio::test]
+    async fn test_readme_indexing() {
+        for (strategy, indexed) in [
+            (ReadmeIndexing::All, [true, true, true]),
+            (ReadmeIndexing::Distinct, [true, false, true]),
+            (ReadmeIndexing::Latest, [true, true, true]),
+        ] {
+            // The paths and bodies of the requests the stub cluster got
+            let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            let stub = Router::new().fallback(move |uri: axum::http::Uri, body: String| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((uri.path().to_string(), body));
+                async { axum::Json(json!({ "errors": false, "items": [], "updated": 1 })) }
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+            tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+            let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+                .build()
+                .unwrap();
+            let opensearch = OpenSearch::new(transport);
+            let (queue, worker) = indexer::spawn(opensearch.clone(), IndexConfig::default());
+            let app = TestApp::with_state(|state| {
+                state.opensearch = opensearch;
+                state.index_queue = queue;
+                state.readme_indexing = strategy;
+            })
+            .await;
+
+            for (version, readme) in [("1.0", "# Same"), ("1.1", "# Same"), ("1.2", "# New")] {
+                let response = app
+                    .post("/api/publish")
+                    .json(&json!({
+                        "owner": "test-readme-indexing",
+                        "repo": "flake",
+                        "version": version,
+                        "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                        "readme": readme,
+                    }))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED, "{strategy:?}");
+            }
+            worker.shutdown().await;
+            remove_owner(&app.pool, "test-readme-indexing").await;
+
+            let requests = requests.lock().unwrap();
+            let documents: Vec<Value> = requests
+                .iter()
+                .filter(|(path, _)| path.ends_with("/_bulk"))
+                .flat_map(|(_, body)| body.lines().skip(1).step_by(2))
+                .map(|document| serde_json::from_str(document).unwrap())
+                .collect();
+            let has_readme: Vec<bool> = documents
+                .iter()
+                .map(|document| !document["readme"].is_null())
+                .collect();
+            assert_eq!(has_readme, indexed, "{strategy:?}");
+            // Each publish removes the readme from the documents of the releases before it
+            let updates = requests
+                .iter()
+                .filter(|(path, body)| {
+                    path.ends_with("/_update_by_query") && body.contains("remove('readme')")
+                })
+                .count();
+            let expected = if strategy == ReadmeIndexing::Latest {
+                3
+            } else {
+                0
+            };
+            assert_eq!(updates, expected, "{strategy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhooks() {
+        let app = TestApp::with_state(|state| state.admin_token = Some("secret".to_string())).await;
+        // Fails the first delivery, to have it retried
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let payloads = received.clone();
+        let receiver = Router::new().fallback(move |axum::Json(payload): axum::Json<Value>| {
+            let payloads = payloads.clone();
+            async move {
+                let mut payloads = payloads.lock().unwrap();
+                payloads.push(payload);
+                if payloads.len() == 1 {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::NO_CONTENT
+                }
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let register = |url: &str, token: &str| {
+            app.post("/api/webhooks")
+                .bearer_auth(token)
+                .json(&json!({ "owner": "Test-Webhooks", "repo": "flake", "url": url }))
+                .send()
+        };
+        let unauthorized = register(&url, "wrong").await.unwrap();
+        let invalid = register("ftp://example.com", "secret").await.unwrap();
+        let created = register(&url, "secret").await.unwrap();
+        let status = created.status();
+        let webhook: Value = created.json().await.unwrap();
+        let duplicate = register(&url, "secret").await.unwrap();
+
+        let published = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-webhooks",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        let mut delivery = None;
+        for _ in 0..100 {
+            delivery = sqlx::query_as::<_, (i32, bool)>(
+                "SELECT attempts, delivered_at IS NOT NULL FROM webhook_delivery \
+                    WHERE webhook_id = $1 AND delivered_at IS NOT NULL",
+            )
+            .bind(webhook["id"].as_i64().unwrap() as i32)
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap();
+            if delivery.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        sqlx::query("DELETE FROM webhook WHERE owner = 'test-webhooks'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-webhooks").await;
+
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(webhook["owner"], "test-webhooks");
+        assert_eq!(webhook["url"], url);
+        assert_eq!(duplicate.status(), StatusCode::CONFLICT);
+        assert_eq!(published.status(), StatusCode::CREATED);
+        assert_eq!(delivery, Some((2, true)));
+        let payloads = received.lock().unwrap();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[1], payloads[0]);
+        assert_eq!(payloads[1]["event"], "release");
+        assert_eq!(payloads[1]["version"], "1.0.0");
+        assert_eq!(
+            payloads[1]["url"],
+            "https://flakestry.dev/flake/github/test-webhooks/flake/1.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_readme_dedup() {
+        let app = TestApp::new().await;
+        for (version, readme) in [("1.0.0", "# Same"), ("1.1.0", "# Same"), ("1.2.0", "# New")] {
+            let response = app
+                .post("/api/publish")
+                .json(&json!({
+                    "owner": "test-readme-dedup",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                    "readme": readme,
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        // Publishing the same readme again is still recognized as unchanged
+        let republished = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-readme-dedup",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "readme": "# Same",
+            }))
+            .send()
+            .await
+            .unwrap();
+
+        let stored: i64 = sqlx::query_scalar(
+            "SELECT count(DISTINCT readme_hash) FROM release \
+                INNER JOIN githubrepo ON githubrepo.id = release.repo_id \
+                WHERE githubrepo.name = 'flake' AND release.readme_hash IS NOT NULL \
+                    AND release.version IN ('1.0.0', '1.1.0', '1.2.0') \
+                    AND githubrepo.owner_id = (SELECT id FROM githubowner WHERE name = $1)",
+        )
+        .bind("test-readme-dedup")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        let body: Value = app
+            .get("/api/flake/github/test-readme-dedup/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let readme = app
+            .get("/api/flake/github/test-readme-dedup/flake/1.1.0/readme")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-readme-dedup").await;
+
+        assert_eq!(republished.status(), StatusCode::NO_CONTENT);
+        assert_eq!(stored, 2);
+        let releases: std::collections::HashMap<&str, (&Value, &Value)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| {
+                let version = release["version"].as_str().unwrap();
+                (version, (&release["readme"], &release["readme_hash"]))
+            })
+            .collect();
+        assert_eq!(releases["1.0.0"].0, "# Same");
+        assert_eq!(releases["1.2.0"].0, "# New");
+        assert_eq!(releases["1.0.0"].1, releases["1.1.0"].1);
+        assert_ne!(releases["1.0.0"].1, releases["1.2.0"].1);
+        assert_eq!(readme, "# Same");
+    }
+
+    #[tokio::test]
+    async fn test_publish_batch() {
+        let app = TestApp::with_state(|state| state.admin_token = Some("secret".to_string())).await;
+        let release = |version: &str, commit: &str| {
+            json!({
+                "owner": "Test-Publish-Batch",
+                "repo": "flake",
+                "version": version,
+                "commit": commit,
+            })
+        };
+        let batch = json!([
+            release("1.0.0", "f8f9f95"),
+            release("latest", "f8f9f95"),
+            release("v1.1.0", "f8f9f95"),
+            // The same release again, and then with another commit
+            release("1.0.0", "f8f9f95"),
+            release("1.0.0", "a1b2c3d"),
+        ]);
+
+        let unauthorized = app
+            .post("/api/publish/batch")
+            .json(&batch)
+            .send()
+            .await
+            .unwrap();
+        let too_many = app
+            .post("/api/publish/batch")
+            .bearer_auth("secret")
+            .json(&vec![release("1.0.0", "f8f9f95"); 101])
+            .send()
+            .await
+            .unwrap();
+        let response = app
+            .post("/api/publish/batch")
+            .bearer_auth("secret")
+            .json(&batch)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let results: Value = response.json().await.unwrap();
+        let body: Value = app
+            .get("/api/flake/github/test-publish-batch/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-publish-batch").await;
+
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(too_many.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(status, StatusCode::OK);
+        let statuses: Vec<&Value> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| &result["status"])
+            .collect();
+        assert_eq!(statuses, [201, 400, 201, 204, 409]);
+        assert_eq!(results[4]["message"], "Version 1.0.0 already exists");
+        assert_eq!(versions(&body), ["1.1.0", "1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_readme_type() {
+        let app = TestApp::new().await;
+        let publish = |version: &str, readme_type: Option<&str>| {
+            app.post("/api/publish")
+                .json(&json!({
+                    "owner": "test-readme-type",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                    "readme": "Title\n=====",
+                    "readme_type": readme_type,
+                }))
+                .send()
+        };
+
+        let statuses = [
+            publish("1.0.0", Some("RST")).await.unwrap().status(),
+            publish("1.1.0", None).await.unwrap().status(),
+            publish("1.2.0", Some("asciidoc")).await.unwrap().status(),
+        ];
+        let body: Value = app
+            .get("/api/flake/github/test-readme-type/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let readme = app
+            .get("/api/flake/github/test-readme-type/flake/1.0.0/readme")
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-readme-type").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::UNPROCESSABLE_ENTITY
+            ]
+        );
+        let readme_types: Vec<&Value> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| &release["readme_type"])
+            .collect();
+        assert_eq!(readme_types, ["markdown", "rst"]);
+        assert_eq!(
+            readme.headers()["content-type"],
+            "text/x-rst; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_verify_github() {
+        let github = Router::new()
+            .route(
+                "/repos/:owner/:repo",
+                get(|Path((owner, repo)): Path<(String, String)>| async move {
+                    if (owner.as_str(), repo.as_str()) == ("test-verify-github", "flake") {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::NOT_FOUND
+                    }
+                }),
+            )
+            .route(
+                "/repos/:owner/:repo/commits/:commit",
+                get(
+                    |Path((_, _, commit)): Path<(String, String, String)>| async move {
+                        if commit == "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad" {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::UNPROCESSABLE_ENTITY
+                        }
+                    },
+                ),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, github).await.unwrap() });
+        let app = TestApp::with_state(|state| {
+            state.github = Some(GitHub::new(format!("http://{addr}"), "token".to_string()))
+        })
+        .await;
+
+        let publishes = [
+            ("typo", "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad"),
+            ("flake", "0000000000000000000000000000000000000000"),
+            ("flake", "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad"),
+        ];
+        let mut statuses = Vec::new();
+        for (repo, commit) in publishes {
+            let response = app
+                .post("/api/publish")
+                .json(&json!({
+                    "owner": "test-verify-github",
+                    "repo": repo,
+                    "version": "1.0.0",
+                    "commit": commit,
+                }))
+                .send()
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        remove_owner(&app.pool, "test-verify-github").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::CREATED
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_verify_ancestry() {
+        // The commits of 1.0 and 1.1 follow each other, the one of 1.2 is older than 1.1's
+        let github = Router::new()
+            .route("/repos/:owner/:repo", get(|| async { StatusCode::OK }))
+            .route(
+                "/repos/:owner/:repo/commits/:commit",
+                get(|| async { StatusCode::OK }),
+            )
+            .route(
+                "/repos/:owner/:repo/compare/:range",
+                get(
+                    |Path((_, _, range)): Path<(String, String, String)>| async move {
+                        let status = match range.as_str() {
+                            "aaaaaaa...bbbbbbb" => "ahead",
+                            "bbbbbbb...ccccccc" => "behind",
+                            _ => return StatusCode::NOT_FOUND.into_response(),
+                        };
+                        axum::Json(json!({ "status": status })).into_response()
+                    },
+                ),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, github).await.unwrap() });
+        let app = TestApp::with_state(|state| {
+            state.github = Some(GitHub::new(format!("http://{addr}"), "token".to_string()));
+            state.verify_ancestry = true;
+        })
+        .await;
+
+        let publishes = [
+            ("1.0", "aaaaaaa"),
+            ("1.1", "bbbbbbb"),
+            ("1.2", "ccccccc"),
+            // Republishing an older version is no regression
+            ("1.0", "aaaaaaa"),
+        ];
+        let mut statuses = Vec::new();
+        for (version, commit) in publishes {
+            let response = app
+                .post("/api/publish")
+                .json(&json!({
+                    "owner": "test-verify-ancestry",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": commit,
+                }))
+                .send()
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        remove_owner(&app.pool, "test-verify-ancestry").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::CONFLICT,
+                StatusCode::NO_CONTENT
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_republish_policy() {
+        for policy in [Republish::Reject, Republish::Update] {
+            let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            let stub = Router::new().fallback(move |body: String| {
+                recorded.lock().unwrap().push(body);
+                async { axum::Json(json!({ "errors": false, "items": [] })) }
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+            tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+            let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+                .build()
+                .unwrap();
+            let opensearch = OpenSearch::new(transport);
+            let (queue, worker) = indexer::spawn(opensearch.clone(), IndexConfig::default());
+            let app = TestApp::with_state(|state| {
+                state.opensearch = opensearch;
+                state.index_queue = queue;
+                state.republish = policy;
+            })
+            .await;
+
+            // The tag of 1.0 moved to another commit
+            let mut statuses = Vec::new();
+            for (commit, description) in [
+                ("aaaaaaa", "Before"),
+                ("bbbbbbb", "After"),
+                ("bbbbbbb", "After"),
+            ] {
+                let response = app
+                    .post("/api/publish")
+                    .json(&json!({
+                        "owner": "test-republish",
+                        "repo": "flake",
+                        "version": "1.0",
+                        "commit": commit,
+                        "description": description,
+                    }))
+                    .send()
+                    .await
+                    .unwrap();
+                statuses.push(response.status());
+            }
+            worker.shutdown().await;
+
+            let release_id = release_id(&app.pool, "test-republish", "flake", "1.0").await;
+            let (commit, description): (String, String) =
+                sqlx::query_as("SELECT commit, description FROM release WHERE id = $1")
+                    .bind(release_id)
+                    .fetch_one(&app.pool)
+                    .await
+                    .unwrap();
+            let changes: Vec<(String, String)> = sqlx::query_as(
+                "SELECT previous_commit, commit FROM release_change WHERE release_id = $1",
+            )
+            .bind(release_id)
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+            remove_owner(&app.pool, "test-republish").await;
+
+            let requests = requests.lock().unwrap();
+            let lines: Vec<Value> = requests
+                .iter()
+                .flat_map(|body| body.lines())
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            let documents: Vec<(&Value, &Value)> = lines
+                .chunks(2)
+                .map(|pair| (&pair[0]["index"]["_id"], &pair[1]["description"]))
+                .collect();
+            let id = json!(release_id.to_string());
+
+            if policy == Republish::Reject {
+                assert_eq!(
+                    statuses,
+                    [
+                        StatusCode::CREATED,
+                        StatusCode::CONFLICT,
+                        StatusCode::CONFLICT
+                    ]
+                );
+                assert_eq!(
+                    (commit.as_str(), description.as_str()),
+                    ("aaaaaaa", "Before")
+                );
+                assert!(changes.is_empty());
+                assert_eq!(documents, [(&id, &json!("Before"))]);
+            } else {
+                // Publishing the updated release again has nothing left to do
+                assert_eq!(
+                    statuses,
+                    [StatusCode::CREATED, StatusCode::OK, StatusCode::NO_CONTENT]
+                );
+                assert_eq!(
+                    (commit.as_str(), description.as_str()),
+                    ("bbbbbbb", "After")
+                );
+                assert_eq!(changes, [("aaaaaaa".to_string(), "bbbbbbb".to_string())]);
+                assert_eq!(documents, [(&id, &json!("Before")), (&id, &json!("After"))]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_owner_avatars() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = lookups.clone();
+        let github = Router::new().route(
+            "/users/:owner",
+            get(move |Path(owner): Path<String>| async move {
+                counter.fetch_add(1, AtomicOrdering::SeqCst);
+                if owner == "test-avatars" {
+                    axum::Json(json!({ "avatar_url": "https://avatars.example/test-avatars" }))
+                        .into_response()
+                } else {
+                    StatusCode::NOT_FOUND.into_response()
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, github).await.unwrap() });
+        let app = TestApp::with_state(|state| {
+            state.avatars = Some(Arc::new(Avatars::new(format!("http://{addr}"), None)))
+        })
+        .await;
+        seed_repo(&app.pool, "test-avatars", "flake", &["1.0"]).await;
+        seed_repo(&app.pool, "test-no-avatar", "flake", &["1.0"]).await;
+
+        let mut releases = Vec::new();
+        for owner in ["test-avatars", "test-no-avatar", "test-avatars"] {
+            let body: Value = app
+                .get(&format!("/api/flake/github/{owner}/flake"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            releases.push(body["items"][0].clone());
+        }
+        remove_owner(&app.pool, "test-avatars").await;
+        remove_owner(&app.pool, "test-no-avatar").await;
+
+        assert_eq!(releases[0]["owner_url"], "https://github.com/test-avatars");
+        assert_eq!(
+            releases[0]["owner_avatar_url"],
+            "https://avatars.example/test-avatars"
+        );
+        assert!(releases[1].get("owner_avatar_url").is_none());
+        assert_eq!(releases[2], releases[0]);
+        // Later requests for the same owners are answered from the cache
+        assert_eq!(lookups.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_publish_allowed_owners() {
+        let app = TestApp::with_state(|state| {
+            state.allowed_publish_owners = vec!["test-allowed".to_string()];
+        })
+        .await;
+        let mut statuses = Vec::new();
+        for owner in ["Test-Allowed", "test-not-allowed"] {
+            let response = app
+                .post("/api/publish")
+                .json(&json!({
+                    "owner": owner,
+                    "repo": "flake",
+                    "version": "1.0.0",
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                }))
+                .send()
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        remove_owner(&app.pool, "test-allowed").await;
+        remove_owner(&app.pool, "test-not-allowed").await;
+
+        assert_eq!(statuses, [StatusCode::CREATED, StatusCode::FORBIDDEN]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_dry_run() {
+        let app = TestApp::new().await;
+        let publish = |commit: &str, dry_run: bool| {
+            app.post(&format!("/api/publish?dry_run={dry_run}"))
+                .json(&json!({
+                    "owner": "test-dry-run",
+                    "repo": "flake",
+                    "version": "1.0.0",
+                    "commit": commit,
+                }))
+                .send()
+        };
+        let commit = "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad";
+
+        let response = publish(commit, true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["unchanged"], false);
+        let owners: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM githubowner WHERE name = 'test-dry-run'")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(owners, 0);
+
+        let status = publish(commit, false).await.unwrap().status();
+        let response = publish(commit, true).await.unwrap();
+        let unchanged = response.json::<Value>().await.unwrap()["unchanged"].clone();
+        let conflict = publish("abcdef0", true).await.unwrap().status();
+        remove_owner(&app.pool, "test-dry-run").await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(unchanged, true);
+        assert_eq!(conflict, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_publish_dry_run_read_only() {
+        let app = TestApp::with_state(|state| state.read_only = true).await;
+
+        let response = app
+            .post("/api/publish?dry_run=true")
+            .json(&json!({
+                "owner": "test-dry-run-read-only",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_publish_normalizes_names() {
+        let app = TestApp::new().await;
+        let mut statuses = Vec::new();
+        for (owner, repo, version) in [
+            (" Test-Normalize ", "My-Flake\n", "1.0.0"),
+            ("test-normalize", "my-flake", "1.1.0"),
+            ("  ", "my-flake", "1.2.0"),
+        ] {
+            let response = app
+                .post("/api/publish")
+                .json(&json!({
+                    "owner": owner,
+                    "repo": repo,
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                }))
+                .send()
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        let owners: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM githubowner WHERE lower(name) = 'test-normalize'",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        let response = app
+            .get("/api/flake/github/TEST-normalize/My-Flake")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-normalize").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::BAD_REQUEST
+            ]
+        );
+        assert_eq!(owners, 1);
+        assert_eq!(body["items"][0]["owner"], "test-normalize");
+        assert_eq!(versions(&body), ["1.1.0", "1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_artifact_info() {
+        let app = TestApp::new().await;
+        let nar_hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        let publish = |version: &str, artifacts: Value| {
+            let mut publish = json!({
+                "owner": "test-artifact-info",
+                "repo": "flake",
+                "version": version,
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            });
+            publish
+                .as_object_mut()
+                .unwrap()
+                .extend(artifacts.as_object().unwrap().clone());
+            app.post("/api/publish").json(&publish).send()
+        };
+
+        let statuses = [
+            publish(
+                "1.0.0",
+                json!({ "closure_size": 123456, "nar_hash": nar_hash }),
+            )
+            .await
+            .unwrap()
+            .status(),
+            publish("1.1.0", json!({})).await.unwrap().status(),
+            publish("1.2.0", json!({ "closure_size": -1 }))
+                .await
+                .unwrap()
+                .status(),
+            publish("1.2.0", json!({ "nar_hash": "sha256-abc" }))
+                .await
+                .unwrap()
+                .status(),
+        ];
+        let body: Value = app
+            .get("/api/flake/github/test-artifact-info/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-artifact-info").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::BAD_REQUEST,
+                StatusCode::BAD_REQUEST
+            ]
+        );
+        assert_eq!(versions(&body), ["1.1.0", "1.0.0"]);
+        assert_eq!(body["items"][0]["closure_size"], Value::Null);
+        assert_eq!(body["items"][0]["nar_hash"], Value::Null);
+        assert_eq!(body["items"][1]["closure_size"], 123456);
+        assert_eq!(body["items"][1]["nar_hash"], nar_hash);
+    }
+
+    #[tokio::test]
+    async fn test_publish_output_kinds() {
+        let outputs = json!({
+            "packages": { "x86_64-linux": { "default": { "type": "derivation" } } },
+            "pakages": { "x86_64-linux": { "default": { "type": "derivation" } } },
+        });
+        let publish = |version: &str| {
+            json!({
+                "owner": "test-output-kinds",
+                "repo": "flake",
+                "version": version,
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "outputs": outputs,
+            })
+        };
+
+        let app = TestApp::new().await;
+        let lenient = app
+            .post("/api/publish")
+            .json(&publish("1.0.0"))
+            .send()
+            .await
+            .unwrap()
+            .status();
+        let listed: Value = app
+            .get("/api/flake/github/test-output-kinds/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let app = TestApp::with_state(|state| state.strict_output_kinds = true).await;
+        let strict = app
+            .post("/api/publish")
+            .json(&publish("1.1.0"))
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-output-kinds").await;
+
+        assert_eq!(lenient, StatusCode::CREATED);
+        assert_eq!(
+            listed["items"][0]["outputs"],
+            json!({ "packages": outputs["packages"] })
+        );
+        assert_eq!(strict.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = strict.json().await.unwrap();
+        assert!(body["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unknown output kinds pakages"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_identical_release() {
+        let app = TestApp::new().await;
+        let mut publish = json!({
+            "owner": "test-identical-release",
+            "repo": "flake",
+            "version": "1.0.0",
+            "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+            "readme": "# Flake",
+            "outputs": { "packages": { "x86_64-linux": { "default": { "type": "derivation" } } } },
+        });
+
+        let mut statuses = Vec::new();
+        for _ in 0..2 {
+            let response = app
+                .post("/api/publish")
+                .json(&publish)
+                .send()
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        publish["readme"] = json!("# Changed");
+        let response = app
+            .post("/api/publish")
+            .json(&publish)
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status());
+        remove_owner(&app.pool, "test-identical-release").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::NO_CONTENT,
+                StatusCode::CONFLICT
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_releases_feed() {
+        let app = TestApp::new().await;
+
+        let response = app.get("/api/releases.atom").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/atom+xml");
+
+        let body = response.text().await.unwrap();
+        assert!(body.starts_with("<?xml"));
+        assert!(body.contains("<title>nix-community/home-manager 23.05</title>"));
+        assert!(body
+            .contains("<link href=\"https://flakestry.dev/flake/github/nixos/nixpkgs/22.05\"/>"));
+    }
+
+    #[tokio::test]
+    async fn test_get_releases_feed_pages() {
+        let app = TestApp::new().await;
+        // More than a page of releases
+        let versions: Vec<String> = (0..60).map(|minor| format!("1.{minor}")).collect();
+        let versions: Vec<&str> = versions.iter().map(String::as_str).collect();
+        seed_repo(&app.pool, "test-feed-pages", "flake", &versions).await;
+
+        let mut pages = Vec::new();
+        for path in ["/api/releases.atom", "/api/releases.atom?page=2"] {
+            pages.push(app.get(path).send().await.unwrap().text().await.unwrap());
+        }
+        let [first, second] = &pages[..] else {
+            unreachable!()
+        };
+        let invalid = app
+            .get("/api/releases.atom?page=0")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        remove_owner(&app.pool, "test-feed-pages").await;
+
+        let links = |feed: &str| -> Vec<String> {
+            feed.lines()
+                .filter(|line| line.contains("<link rel="))
+                .map(|line| line.trim().to_string())
+                .collect()
+        };
+        assert_eq!(
+            links(first),
+            [
+                "<link rel=\"self\" href=\"https://flakestry.dev/api/releases.atom\"/>",
+                "<link rel=\"next\" href=\"https://flakestry.dev/api/releases.atom?page=2\"/>",
+            ]
+        );
+        assert_eq!(
+            links(second)[..2],
+            [
+                "<link rel=\"self\" href=\"https://flakestry.dev/api/releases.atom?page=2\"/>",
+                "<link rel=\"previous\" href=\"https://flakestry.dev/api/releases.atom\"/>",
+            ]
+        );
+        assert_eq!(first.matches("<entry>").count(), 50);
+        assert_eq!(invalid, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_explain() {
+        let id = release_id(&TestApp::new().await.pool, "nixos", "nixpkgs", "22.05").await;
+        let search_response = json!({
+            "hits": {
+                "total": { "value": 1, "relation": "eq" },
+                "hits": [{
+                    "_id": id.to_string(),
+                    "_score": 1.512_345_6,
+                    "_explanation": { "value": 1.512_345_6, "description": "sum of:", "details": [] },
+                }],
+            }
+        });
+
+        let opensearch = stub_opensearch(StatusCode::OK, search_response.clone()).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.search_debug = true;
+        })
+        .await;
+        let response = app
+            .get("/api/flake?q=nixpkgs&explain=true")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["items"][0]["score"], 1.512);
+        assert_eq!(body["items"][0]["explanation"]["description"], "sum of:");
+
+        // Without the debug flag explain is ignored
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app
+            .get("/api/flake?q=nixpkgs&explain=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["items"][0]["version"], "22.05");
+        assert!(body["items"][0].get("score").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_last_modified() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_modified = response.headers()["last-modified"].clone();
+        assert_eq!(last_modified, "Fri, 12 Jul 2024 23:08:41 GMT");
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = app
+            .get("/api/flake/github/nixos/nixpkgs")
+            .header("If-Modified-Since", "Thu, 11 Jul 2024 00:00:00 GMT")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_last_modified_changes() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "result": "deleted" })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.republish = Republish::Update;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        seed_repo(&app.pool, "test-last-modified", "flake", &["1.0", "1.1"]).await;
+        let path = "/api/flake/github/test-last-modified/flake";
+        // The status of revalidating what was last fetched, after each change. HTTP dates are in
+        // whole seconds, so each change waits for the next one.
+        let mut last_modified =
+            app.get(path).send().await.unwrap().headers()["last-modified"].clone();
+        let mut revalidated = Vec::new();
+        for change in ["republish", "delete"] {
+            tokio::time::sleep(Duration::from_millis(1100)).await;
+            let response = match change {
+                "republish" => app
+                    .post("/api/publish")
+                    .json(&json!({
+                        "owner": "test-last-modified",
+                        "repo": "flake",
+                        "version": "1.0",
+                        "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                    }))
+                    .send(),
+                _ => app
+                    .delete(&format!("{path}/1.1"))
+                    .bearer_auth("secret")
+                    .send(),
+            };
+            assert_eq!(response.await.unwrap().status(), StatusCode::OK, "{change}");
+
+            let response = app
+                .get(path)
+                .header("If-Modified-Since", &last_modified)
+                .send()
+                .await
+                .unwrap();
+            revalidated.push(response.status());
+            last_modified = response.headers()["last-modified"].clone();
+            let body: Value = response.json().await.unwrap();
+            let republished = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|release| release["version"] == "1.0")
+                .unwrap();
+            assert_eq!(
+                republished["commit"], "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "{change}"
+            );
+        }
+        let unchanged = app
+            .get(path)
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap()
+            .status();
+        remove_owner(&app.pool, "test-last-modified").await;
+
+        assert_eq!(revalidated, [StatusCode::OK, StatusCode::OK]);
+        assert_eq!(unchanged, StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_invalid_license() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .get("/api/flake?license=MIT%20%7C%7C%20x")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-invalid-license",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                "license": "<MIT>",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_post_flakes_batch() {
+        let app = TestApp::new().await;
+        let nixpkgs_22 = release_id(&app.pool, "nixos", "nixpkgs", "22.05").await;
+        let home_manager = release_id(&app.pool, "nix-community", "home-manager", "23.05").await;
+
+        let response = app
+            .post("/api/flakes/batch")
+            .json(&json!({ "ids": [home_manager, -1, nixpkgs_22] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["items"][0]["repo"], "home-manager");
+        assert_eq!(body["items"][1]["repo"], "nixpkgs");
+
+        // A full batch fetches the known releases among it
+        let mut ids: Vec<i32> = (-98..0).collect();
+        ids.extend([nixpkgs_22, home_manager]);
+        let response = app
+            .post("/api/flakes/batch")
+            .json(&json!({ "ids": ids }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["items"][0]["repo"], "nixpkgs");
+        assert_eq!(body["items"][1]["repo"], "home-manager");
+
+        let ids: Vec<i32> = (0..101).collect();
+        let response = app
+            .post("/api/flakes/batch")
+            .json(&json!({ "ids": ids }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_publish_invalid_commit() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .post("/api/publish")
+            .json(&json!({
+                "owner": "test-invalid-commit",
+                "repo": "flake",
+                "version": "1.0.0",
+                "commit": "main",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_total_relation() {
+        let search_response = json!({
+            "hits": { "total": { "value": 100000, "relation": "gte" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 100000);
+        assert_eq!(body["total_relation"], "gte");
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_search_paging() {
+        let search_response = json!({
+            "hits": { "total": { "value": 42, "relation": "eq" }, "hits": [] }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app
+            .get("/api/flake?q=nix&from=20&size=5")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 42);
+        assert_eq!(body["limit"], 5);
+        assert_eq!(body["offset"], 20);
+
+        let response = app.get("/api/flake?q=nix&page=3").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["offset"], 20);
+
+        for query in ["size=0", "size=51", "from=-1", "from=999&size=10"] {
+            let response = app
+                .get(&format!("/api/flake?q=nix&{query}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_point_in_time() {
+        let searches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = searches.clone();
+        let stub = Router::new().fallback(move |uri: axum::http::Uri, body: String| {
+            let recorded = recorded.clone();
+            async move {
+                if uri.path() == "/flakes/_search/point_in_time" {
+                    return (StatusCode::OK, axum::Json(json!({ "pit_id": "pit-1" })));
+                }
+                let body: Value = serde_json::from_str(&body).unwrap();
+                let pit_id = body["pit"]["id"].clone();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((uri.path().to_string(), pit_id.clone()));
+                if pit_id == "expired" {
+                    return (StatusCode::NOT_FOUND, axum::Json(json!({ "status": 404 })));
+                }
+                let hits = json!({
+                    "pit_id": "pit-2",
+                    "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] },
+                });
+                (StatusCode::OK, axum::Json(hits))
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let app = TestApp::with_state(|state| state.opensearch = OpenSearch::new(transport)).await;
+
+        let response = app.get("/api/flake?q=nix&pit=new").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["pit_id"], "pit-2");
+
+        let response = app
+            .get("/api/flake?q=nix&page=2&pit=pit-2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // An expired point in time isn't answered from the database, which has no snapshot
+        let response = app
+            .get("/api/flake?q=nix&pit=expired")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app.get("/api/flake?q=nix").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert!(body.get("pit_id").is_none());
+
+        assert_eq!(
+            *searches.lock().unwrap(),
+            [
+                ("/_search".to_string(), json!("pit-1")),
+                ("/_search".to_string(), json!("pit-2")),
+                ("/_search".to_string(), json!("expired")),
+                ("/flakes/_search".to_string(), Value::Null),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_readme_scope() {
+        let searched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = searched.clone();
+        let stub = Router::new().fallback(move |body: String| {
+            let recorded = recorded.clone();
+            async move {
+                let body: Value = serde_json::from_str(&body).unwrap();
+                let fields = &body["query"]["bool"]["must"]["multi_match"]["fields"];
+                recorded.lock().unwrap().push(fields.clone());
+                let hits = json!({
+                    "hits": { "total": { "value": 0, "relation": "eq" }, "hits": [] },
+                });
+                (StatusCode::OK, axum::Json(hits))
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let app = TestApp::with_state(|state| state.opensearch = OpenSearch::new(transport)).await;
+
+        for path in [
+            "/api/flake?q=overlay&scope=readme",
+            "/api/flake?q=overlay&scope=readme&boost=description:5",
+        ] {
+            let response = app.get(path).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+        }
+        let response = app
+            .get("/api/flake?q=overlay&scope=all")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let searched = searched.lock().unwrap().clone();
+        assert_eq!(searched[0], json!(["readme^1"]));
+        assert_eq!(searched[1], json!(["readme^1"]));
+        assert!(searched[2].as_array().unwrap().len() > 1);
+
+        let response = app
+            .get("/api/flake?q=overlay&scope=name")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // The database search doesn't match readmes, so it's no substitute
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let response = app
+            .get("/api/flake?q=overlay&scope=readme")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_relevance_order() {
+        let seeded = TestApp::new().await;
+        seed_repo(
+            &seeded.pool,
+            "relevance-order",
+            "flake",
+            &["1.0", "1.1", "1.2"],
+        )
+        .await;
+        let mut hits = Vec::new();
+        // The best match is neither the first nor the last release
+        for (version, score) in [("1.0", 1.0), ("1.1", 3.0), ("1.2", 2.0)] {
+            let id = release_id(&seeded.pool, "relevance-order", "flake", version).await;
+            hits.push(json!({ "_id": id.to_string(), "_score": score }));
+        }
+        let search_response = json!({
+            "hits": { "total": { "value": 3, "relation": "eq" }, "hits": hits }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=flake").send().await.unwrap();
+        remove_owner(&app.pool, "relevance-order").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), ["1.1", "1.2", "1.0"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_owner_search() {
+        // The stub has to know the ids of the seeded releases up front
+        let seeded = TestApp::new().await;
+        seed_repo(&seeded.pool, "owner-search", "flake", &["1.0", "1.1"]).await;
+        let old = release_id(&seeded.pool, "owner-search", "flake", "1.0").await;
+        let new = release_id(&seeded.pool, "owner-search", "flake", "1.1").await;
+
+        let search_response = json!({
+            "hits": {
+                "total": { "value": 2, "relation": "eq" },
+                "hits": [
+                    { "_id": old.to_string(), "_score": 1.0 },
+                    { "_id": new.to_string(), "_score": 3.0 },
+                ],
+            }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app
+            .get("/api/owner/Owner-Search/search?q=flake")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), ["1.1", "1.0"]);
+        assert_eq!(body["total"], 2);
+
+        let response = app
+            .get("/api/owner/owner-search/search")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        remove_owner(&app.pool, "owner-search").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_repo_search() {
+        let seeded = TestApp::new().await;
+        seed_repo(&seeded.pool, "repo-search", "flake", &["1.0", "1.1"]).await;
+        let old = release_id(&seeded.pool, "repo-search", "flake", "1.0").await;
+
+        let searched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = searched.clone();
+        let stub = Router::new().fallback(move |body: String| {
+            let recorded = recorded.clone();
+            async move {
+                let body: Value = serde_json::from_str(&body).unwrap();
+                recorded.lock().unwrap().push(body["query"]["bool"].clone());
+                let hits = json!({
+                    "hits": {
+                        "total": { "value": 1, "relation": "eq" },
+                        "hits": [{ "_id": old.to_string(), "_score": 2.0 }],
+                    }
+                });
+                (StatusCode::OK, axum::Json(hits))
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        let url = Url::parse(&format!("http://{addr}")).unwrap();
+        let transport = TransportBuilder::new(SingleNodeConnectionPool::new(url))
+            .build()
+            .unwrap();
+        let app = TestApp::with_state(|state| state.opensearch = OpenSearch::new(transport)).await;
+
+        let response = app
+            .get("/api/flake/github/Repo-Search/flake/search?q=segfault")
+            .send()
+            .await
+            .unwrap();
+        let statuses = [
+            app.get("/api/flake/github/repo-search/flake/search")
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            app.get("/api/flake/github/repo-search/unknown/search?q=segfault")
+                .send()
+                .await
+                .unwrap()
+                .status(),
+        ];
+        remove_owner(&app.pool, "repo-search").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), ["1.0"]);
+        assert_eq!(body["total"], 1);
+        let query = searched.lock().unwrap()[0].clone();
+        assert_eq!(
+            query["must"]["multi_match"]["fields"],
+            json!(["description^2", "readme^1"])
+        );
+        assert!(query["filter"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "term": { "full_name": "repo-search/flake" } })));
+        assert_eq!(statuses, [StatusCode::BAD_REQUEST, StatusCode::NOT_FOUND]);
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_skips_malformed_ids() {
+        let seeded = TestApp::new().await;
+        seed_repo(&seeded.pool, "malformed-ids", "flake", &["1.0"]).await;
+        let id = release_id(&seeded.pool, "malformed-ids", "flake", "1.0").await;
+
+        let search_response = json!({
+            "hits": {
+                "total": { "value": 2, "relation": "eq" },
+                "hits": [
+                    { "_id": "not-a-release", "_score": 2.0 },
+                    { "_id": id.to_string(), "_score": 1.0 },
+                ],
+            }
+        });
+        let opensearch = stub_opensearch(StatusCode::OK, search_response).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/flake?q=flake").send().await.unwrap();
+        remove_owner(&app.pool, "malformed-ids").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(versions(&body), ["1.0"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_recent_repos() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "recent-repos", "busy", &["1.0", "1.1", "1.2"]).await;
+        // The oldest version was released last, the repo is listed once with it
+        let latest = release_id(&app.pool, "recent-repos", "busy", "1.0").await;
+        sqlx::query("UPDATE release SET created_at = '2100-01-01' WHERE id = $1")
+            .bind(latest)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        // Released after the other versions of the busy repo, but before its latest one
+        sqlx::query(
+            "WITH repo AS (INSERT INTO githubrepo (name, owner_id, created_at) \
+                SELECT 'quiet', id, now() FROM githubowner WHERE name = 'recent-repos' RETURNING id) \
+                INSERT INTO release (repo_id, version, commit, created_at) \
+                SELECT id, '2.0', '123', '2099-12-31' FROM repo",
+        )
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        let response = app.get("/api/recent-repos?limit=2").send().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        let invalid = app.get("/api/recent-repos?limit=0").send().await.unwrap();
+        remove_owner(&app.pool, "recent-repos").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let repos: Vec<(&Value, &Value)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repo| (&repo["repo"], &repo["latest_version"]))
+            .collect();
+        assert_eq!(
+            repos,
+            [
+                (&json!("busy"), &json!("1.0")),
+                (&json!("quiet"), &json!("2.0"))
+            ]
+        );
+        assert!(body["total"].as_i64().unwrap() >= 2);
+        assert_eq!(body["limit"], 2);
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_catalog() {
+        let app = TestApp::new().await;
+        // Names starting with a digit come before the other repos
+        seed_repo(
+            &app.pool,
+            "0catalog-a",
+            "flake",
+            &["1.9", "v1.10", "2.0", "2.0-rc.1"],
+        )
+        .await;
+        seed_repo(&app.pool, "0catalog-b", "flake", &["0.1", "nightly"]).await;
+        let archived = seed_repo(&app.pool, "0catalog-0", "archived", &["1.0"]).await;
+        sqlx::query("UPDATE githubrepo SET archived = true WHERE id = $1")
+            .bind(archived)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let response = app.get("/api/catalog?limit=2").send().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        let next: Value = app
+            .get("/api/catalog?limit=1&offset=1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let invalid = app.get("/api/catalog?offset=-1").send().await.unwrap();
+        for owner in ["0catalog-a", "0catalog-b", "0catalog-0"] {
+            remove_owner(&app.pool, owner).await;
+        }
+
+        assert_eq!(status, StatusCode::OK);
+        let repos: Vec<(&Value, &Value)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repo| (&repo["owner"], &repo["latest_version"]))
+            .collect();
+        // The highest version rather than the last one published, stable before pre-release
+        assert_eq!(
+            repos,
+            [
+                (&json!("0catalog-a"), &json!("2.0")),
+                (&json!("0catalog-b"), &json!("0.1"))
+            ]
+        );
+        assert_eq!(next["items"][0]["owner"], "0catalog-b");
+        assert!(body["total"].as_i64().unwrap() >= 2);
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // Responses are hashed for ETags and snapshot tests, so the same data has to serialize to the
+    // same bytes
+    #[tokio::test]
+    async fn test_responses_are_deterministic() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "deterministic", "flake", &["1.0", "v1.0", "1.1"]).await;
+
+        for path in [
+            "/api/flake",
+            "/api/flake?q=deterministic",
+            "/api/flake/github/deterministic/flake",
+            "/api/trending",
+            "/api/recent-repos",
+            "/api/catalog",
+        ] {
+            let first = app.get(path).send().await.unwrap().bytes().await.unwrap();
+            let second = app.get(path).send().await.unwrap().bytes().await.unwrap();
+            assert_eq!(first, second, "{path}");
+        }
+        remove_owner(&app.pool, "deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_outputs_diff() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "diff-owner", "diff-repo", &["1.0.0", "1.1.0"]).await;
+        let outputs = [
+            (
+                "1.0.0",
+                json!({
+                    "packages": { "x86_64-linux": {
+                        "default": { "type": "derivation", "name": "hello-1.0" },
+                        "old": { "type": "derivation", "name": "old-1.0" }
+                    }},
+                    "overlays": { "default": { "type": "nixpkgs-overlay" } }
+                }),
+            ),
+            (
+                "1.1.0",
+                json!({
+                    "packages": { "x86_64-linux": {
+                        "default": { "type": "derivation", "name": "hello-1.1" },
+                        "new": { "type": "derivation", "name": "new-1.1" }
+                    }},
+                    "overlays": { "default": { "type": "nixpkgs-overlay" } }
+                }),
+            ),
+        ];
+        for (version, outputs) in outputs {
+            sqlx::query("UPDATE release SET outputs = $1 WHERE id = $2")
+                .bind(outputs)
+                .bind(release_id(&app.pool, "diff-owner", "diff-repo", version).await)
+                .execute(&app.pool)
+                .await
+                .unwrap();
+        }
+
+        let response = app
+            .get("/api/flake/github/diff-owner/diff-repo/diff?from=1.0.0&to=1.1.0")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["added"], json!(["packages.x86_64-linux.new"]));
+        assert_eq!(body["removed"], json!(["packages.x86_64-linux.old"]));
+        assert_eq!(body["changed"], json!(["packages.x86_64-linux.default"]));
+
+        let response = app
+            .get("/api/flake/github/diff-owner/diff-repo/diff?from=1.0.0&to=2.0.0")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        remove_owner(&app.pool, "diff-owner").await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_index_document() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "result": "deleted" })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+
+        let response = app.delete("/api/admin/index/1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .delete("/api/admin/index/1")
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "existed": true }));
+    }
+
+    #[tokio::test]
+    async fn test_delete_index_document_read_only() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "result": "deleted" })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+            state.read_only = true;
+        })
+        .await;
+
+        let response = app
+            .delete("/api/admin/index/1")
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_get_index_document() {
+        let document = json!({ "owner": "nixos", "repo": "nixpkgs" });
+        let stored = json!({ "_id": "1", "found": true, "_source": document });
+        let opensearch = stub_opensearch(StatusCode::OK, stored).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+
+        let response = app.get("/api/admin/index/1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .get("/api/admin/index/1")
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, document);
+
+        let missing = json!({ "_id": "2", "found": false });
+        let opensearch = stub_opensearch(StatusCode::NOT_FOUND, missing).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let response = app
+            .get("/api/admin/index/2")
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_min_version() {
+        let app = TestApp::new().await;
+        let published = ["1.0", "1.2.0-rc.1", "1.2.0", "1.10", "2024-01-15"];
+        seed_repo(&app.pool, "min-version", "flake", &published).await;
+
+        let path = "/api/flake/github/min-version/flake";
+        let response = app
+            .get(&format!("{path}?min_version=1.2.0"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        let statuses = [
+            app.get(&format!("{path}?min_version=1.2"))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            app.get(&format!("{path}?min_version=latest"))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+        ];
+        remove_owner(&app.pool, "min-version").await;
+
+        assert_eq!(versions(&body), ["1.10", "1.2.0"]);
+        // The repo metadata still counts every release
+        assert_eq!(body["meta"]["releases"], 5);
+        assert_eq!(statuses, [StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST]);
+    }
+
+    #[tokio::test]
+    async fn test_read_repo_published_by() {
+        let app = TestApp::with_state(|state| state.admin_token = Some("secret".to_string())).await;
+        let repo_id = seed_repo(&app.pool, "published-by", "flake", &["1.0"]).await;
+        sqlx::query("UPDATE release SET published_by = 'ci' WHERE repo_id = $1")
+            .bind(repo_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let path = "/api/flake/github/published-by/flake";
+        let response = app.get(path).send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert!(body["items"][0].get("published_by").is_none());
+
+        let response = app.get(path).bearer_auth("secret").send().await.unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["items"][0]["published_by"], "ci");
+
+        remove_owner(&app.pool, "published-by").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_releases_after() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "releases-after", "flake", &["1.0", "1.1"]).await;
+        let first = release_id(&app.pool, "releases-after", "flake", "1.0").await;
+        let last = release_id(&app.pool, "releases-after", "flake", "1.1").await;
+
+        let response = app
+            .get(&format!("/api/releases?after_id={}&limit=250", first - 1))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        // Other tests may publish concurrently
+        let synced: Vec<&str> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|release| release["owner"] == "releases-after")
+            .map(|release| release["version"].as_str().unwrap())
+            .collect();
+        assert_eq!(synced, ["1.0", "1.1"]);
+        assert!(body["max_id"].as_i64().unwrap() >= i64::from(last));
+
+        let response = app
+            .get(&format!("/api/releases?after_id={last}&limit=1"))
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_ne!(body["items"][0]["owner"], "releases-after");
+
+        let response = app.get("/api/releases?after_id=-1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        remove_owner(&app.pool, "releases-after").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_export() {
+        let app = TestApp::new().await;
+        seed_repo(&app.pool, "test-export", "flake", &["1.0", "1.1", "1.2"]).await;
+        let first = release_id(&app.pool, "test-export", "flake", "1.0").await;
+
+        let app = &app;
+        let export = |since_id: i32| async move {
+            let response = app
+                .get(&format!("/api/export.ndjson?since_id={since_id}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+            let body = response.text().await.unwrap();
+            let releases: Vec<Value> = body
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            releases
+        };
+        let all = export(first - 1).await;
+        let since = export(first).await;
+        let invalid = app
+            .get("/api/export.ndjson?since_id=-1")
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-export").await;
+
+        // Other tests may publish concurrently
+        let versions = |releases: &[Value]| -> Vec<String> {
+            releases
+                .iter()
+                .filter(|release| release["owner"] == "test-export")
+                .map(|release| release["version"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(versions(&all), ["1.0", "1.1", "1.2"]);
+        assert_eq!(versions(&since), ["1.1", "1.2"]);
+        assert!(all
+            .windows(2)
+            .all(|pair| pair[0]["id"].as_i64() < pair[1]["id"].as_i64()));
+        assert_eq!(all[0]["flake_ref"], "github:test-export/flake/123");
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_delete_release() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "result": "deleted" })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        seed_repo(&app.pool, "test-delete-release", "flake", &["1.0", "1.1"]).await;
+        let release_id = release_id(&app.pool, "test-delete-release", "flake", "1.0").await;
+
+        let path = "/api/flake/github/test-delete-release/flake/1.0";
+        let response = app.delete(path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.delete(path).bearer_auth("secret").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({ "release_id": release_id, "deleted_release": true, "deleted_document": true })
+        );
+
+        // Deleting it again has nothing left to do
+        let response = app.delete(path).bearer_auth("secret").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["release_id"], Value::Null);
+
+        let response = app
+            .get("/api/flake/github/test-delete-release/flake")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        remove_owner(&app.pool, "test-delete-release").await;
+        assert_eq!(versions(&body), ["1.1"]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_owners() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "updated": 1 })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        seed_repo(&app.pool, "test-merge-old", "flake", &["1.0"]).await;
+        seed_repo(&app.pool, "test-merge-clash", "flake", &["1.0"]).await;
+
+        let merge = |from: &str, to: &str| {
+            app.post("/api/admin/owner/merge")
+                .bearer_auth("secret")
+                .json(&json!({ "from": from, "to": to }))
+                .send()
+        };
+        let response = merge("test-merge-old", "Test-Merge-New").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({ "owner": "test-merge-new", "repos_moved": 1, "documents_updated": 1 })
+        );
+
+        // Merging again only updates the search documents
+        let again: Value = merge("test-merge-old", "test-merge-new")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let statuses = [
+            merge("test-merge-unknown", "test-merge-new")
+                .await
+                .unwrap()
+                .status(),
+            merge("test-merge-clash", "test-merge-new")
+                .await
+                .unwrap()
+                .status(),
+        ];
+        let response = app
+            .get("/api/flake/github/test-merge-new/flake")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        let redirect = app
+            .get("/api/flake/github/Test-Merge-Old/flake?view=compact")
+            .send()
+            .await
+            .unwrap();
+        // Renaming back makes the old name canonical again
+        let renamed_back = merge("test-merge-new", "test-merge-old").await.unwrap();
+        let not_redirected = app
+            .get("/api/flake/github/test-merge-old/flake")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        let redirected_back = app
+            .get("/api/flake/github/test-merge-new/flake")
+            .send()
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-merge-old").await;
+        remove_owner(&app.pool, "test-merge-clash").await;
+
+        assert_eq!(
+            again,
+            json!({ "owner": "test-merge-new", "repos_moved": 0, "documents_updated": 1 })
+        );
+        assert_eq!(statuses, [StatusCode::NOT_FOUND, StatusCode::CONFLICT]);
+        assert_eq!(response, StatusCode::OK);
+        assert_eq!(redirect.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            redirect.headers()["location"],
+            "/api/flake/github/test-merge-new/flake?view=compact"
+        );
+        assert_eq!(renamed_back.status(), StatusCode::OK);
+        assert_eq!(not_redirected, StatusCode::OK);
+        assert_eq!(
+            redirected_back.headers()["location"],
+            "/api/flake/github/test-merge-old/flake"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_owners_search_unavailable() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        seed_repo(&app.pool, "test-merge-down-old", "flake", &["1.0"]).await;
+        let merge = |app: &TestApp| {
+            app.post("/api/admin/owner/merge")
+                .bearer_auth("secret")
+                .json(&json!({ "from": "test-merge-down-old", "to": "test-merge-down-new" }))
+                .send()
+        };
+
+        let failed = merge(&app).await.unwrap().status();
+        // The merge is committed all the same, the documents are left for a retry
+        let moved = app
+            .get("/api/flake/github/test-merge-down-new/flake")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "updated": 1 })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let retried: Value = merge(&app).await.unwrap().json().await.unwrap();
+        remove_owner(&app.pool, "test-merge-down-new").await;
+
+        assert_eq!(failed, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(moved, StatusCode::OK);
+        assert_eq!(
+            retried,
+            json!({ "owner": "test-merge-down-new", "repos_moved": 0, "documents_updated": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backfill_descriptions() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "updated": 1 })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let repo_id = seed_repo(&app.pool, "test-backfill", "flake", &["1.0", "1.1"]).await;
+        sqlx::query(
+            "UPDATE release SET readme = CASE version \
+                WHEN '1.0' THEN E'# Flake\n\nPackages for tests.' ELSE '# Flake' END \
+                WHERE repo_id = $1",
+        )
+        .bind(repo_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        let backfill = || {
+            app.post("/api/admin/backfill-descriptions")
+                .bearer_auth("secret")
+                .send()
+        };
+        let unauthorized = app
+            .post("/api/admin/backfill-descriptions")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        let response = backfill().await.unwrap();
+        let status = response.status();
+        let body: Value = response.json().await.unwrap();
+        // Running it again leaves the descriptions alone
+        let again = backfill().await.unwrap().status();
+        let descriptions: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT version, description FROM release WHERE repo_id = $1 ORDER BY version",
+        )
+        .bind(repo_id)
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        remove_owner(&app.pool, "test-backfill").await;
+
+        assert_eq!(unauthorized, StatusCode::UNAUTHORIZED);
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["releases_updated"].as_u64().unwrap() >= 1);
+        assert_eq!(again, StatusCode::OK);
+        assert_eq!(
+            descriptions,
+            [
+                ("1.0".to_string(), Some("Packages for tests.".to_string())),
+                ("1.1".to_string(), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_flake_archived() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let repo_id = seed_repo(&app.pool, "test-archived", "flake", &["1.0"]).await;
+        sqlx::query("UPDATE githubrepo SET archived = true WHERE id = $1")
+            .bind(repo_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let mut owners = Vec::new();
+        for path in [
+            "/api/flake",
+            "/api/flake?include_archived=true",
+            "/api/flake?q=test-archived",
+            "/api/flake?q=test-archived&include_archived=true",
+        ] {
+            let body: Value = app.get(path).send().await.unwrap().json().await.unwrap();
+            let archived = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|item| item["owner"] == "test-archived")
+                .count();
+            owners.push(archived);
+        }
+        remove_owner(&app.pool, "test-archived").await;
+
+        assert_eq!(owners, [0, 1, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_systems() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let publish = |version: &str, systems: Value| {
+            app.post("/api/publish")
+                .json(&json!({
+                    "owner": "test-systems",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                    "systems": systems,
+                }))
+                .send()
+        };
+        let statuses = [
+            publish(
+                "1.0.0",
+                json!(["x86_64-linux", "aarch64-darwin", "x86_64-linux"]),
+            )
+            .await
+            .unwrap()
+            .status(),
+            publish("1.1.0", json!(["x86_64-linux"]))
+                .await
+                .unwrap()
+                .status(),
+            publish("1.2.0", json!(["x86_64"])).await.unwrap().status(),
+        ];
+
+        let repo: Value = app
+            .get("/api/flake/github/test-systems/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        // Searched in the database, OpenSearch being down
+        let search: Value = app
+            .get("/api/flake?q=test-systems&system=aarch64-darwin")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let invalid = app
+            .get("/api/flake?system=Linux")
+            .send()
+            .await
+            .unwrap()
+            .status();
+        remove_owner(&app.pool, "test-systems").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::BAD_REQUEST
+            ]
+        );
+        assert_eq!(
+            repo["items"][1]["systems"],
+            json!(["aarch64-darwin", "x86_64-linux"])
+        );
+        assert_eq!(versions(&search), ["1.0.0"]);
+        assert_eq!(invalid, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_publish_tags() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+        let publish = |version: &str, tags: Value| {
+            app.post("/api/publish")
+                .json(&json!({
+                    "owner": "test-tags",
+                    "repo": "flake",
+                    "version": version,
+                    "commit": "f8f9f95b9f4cf91f6ad552132f196741daf3b1ad",
+                    "tags": tags,
+                }))
+                .send()
+        };
+        let statuses = [
+            publish("1.0.0", json!(["CLI", "web", " cli "]))
+                .await
+                .unwrap()
+                .status(),
+            publish("1.0.0", json!(["web", "cli"]))
+                .await
+                .unwrap()
+                .status(),
+            publish("1.1.0", json!(["web"])).await.unwrap().status(),
+            publish("1.2.0", json!(["two words"]))
+                .await
+                .unwrap()
+                .status(),
+        ];
+
+        let repo: Value = app
+            .get("/api/flake/github/test-tags/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        // Searched in the database, OpenSearch being down
+        let search: Value = app
+            .get("/api/flake?q=test-tags&tag=Cli")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let tags = app.get("/api/tags").send().await.unwrap().status();
+        remove_owner(&app.pool, "test-tags").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::NO_CONTENT,
+                StatusCode::CREATED,
+                StatusCode::BAD_REQUEST
+            ]
+        );
+        assert_eq!(repo["items"][1]["tags"], json!(["cli", "web"]));
+        assert_eq!(versions(&search), ["1.0.0"]);
+        assert_eq!(tags, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_get_tags() {
+        let aggregations = json!({ "aggregations": { "tags": { "buckets": [
+            { "key": "nixos", "doc_count": 2 },
+            { "key": "cli", "doc_count": 5 },
+        ] } } });
+        let opensearch = stub_opensearch(StatusCode::OK, aggregations).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/tags?limit=2").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!([{ "value": "cli", "count": 5 }, { "value": "nixos", "count": 2 }])
+        );
+
+        let invalid = app.get("/api/tags?limit=0").send().await.unwrap().status();
+        assert_eq!(invalid, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stats() {
+        let aggregations = json!({ "aggregations": { "outputs": { "buckets": [
+            { "key": "nixosModules", "doc_count": 9, "repos": { "value": 2 } },
+            { "key": "packages", "doc_count": 4, "repos": { "value": 3 } },
+        ] } } });
+        let (opensearch, requests) = counting_opensearch(StatusCode::OK, aggregations).await;
+        let app = TestApp::with_state(|state| state.opensearch = opensearch).await;
+
+        let response = app.get("/api/stats/outputs").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!([{ "value": "packages", "count": 3 }, { "value": "nixosModules", "count": 2 }])
+        );
+
+        // Served from memory until the cache expires
+        let response = app.get("/api/stats/outputs").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_random_release() {
+        let app = TestApp::new().await;
+        let draw = |path: &'static str| async {
+            let response = app.get(path).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let cache_control = response.headers()["cache-control"].clone();
+            let body: Value = response.json().await.unwrap();
+            (
+                body["owner"].clone(),
+                body["repo"].clone(),
+                body["version"].clone(),
+                cache_control,
+            )
+        };
+
+        let random = draw("/api/flake/random").await;
+        assert!(random.1.is_string());
+        assert_eq!(random.3, "no-store");
+        // Releases other tests publish in between could win the draw, unlikely back to back
+        let seeded = draw("/api/flake/random?seed=homepage").await;
+        assert_eq!(draw("/api/flake/random?seed=homepage").await, seeded);
+        assert_eq!(seeded.3, "public, max-age=60");
+    }
+
+    #[tokio::test]
+    async fn test_recommended_release() {
+        let app = TestApp::with_state(|state| state.admin_token = Some("secret".to_string())).await;
+        seed_repo(
+            &app.pool,
+            "test-recommended",
+            "flake",
+            &["1.0.0", "1.1.0", "2.0.0-rc.1"],
+        )
+        .await;
+
+        let recommended = || async {
+            let response = app
+                .get("/api/flake/github/test-recommended/flake/recommended")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.json().await.unwrap();
+            (body["version"].clone(), body["recommended"].clone())
+        };
+        let recommend = |version: Value, token: &str| {
+            app.put("/api/admin/repo/test-recommended/flake/recommended")
+                .bearer_auth(token)
+                .json(&json!({ "version": version }))
+                .send()
+        };
+
+        let newest_stable = recommended().await;
+        let response = recommend(json!("1.0.0"), "secret").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        let chosen = recommended().await;
+        // Recommending another one replaces it
+        recommend(json!("2.0.0-rc.1"), "secret").await.unwrap();
+        let replaced = recommended().await;
+        let repo: Value = app
+            .get("/api/flake/github/test-recommended/flake")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        recommend(Value::Null, "secret").await.unwrap();
+        let cleared = recommended().await;
+        let statuses = [
+            recommend(json!("1.0.0"), "wrong").await.unwrap().status(),
+            recommend(json!("3.0.0"), "secret").await.unwrap().status(),
+        ];
+        remove_owner(&app.pool, "test-recommended").await;
+
+        assert_eq!(newest_stable, (json!("1.1.0"), json!(false)));
+        assert_eq!(
+            body,
+            json!({ "owner": "test-recommended", "repo": "flake", "recommended": "1.0.0" })
+        );
+        assert_eq!(chosen, (json!("1.0.0"), json!(true)));
+        assert_eq!(replaced, (json!("2.0.0-rc.1"), json!(true)));
+        let flags: Vec<&Value> = repo["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|release| &release["recommended"])
+            .collect();
+        assert_eq!(flags, [&json!(true), &json!(false), &json!(false)]);
+        assert_eq!(cleared, (json!("1.1.0"), json!(false)));
+        assert_eq!(statuses, [StatusCode::UNAUTHORIZED, StatusCode::NOT_FOUND]);
+    }
+
+    #[tokio::test]
+    async fn test_put_repo_archived() {
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "updated": 1 })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let repo_id = seed_repo(&app.pool, "test-put-archived", "flake", &["1.0"]).await;
+
+        let archive = |path: &str, token: &str| {
+            app.put(path)
+                .bearer_auth(token)
+                .json(&json!({ "archived": true }))
+                .send()
+        };
+        let response = archive("/api/admin/repo/Test-Put-Archived/flake/archived", "secret")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        let statuses = [
+            archive("/api/admin/repo/test-put-archived/flake/archived", "wrong")
+                .await
+                .unwrap()
+                .status(),
+            archive("/api/admin/repo/test-put-archived/other/archived", "secret")
+                .await
+                .unwrap()
+                .status(),
+        ];
+        let archived: bool = sqlx::query_scalar("SELECT archived FROM githubrepo WHERE id = $1")
+            .bind(repo_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        remove_owner(&app.pool, "test-put-archived").await;
+
+        assert_eq!(
+            body,
+            json!({
+                "owner": "test-put-archived",
+                "repo": "flake",
+                "archived": true,
+                "documents_updated": 1,
+            })
+        );
+        assert_eq!(statuses, [StatusCode::UNAUTHORIZED, StatusCode::NOT_FOUND]);
+        assert!(archived);
+    }
+
+    #[tokio::test]
+    async fn test_put_repo_archived_search_unavailable() {
+        let opensearch = failing_opensearch().await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let repo_id = seed_repo(&app.pool, "test-archived-down", "flake", &["1.0"]).await;
+        let archive = |app: &TestApp| {
+            app.put("/api/admin/repo/test-archived-down/flake/archived")
+                .bearer_auth("secret")
+                .json(&json!({ "archived": true }))
+                .send()
+        };
+
+        let failed = archive(&app).await.unwrap().status();
+        // It's archived all the same, the documents are left for a retry
+        let archived: bool = sqlx::query_scalar("SELECT archived FROM githubrepo WHERE id = $1")
+            .bind(repo_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        let opensearch = stub_opensearch(StatusCode::OK, json!({ "updated": 1 })).await;
+        let app = TestApp::with_state(|state| {
+            state.opensearch = opensearch;
+            state.admin_token = Some("secret".to_string());
+        })
+        .await;
+        let retried = archive(&app).await.unwrap();
+        let retried_status = retried.status();
+        let retried: Value = retried.json().await.unwrap();
+        remove_owner(&app.pool, "test-archived-down").await;
+
+        assert_eq!(failed, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(archived);
+        assert_eq!(retried_status, StatusCode::OK);
+        assert_eq!(retried["documents_updated"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_commit_releases() {
+        let app = TestApp::new().await;
+        let repo_id = seed_repo(&app.pool, "test-commit", "flake", &["1.0", "1.1"]).await;
+        sqlx::query(
+            "UPDATE release SET commit = '0ddba11c0ffee0ddba11c0ffee0ddba11c0ffee0' \
+                WHERE repo_id = $1 AND version = '1.1'",
+        )
+        .bind(repo_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        let mut statuses = Vec::new();
+        let mut bodies = Vec::new();
+        for sha in [
+            "0ddba11c0ffee0ddba11c0ffee0ddba11c0ffee0",
+            "0DDBA11C0FFEE",
+            "0ddba11c0ffee0ddba11c0ffee0ddba11c0ffee1",
+            "0ddba11",
+            "0ddb",
+        ] {
+            let response = app.get(&format!("/api/commit/{sha}")).send().await.unwrap();
+            statuses.push(response.status());
+            if response.status() == StatusCode::OK {
+                bodies.push(response.json::<Value>().await.unwrap());
+            }
+        }
+        remove_owner(&app.pool, "test-commit").await;
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::NOT_FOUND,
+                StatusCode::OK,
+                StatusCode::BAD_REQUEST,
+            ]
+        );
+        for body in bodies {
+            assert_eq!(versions(&body), ["1.1"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_readme() {
+        let app = TestApp::new().await;
+        seed_repo(
+            &app.pool,
+            "readme-owner",
+            "readme-repo",
+            &["1.0.0", "1.1.0"],
+        )
+        .await;
+        sqlx::query("UPDATE release SET readme = '# Hello' WHERE id = $1")
+            .bind(release_id(&app.pool, "readme-owner", "readme-repo", "1.0.0").await)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let response = app
+            .get("/api/flake/github/readme-owner/readme-repo/1.0.0/readme")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/markdown; charset=utf-8"
+        );
+        assert_eq!(response.text().await.unwrap(), "# Hello");
+
+        for version in ["1.1.0", "2.0.0"] {
+            let response = app
+                .get(&format!(
+                    "/api/flake/github/readme-owner/readme-repo/{version}/readme"
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{version}");
+        }
+
+        remove_owner(&app.pool, "readme-owner").await;
+    }
 }
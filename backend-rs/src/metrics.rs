@@ -0,0 +1,127 @@
+//! Latency histograms of the calls to OpenSearch and Postgres, scraped by Prometheus from
+//! `/metrics`. They are global rather than part of `AppState` since the database helpers are
+//! only handed a pool.
+
+use axum::{http::header, response::IntoResponse};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    future::Future,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+// Upper bounds in seconds, from a quick index lookup to a query about to time out
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Backend {
+    OpenSearch,
+    Postgres,
+}
+
+impl Backend {
+    fn label(self) -> &'static str {
+        match self {
+            Backend::OpenSearch => "opensearch",
+            Backend::Postgres => "postgres",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Not cumulative, each count is of the calls between the previous bound and this one
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+static LATENCIES: LazyLock<Mutex<BTreeMap<(Backend, &'static str), Histogram>>> =
+    LazyLock::new(Default::default);
+
+pub fn record(backend: Backend, operation: &'static str, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let mut latencies = LATENCIES.lock().unwrap();
+    let histogram = latencies.entry((backend, operation)).or_default();
+    if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+        histogram.buckets[bucket] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+/// Records how long `call` takes, whether it succeeds or not.
+pub async fn time<T>(
+    backend: Backend,
+    operation: &'static str,
+    call: impl Future<Output = T>,
+) -> T {
+    let start = Instant::now();
+    let result = call.await;
+    record(backend, operation, start.elapsed());
+    result
+}
+
+// The Prometheus text format of the histograms
+fn render() -> String {
+    let mut text = String::from(
+        "# HELP flakestry_backend_duration_seconds Latency of calls to OpenSearch and Postgres\n\
+         # TYPE flakestry_backend_duration_seconds histogram\n",
+    );
+    for ((backend, operation), histogram) in LATENCIES.lock().unwrap().iter() {
+        let labels = format!("backend=\"{}\",operation=\"{operation}\"", backend.label());
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "flakestry_backend_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            text,
+            "flakestry_backend_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}\n\
+             flakestry_backend_duration_seconds_sum{{{labels}}} {}\n\
+             flakestry_backend_duration_seconds_count{{{labels}}} {}",
+            histogram.count, histogram.sum, histogram.count
+        );
+    }
+    text
+}
+
+pub async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        record(Backend::Postgres, "test_render", Duration::from_millis(20));
+        record(Backend::Postgres, "test_render", Duration::from_secs(30));
+
+        let labels = "backend=\"postgres\",operation=\"test_render\"";
+        let bucket = |le: &str, count: u64| {
+            format!("flakestry_backend_duration_seconds_bucket{{{labels},le=\"{le}\"}} {count}")
+        };
+        let text = render();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            bucket("0.01", 0),
+            bucket("0.025", 1),
+            bucket("10", 1),
+            bucket("+Inf", 2),
+            format!("flakestry_backend_duration_seconds_count{{{labels}}} 2"),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "{expected}");
+        }
+    }
+}
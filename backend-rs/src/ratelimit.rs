@@ -0,0 +1,139 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::common::AppState;
+
+/// Lets each client IP make `limit` requests per `window`, as a token bucket refilled evenly
+/// over the window so that a client can burst up to the whole limit.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    // Buckets left alone for a whole window are full again, so they are dropped once per window
+    pruned_at: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                pruned_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a request from the bucket of `ip`, or tells how long until it has one again.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let limit = f64::from(self.limit);
+        let per_second = limit / self.window.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.pruned_at) >= self.window {
+            let window = self.window;
+            buckets
+                .by_ip
+                .retain(|_, bucket| now.duration_since(bucket.updated_at) < window);
+            buckets.pruned_at = now;
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: limit,
+            updated_at: now,
+        });
+        let refilled = now.duration_since(bucket.updated_at).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(limit);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+// Probes come from the load balancer and the orchestrator, which mustn't be locked out
+fn is_exempt(path: &str) -> bool {
+    path == "/api/health" || path.starts_with("/health/")
+}
+
+/// Answers 429 with a `Retry-After` once the client IP used up its requests, with
+/// `RATE_LIMIT_PER_MINUTE`.
+pub async fn limit_rate(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let limiter = state
+        .rate_limiter
+        .as_ref()
+        .filter(|_| !is_exempt(req.uri().path()));
+    if let Some(Err(retry_after)) = limiter.map(|limiter| limiter.check(addr.ip())) {
+        // Whole seconds, a client retrying early would only be limited again
+        let retry_after = retry_after.as_secs_f64().ceil() as u64;
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(json!({ "detail": "Too many requests, please try again later" })),
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(2));
+        let (client, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(limiter.check_at(client, start), Ok(()));
+        assert_eq!(limiter.check_at(client, start), Ok(()));
+        // Half a request is back after half a second, the other half takes as long again
+        assert_eq!(
+            limiter.check_at(client, at(500)),
+            Err(Duration::from_millis(500))
+        );
+        // Clients don't share their buckets
+        assert_eq!(limiter.check_at(other, at(500)), Ok(()));
+        assert_eq!(limiter.check_at(client, at(1000)), Ok(()));
+
+        // Buckets left alone for a window are dropped
+        limiter.check_at(client, at(10_000)).unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().by_ip.len(), 1);
+    }
+}
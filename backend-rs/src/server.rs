@@ -0,0 +1,130 @@
+use axum::{body::Body, extract::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::{env, future::Future, io, net::SocketAddr, time::Duration};
+use tokio::{net::TcpListener, task::JoinSet};
+use tower::{Service, ServiceExt};
+
+/// Connection settings of the HTTP server, the defaults match what `axum::serve` does.
+#[derive(Default)]
+pub struct ServerConfig {
+    // Serves HTTP/2 with prior knowledge next to HTTP/1
+    pub http2: bool,
+    pub http2_max_concurrent_streams: Option<u32>,
+    pub tcp_keepalive: Option<Duration>,
+    // How long requests in flight get to finish on shutdown before their connections are closed
+    pub shutdown_grace: Duration,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        ServerConfig {
+            http2: env::var("HTTP2").is_ok_and(|value| matches!(value.as_str(), "1" | "true")),
+            http2_max_concurrent_streams: env::var("HTTP2_MAX_CONCURRENT_STREAMS").ok().map(
+                |streams| {
+                    streams
+                        .parse()
+                        .expect("Failed to parse HTTP2_MAX_CONCURRENT_STREAMS")
+                },
+            ),
+            tcp_keepalive: env::var("TCP_KEEPALIVE_SECS").ok().map(|secs| {
+                Duration::from_secs(secs.parse().expect("Failed to parse TCP_KEEPALIVE_SECS"))
+            }),
+            shutdown_grace: Duration::from_secs(
+                env::var("SHUTDOWN_GRACE_SECS").map_or(30, |secs| {
+                    secs.parse().expect("Failed to parse SHUTDOWN_GRACE_SECS")
+                }),
+            ),
+        }
+    }
+
+    fn builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        if !self.http2 {
+            return builder.http1_only();
+        }
+        builder
+            .http2()
+            .max_concurrent_streams(self.http2_max_concurrent_streams);
+        builder
+    }
+}
+
+/// Like `axum::serve` with connect info and graceful shutdown, but with the connections
+/// configured by `config`. Once `shutdown` completes no more connections are accepted, and the
+/// open ones are closed as soon as their requests in flight are answered, or once
+/// `shutdown_grace` is over.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let builder = config.builder();
+    let keepalive = config
+        .tcp_keepalive
+        .map(|time| TcpKeepalive::new().with_time(time));
+    let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let graceful = GracefulShutdown::new();
+    // To close what's left once the grace period is over
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok(connection) => connection,
+                Err(err) => {
+                    // Running out of file descriptors is usually over soon, so don't spin
+                    // meanwhile
+                    tracing::error!("Failed to accept connection: {err}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        if let Some(ref keepalive) = keepalive {
+            if let Err(err) = SockRef::from(&stream).set_tcp_keepalive(keepalive) {
+                tracing::warn!("Failed to enable TCP keepalive: {err}");
+            }
+        }
+
+        let tower_service = make_service
+            .call(remote_addr)
+            .await
+            .unwrap_or_else(|err| match err {})
+            .map_request(|request: Request<Incoming>| request.map(Body::new));
+        let hyper_service = TowerToHyperService::new(tower_service);
+
+        // Without upgrades, which `http1_only` doesn't apply to, but nothing here needs them
+        let connection = builder
+            .serve_connection(TokioIo::new(stream), hyper_service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        // Errors are clients going away without finishing a request, nothing to act on
+        connections.spawn(async move {
+            let _ = connection.await;
+        });
+        while connections.try_join_next().is_some() {}
+    }
+
+    drop(listener);
+    tracing::info!(
+        connections = graceful.count(),
+        "Shutting down, waiting for requests in flight"
+    );
+    if tokio::time::timeout(config.shutdown_grace, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Requests still in flight after the shutdown grace period, closing them");
+        connections.shutdown().await;
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use anyhow::Context;
+use serde_json::Value;
+use sqlx::{types::Json, PgPool};
+use std::{env, sync::Arc, time::Duration};
+use tokio::{
+    sync::{oneshot, Notify},
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+
+// Deliveries claimed by one pass of the worker, the rest wait for the next one
+const DELIVERY_BATCH_SIZE: i64 = 50;
+
+/// How webhooks are delivered and how often failed deliveries are retried.
+pub struct WebhookConfig {
+    // A delivery is given up after this many failed attempts
+    pub max_attempts: i32,
+    // Doubled after every failed attempt
+    pub retry_backoff: Duration,
+    pub timeout: Duration,
+    // How often the worker looks for deliveries that are due to be retried
+    pub poll_interval: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 5,
+            retry_backoff: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> Self {
+        let default = WebhookConfig::default();
+        let duration = |name: &str, default: Duration| {
+            env::var(name).map_or(default, |millis| {
+                Duration::from_millis(millis.parse().unwrap_or_else(|_| {
+                    panic!("Failed to parse {name}");
+                }))
+            })
+        };
+        WebhookConfig {
+            // At least one attempt is made
+            max_attempts: env::var("WEBHOOK_MAX_ATTEMPTS")
+                .map(|attempts| {
+                    attempts
+                        .parse()
+                        .expect("Failed to parse WEBHOOK_MAX_ATTEMPTS")
+                })
+                .map_or(default.max_attempts, |attempts: i32| attempts.max(1)),
+            retry_backoff: duration("WEBHOOK_RETRY_BACKOFF_MS", default.retry_backoff),
+            timeout: duration("WEBHOOK_TIMEOUT_MS", default.timeout),
+            poll_interval: duration("WEBHOOK_POLL_INTERVAL_MS", default.poll_interval),
+        }
+    }
+
+    // The delay before retrying a delivery that failed `attempts` times
+    fn backoff(&self, attempts: i32) -> Duration {
+        let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+        self.retry_backoff.saturating_mul(1 << doublings)
+    }
+}
+
+/// Wakes the background worker up when deliveries were queued, rather than waiting for its
+/// next poll.
+#[derive(Clone)]
+pub struct WebhookQueue {
+    notify: Arc<Notify>,
+}
+
+impl WebhookQueue {
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+}
+
+/// The worker task delivering webhooks. Deliveries are stored, so whatever is pending at
+/// shutdown is picked up again on the next start.
+pub struct WebhookWorker {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl WebhookWorker {
+    /// Waits for the deliveries in flight to finish.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if let Err(err) = self.handle.await {
+            tracing::error!("Webhook worker failed: {err}");
+        }
+    }
+}
+
+pub fn spawn(pool: PgPool, config: WebhookConfig) -> (WebhookQueue, WebhookWorker) {
+    let notify = Arc::new(Notify::new());
+    let (shutdown, shutdown_receiver) = oneshot::channel();
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .build()
+        .expect("Failed to build webhook client");
+    let handle = tokio::spawn(run(pool, client, config, notify.clone(), shutdown_receiver));
+    (WebhookQueue { notify }, WebhookWorker { shutdown, handle })
+}
+
+async fn run(
+    pool: PgPool,
+    client: reqwest::Client,
+    config: WebhookConfig,
+    notify: Arc<Notify>,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut interval = time::interval(config.poll_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = notify.notified() => {}
+            _ = interval.tick() => {}
+            _ = &mut shutdown => break,
+        }
+        // A full batch means more deliveries are likely due already
+        loop {
+            match deliver_due(&pool, &client, &config).await {
+                Ok(count) if count == DELIVERY_BATCH_SIZE as usize => continue,
+                Ok(_) => break,
+                Err(err) => {
+                    tracing::error!("Failed to deliver webhooks: {err:#}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Delivery {
+    id: i32,
+    url: String,
+    payload: Json<Value>,
+    attempts: i32,
+}
+
+// Delivers the deliveries that are due, returning how many were attempted
+async fn deliver_due(
+    pool: &PgPool,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+) -> anyhow::Result<usize> {
+    // Claiming pushes the next attempt past the request timeout, so other instances skip the
+    // deliveries in flight, and a crash leaves them to be retried
+    let deliveries: Vec<Delivery> = sqlx::query_as(
+        "UPDATE webhook_delivery SET \
+            attempts = webhook_delivery.attempts + 1, \
+            next_attempt_at = now() AT TIME ZONE 'utc' + $1 \
+            FROM webhook \
+            WHERE webhook.id = webhook_delivery.webhook_id AND webhook_delivery.id IN ( \
+                SELECT id FROM webhook_delivery \
+                WHERE delivered_at IS NULL AND failed_at IS NULL \
+                    AND next_attempt_at <= now() AT TIME ZONE 'utc' \
+                ORDER BY next_attempt_at \
+                LIMIT $2 \
+                FOR UPDATE SKIP LOCKED) \
+            RETURNING webhook_delivery.id, webhook.url, webhook_delivery.payload, \
+                webhook_delivery.attempts",
+    )
+    .bind(config.timeout * 2)
+    .bind(DELIVERY_BATCH_SIZE)
+    .fetch_all(pool)
+    .await
+    .context("Failed to claim webhook deliveries")?;
+
+    let count = deliveries.len();
+    for delivery in deliveries {
+        match deliver(client, &delivery).await {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE webhook_delivery SET delivered_at = now() AT TIME ZONE 'utc', \
+                        last_error = NULL WHERE id = $1",
+                )
+                .bind(delivery.id)
+                .execute(pool)
+                .await
+                .context("Failed to record webhook delivery")?;
+            }
+            Err(error) => {
+                let gave_up = delivery.attempts >= config.max_attempts;
+                tracing::warn!(
+                    delivery_id = delivery.id,
+                    url = delivery.url,
+                    attempts = delivery.attempts,
+                    gave_up,
+                    "Failed to deliver webhook: {error}"
+                );
+                sqlx::query(
+                    "UPDATE webhook_delivery SET last_error = $2, \
+                        next_attempt_at = now() AT TIME ZONE 'utc' + $3, \
+                        failed_at = CASE WHEN $4 THEN now() AT TIME ZONE 'utc' END \
+                        WHERE id = $1",
+                )
+                .bind(delivery.id)
+                .bind(&error)
+                .bind(config.backoff(delivery.attempts))
+                .bind(gave_up)
+                .execute(pool)
+                .await
+                .context("Failed to record failed webhook delivery")?;
+            }
+        }
+    }
+    Ok(count)
+}
+
+// Any 2xx response counts as delivered
+async fn deliver(client: &reqwest::Client, delivery: &Delivery) -> Result<(), String> {
+    let response = client
+        .post(&delivery.url)
+        .json(&delivery.payload.0)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("Responded with {status}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = WebhookConfig::default();
+        let backoffs: Vec<u64> = (1..=4)
+            .map(|attempts| config.backoff(attempts).as_secs())
+            .collect();
+        assert_eq!(backoffs, [30, 60, 120, 240]);
+    }
+}